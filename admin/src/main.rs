@@ -0,0 +1,177 @@
+// operator tool for the admin api of the service
+// creates polls from a TOML or JSON definition, moves them through their phases, edits the
+// registration allowlist and revokes registrations before voting opens
+use clap::{Parser, Subcommand};
+use client::types::{AllowlistEdit, PhaseChange, PollPhase};
+use reqwest::{
+    blocking::{Client, RequestBuilder},
+    header::CONTENT_TYPE,
+};
+use std::{env, fs, path::PathBuf, process};
+
+const TOKEN_ENV: &str = "CYPHER_POLL_ADMIN_TOKEN";
+
+#[derive(Parser)]
+struct Cli {
+    #[arg(long, global = true, default_value = "http://127.0.0.1:8080")]
+    server: String,
+    /// admin token of the service, defaults to $CYPHER_POLL_ADMIN_TOKEN
+    #[arg(long, global = true)]
+    token: Option<String>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// list all polls with their phase and registrations
+    List,
+    Show {
+        #[arg(long)]
+        poll: String,
+    },
+    /// create a poll from a [[polls]] entry of the service config, .toml or .json
+    Create {
+        #[arg(long)]
+        file: PathBuf,
+    },
+    /// open voting on a poll in its registration phase
+    Open {
+        #[arg(long)]
+        poll: String,
+    },
+    /// end voting, a commit-reveal poll moves to its reveal phase
+    Reveal {
+        #[arg(long)]
+        poll: String,
+    },
+    Close {
+        #[arg(long)]
+        poll: String,
+    },
+    /// edit the users that may register, a poll without allowlist admits anyone
+    Allow {
+        #[arg(long)]
+        poll: String,
+        #[arg(long, value_delimiter = ',')]
+        add: Vec<String>,
+        #[arg(long, value_delimiter = ',')]
+        remove: Vec<String>,
+        /// drop the allowlist before adding
+        #[arg(long)]
+        clear: bool,
+    },
+    /// show the webhook deliveries of a poll since the service started
+    Deliveries {
+        #[arg(long)]
+        poll: String,
+    },
+    /// revoke the registration of a user before voting opens
+    Revoke {
+        #[arg(long)]
+        poll: String,
+        #[arg(long)]
+        username: String,
+    },
+}
+
+// scripts check the exit code, every failure ends the tool with 1
+fn fail(message: String) -> ! {
+    eprintln!("Error: {}", message);
+    process::exit(1)
+}
+
+struct Admin {
+    client: Client,
+    server: String,
+    token: String,
+}
+
+impl Admin {
+    fn request(&self, builder: RequestBuilder) {
+        let response = builder
+            .bearer_auth(&self.token)
+            .send()
+            .unwrap_or_else(|e| fail(format!("Failed to reach the service: {}", e)));
+        let status = response.status();
+        let body: String = response.text().unwrap_or_default();
+        if !status.is_success() {
+            fail(format!("Response Status {}: {}", status, body));
+        }
+        // pretty print json answers, the transitions answer with an empty body
+        match serde_json::from_str::<serde_json::Value>(&body) {
+            Ok(json) => println!("{}", serde_json::to_string_pretty(&json).unwrap()),
+            Err(_) => println!("{}", status),
+        }
+    }
+
+    fn url(&self, route: &str) -> String {
+        format!("{}/admin/polls{}", self.server.trim_end_matches('/'), route)
+    }
+
+    fn phase(&self, poll: &str, phase: PollPhase) {
+        self.request(
+            self.client
+                .post(self.url(&format!("/{}/phase", poll)))
+                .json(&PhaseChange { phase }),
+        )
+    }
+}
+
+fn main() {
+    let cli = Cli::parse();
+    let admin = Admin {
+        client: Client::new(),
+        server: cli.server,
+        token: cli
+            .token
+            .or_else(|| env::var(TOKEN_ENV).ok())
+            .unwrap_or_else(|| {
+                fail(format!(
+                    "Missing admin token, pass --token or set {}",
+                    TOKEN_ENV
+                ))
+            }),
+    };
+    match cli.command {
+        Command::List => admin.request(admin.client.get(admin.url(""))),
+        Command::Show { poll } => admin.request(admin.client.get(admin.url(&format!("/{}", poll)))),
+        Command::Create { file } => {
+            let definition: String = fs::read_to_string(&file)
+                .unwrap_or_else(|e| fail(format!("Failed to read {}: {}", file.display(), e)));
+            let content_type: &str = match file.extension().and_then(|e| e.to_str()) {
+                Some("toml") => "application/toml",
+                _ => "application/json",
+            };
+            admin.request(
+                admin
+                    .client
+                    .post(admin.url(""))
+                    .header(CONTENT_TYPE, content_type)
+                    .body(definition),
+            )
+        }
+        Command::Open { poll } => admin.phase(&poll, PollPhase::Open),
+        Command::Reveal { poll } => admin.phase(&poll, PollPhase::Reveal),
+        Command::Close { poll } => admin.phase(&poll, PollPhase::Closed),
+        Command::Allow {
+            poll,
+            add,
+            remove,
+            clear,
+        } => admin.request(
+            admin
+                .client
+                .post(admin.url(&format!("/{}/allowlist", poll)))
+                .json(&AllowlistEdit { add, remove, clear }),
+        ),
+        Command::Deliveries { poll } => {
+            admin.request(admin.client.get(admin.url(&format!("/{}/webhooks", poll))))
+        }
+        Command::Revoke { poll, username } => admin.request(
+            admin
+                .client
+                .delete(admin.url(&format!("/{}/registrations/{}", poll, username))),
+        ),
+    }
+}
@@ -0,0 +1,277 @@
+// client configuration
+// a TOML file with named profiles, so that one binary can talk to several poll services:
+//
+// default_profile = "staging"
+// [profiles.staging]
+// server = "https://staging.example.org"
+// poll = "board-2024"
+// relay = "https://relay.example.org"
+// ca_bundle = "/etc/cypher-poll/staging-ca.pem"
+// keystore = "/home/user/.cypher-poll/staging"
+// public_key_path = "/home/user/keys/public_key.asc"
+// private_key_path = "/home/user/keys/private_key.sec.asc"
+// timeout_secs = 600
+use reqwest::{Certificate, Client};
+use serde::Deserialize;
+use std::{collections::HashMap, env, fmt, fs, path::PathBuf, time::Duration};
+
+pub const DEFAULT_SERVER: &str = "http://127.0.0.1:8080";
+pub const DEFAULT_PROFILE: &str = "default";
+pub const DEFAULT_POLL: &str = "default";
+// proof submission waits for the full verification on the service
+pub const DEFAULT_TIMEOUT_SECS: u64 = 600;
+
+#[derive(Deserialize, Default)]
+pub struct ClientConfig {
+    pub default_profile: Option<String>,
+    #[serde(default)]
+    pub profiles: HashMap<String, Profile>,
+}
+
+#[derive(Deserialize, Default, Clone)]
+pub struct Profile {
+    pub server: Option<String>,
+    // id of the poll on the service
+    pub poll: Option<String>,
+    // votes and reveals are submitted through this relayer instead of directly to the service
+    pub relay: Option<String>,
+    pub ca_bundle: Option<PathBuf>,
+    // directory that holds the nullifier and snapshot of this profile
+    pub keystore: Option<PathBuf>,
+    pub public_key_path: Option<PathBuf>,
+    pub private_key_path: Option<PathBuf>,
+    pub timeout_secs: Option<u64>,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+    UnknownProfile(String),
+    Tls(reqwest::Error),
+    Missing(&'static str),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, e) => write!(f, "Failed to read {}: {}", path.display(), e),
+            ConfigError::Parse(path, e) => write!(f, "Invalid config {}: {}", path.display(), e),
+            ConfigError::UnknownProfile(name) => write!(f, "Unknown profile {}", name),
+            ConfigError::Tls(e) => write!(f, "Invalid CA bundle: {}", e),
+            ConfigError::Missing(setting) => write!(f, "Missing setting {}", setting),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl ClientConfig {
+    // $CYPHER_POLL_CONFIG or ~/.config/cypher-poll/client.toml
+    pub fn default_path() -> Option<PathBuf> {
+        if let Ok(path) = env::var("CYPHER_POLL_CONFIG") {
+            return Some(PathBuf::from(path));
+        }
+        env::var("HOME")
+            .ok()
+            .map(|home| PathBuf::from(home).join(".config/cypher-poll/client.toml"))
+    }
+
+    /// an explicitly passed path must exist, a missing default config is an empty config
+    pub fn load(path: Option<PathBuf>) -> Result<ClientConfig, ConfigError> {
+        let (path, explicit) = match path {
+            Some(path) => (path, true),
+            None => match ClientConfig::default_path() {
+                Some(path) => (path, false),
+                None => return Ok(ClientConfig::default()),
+            },
+        };
+        if !explicit && !path.exists() {
+            return Ok(ClientConfig::default());
+        }
+        let raw: String =
+            fs::read_to_string(&path).map_err(|e| ConfigError::Io(path.clone(), e))?;
+        toml::from_str(&raw).map_err(|e| ConfigError::Parse(path, e))
+    }
+
+    pub fn profile(&self, name: Option<&str>) -> Result<(String, Profile), ConfigError> {
+        let name: String = name
+            .map(str::to_string)
+            .or_else(|| self.default_profile.clone())
+            .unwrap_or_else(|| DEFAULT_PROFILE.to_string());
+        match self.profiles.get(&name) {
+            Some(profile) => Ok((name, profile.clone())),
+            // the implicit default profile does not have to be declared
+            None if name == DEFAULT_PROFILE => Ok((name, Profile::default())),
+            None => Err(ConfigError::UnknownProfile(name)),
+        }
+    }
+}
+
+/// Fully resolved client settings: flags > profile > environment > defaults
+pub struct Settings {
+    pub server: String,
+    pub poll: String,
+    pub relay: Option<String>,
+    pub ca_bundle: Option<PathBuf>,
+    pub nullifier_path: PathBuf,
+    pub snapshot_path: PathBuf,
+    // the ballot kind and options of the poll, fetched at registration
+    pub spec_path: PathBuf,
+    // the weight assigned at registration in a weighted poll
+    pub weight_path: PathBuf,
+    // the opening of the latest committed ballot in a commit-reveal poll
+    pub reveal_path: PathBuf,
+    pub public_key_path: Option<PathBuf>,
+    pub private_key_path: Option<PathBuf>,
+    pub timeout: Duration,
+}
+
+impl Settings {
+    pub fn resolve(
+        config: &ClientConfig,
+        profile: Option<&str>,
+        server: Option<String>,
+        poll: Option<String>,
+        relay: Option<String>,
+    ) -> Result<Settings, ConfigError> {
+        let (name, profile) = config.profile(profile)?;
+        let keystore: Option<PathBuf> = profile.keystore.clone().or_else(|| {
+            env::var("HOME")
+                .ok()
+                .map(|home| PathBuf::from(home).join(".cypher-poll").join(&name))
+        });
+        // NULLIFIER_PATH, SNAPSHOT_PATH, SPEC_PATH, WEIGHT_PATH and REVEAL_PATH are still honored
+        // when no keystore is configured
+        let nullifier_path: PathBuf = match (&profile.keystore, env::var("NULLIFIER_PATH")) {
+            (None, Ok(path)) => PathBuf::from(path),
+            _ => keystore
+                .as_ref()
+                .ok_or(ConfigError::Missing("keystore"))?
+                .join("nullifier"),
+        };
+        let snapshot_path: PathBuf = match (&profile.keystore, env::var("SNAPSHOT_PATH")) {
+            (None, Ok(path)) => PathBuf::from(path),
+            _ => keystore
+                .as_ref()
+                .ok_or(ConfigError::Missing("keystore"))?
+                .join("snapshot"),
+        };
+        let spec_path: PathBuf = match (&profile.keystore, env::var("SPEC_PATH")) {
+            (None, Ok(path)) => PathBuf::from(path),
+            _ => keystore
+                .as_ref()
+                .ok_or(ConfigError::Missing("keystore"))?
+                .join("spec"),
+        };
+        let weight_path: PathBuf = match (&profile.keystore, env::var("WEIGHT_PATH")) {
+            (None, Ok(path)) => PathBuf::from(path),
+            _ => keystore
+                .as_ref()
+                .ok_or(ConfigError::Missing("keystore"))?
+                .join("weight"),
+        };
+        let reveal_path: PathBuf = match (&profile.keystore, env::var("REVEAL_PATH")) {
+            (None, Ok(path)) => PathBuf::from(path),
+            _ => keystore
+                .as_ref()
+                .ok_or(ConfigError::Missing("keystore"))?
+                .join("reveal"),
+        };
+        Ok(Settings {
+            server: server
+                .or(profile.server)
+                .unwrap_or_else(|| DEFAULT_SERVER.to_string())
+                .trim_end_matches('/')
+                .to_string(),
+            poll: poll
+                .or(profile.poll)
+                .unwrap_or_else(|| DEFAULT_POLL.to_string()),
+            relay: relay
+                .or(profile.relay)
+                .map(|relay| relay.trim_end_matches('/').to_string()),
+            ca_bundle: profile.ca_bundle,
+            nullifier_path,
+            snapshot_path,
+            spec_path,
+            weight_path,
+            reveal_path,
+            public_key_path: profile.public_key_path,
+            private_key_path: profile.private_key_path,
+            timeout: Duration::from_secs(profile.timeout_secs.unwrap_or(DEFAULT_TIMEOUT_SECS)),
+        })
+    }
+
+    pub fn url(&self, route: &str) -> String {
+        format!("{}{}", self.server, route)
+    }
+
+    // routes of the selected poll, e.g. /polls/default/vote
+    pub fn poll_url(&self, route: &str) -> String {
+        self.url(&format!("/polls/{}{}", self.poll, route))
+    }
+
+    // the relayer exposes the same vote route as the service
+    pub fn vote_url(&self) -> String {
+        match &self.relay {
+            Some(relay) => format!("{}/polls/{}/vote", relay, self.poll),
+            None => self.poll_url("/vote"),
+        }
+    }
+
+    pub fn http_client(&self) -> Result<Client, ConfigError> {
+        let mut builder = Client::builder().timeout(self.timeout);
+        if let Some(ca_bundle) = &self.ca_bundle {
+            let pem: Vec<u8> =
+                fs::read(ca_bundle).map_err(|e| ConfigError::Io(ca_bundle.clone(), e))?;
+            for certificate in Certificate::from_pem_bundle(&pem).map_err(ConfigError::Tls)? {
+                builder = builder.add_root_certificate(certificate);
+            }
+        }
+        builder.build().map_err(ConfigError::Tls)
+    }
+}
+
+#[test]
+fn test_profiles() {
+    let config: ClientConfig = toml::from_str(
+        r#"
+        default_profile = "staging"
+        [profiles.staging]
+        server = "https://staging.example.org/"
+        keystore = "/tmp/cypher-poll/staging"
+        [profiles.production]
+        server = "https://poll.example.org"
+        poll = "board-2024"
+        timeout_secs = 30
+        keystore = "/tmp/cypher-poll/production"
+        "#,
+    )
+    .unwrap();
+    let staging = Settings::resolve(&config, None, None, None, None).unwrap();
+    assert_eq!(
+        staging.poll_url("/vote"),
+        "https://staging.example.org/polls/default/vote"
+    );
+    assert_eq!(
+        staging.nullifier_path,
+        PathBuf::from("/tmp/cypher-poll/staging/nullifier")
+    );
+    let production = Settings::resolve(&config, Some("production"), None, None, None).unwrap();
+    assert_eq!(production.timeout, Duration::from_secs(30));
+    assert_eq!(production.poll, "board-2024");
+    let overridden = Settings::resolve(
+        &config,
+        Some("production"),
+        Some("http://localhost:9000".into()),
+        None,
+        Some("http://localhost:9001/".into()),
+    )
+    .unwrap();
+    assert_eq!(overridden.server, "http://localhost:9000");
+    assert_eq!(
+        overridden.vote_url(),
+        "http://localhost:9001/polls/board-2024/vote"
+    );
+    assert!(Settings::resolve(&config, Some("missing"), None, None, None).is_err());
+}
@@ -4,33 +4,45 @@
 // 2. generate a proof payload / receipt
 
 use std::{
-    env,
-    fs::{self, File},
-    io::{Read, Write},
-    path::PathBuf,
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
     time::Duration,
 };
 
 use clap::{Parser, Subcommand};
-use crypto::{
-    gpg::GpgSigner,
-    identity::{Identity, Nullifier, UniqueIdentity},
-};
-use pgp::types::Mpi;
-use reqwest::blocking::Client;
-#[cfg(not(feature = "groth16"))]
-use risc0_prover::prover::prove_default as prover;
-#[cfg(feature = "groth16")]
-use risc0_prover::prover::prove_groth16 as prover;
-use risc0_types::CircuitInputs;
-use risc0_zkvm::Receipt;
-use serde_json;
-use types::IdentityPayload;
+use config::{ClientConfig, Settings};
+use crypto::{identity::Nullifier, to_hex};
+use risc0_prover::{prover::default_vote_prover, verifier::Risc0Verifier};
+use risc0_types::{CircuitOutputs, PollSpec, PublicBallot};
+use sdk::{ClientError, CypherPollClient, PollUpdate, ProvedVote, Registration, Submission, Voter};
+use types::RevealPayload;
 use voting_tree::VotingTree;
+use zk_associated::{
+    dev::{DevProver, DevVerifier},
+    proof::{ProofEnvelope, VerifierSet, VoteProver, VoteVerifier},
+};
+pub mod config;
+pub mod sdk;
 pub mod types;
 
 #[derive(Parser)]
 pub struct Cli {
+    /// client config file, defaults to ~/.config/cypher-poll/client.toml
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+    /// named profile in the config file
+    #[arg(long, global = true)]
+    pub profile: Option<String>,
+    /// service url, overrides the profile
+    #[arg(long, global = true)]
+    pub server: Option<String>,
+    /// poll id on the service, overrides the profile
+    #[arg(long, global = true)]
+    pub poll: Option<String>,
+    /// submit votes and reveals through this relayer, overrides the profile
+    #[arg(long, global = true)]
+    pub relay: Option<String>,
     #[clap(subcommand)]
     pub command: Command,
 }
@@ -38,134 +50,376 @@ pub struct Cli {
 #[derive(Subcommand)]
 pub enum Command {
     Register {
-        #[arg(short, long)]
-        data: String,
         #[arg(long)]
-        public_key_path: String,
+        public_key_path: Option<PathBuf>,
         #[arg(long)]
-        private_key_path: String,
+        private_key_path: Option<PathBuf>,
         #[arg(short, long)]
         random_seed: String,
         #[arg(short, long)]
         username: String,
-        #[arg(short, long)]
-        vote: String,
+        /// the choice, or a comma separated list of options for ranked, approval and multi-select
+        /// polls, or of option=votes for quadratic polls
+        #[arg(short, long, value_delimiter = ',', num_args = 1..)]
+        vote: Vec<String>,
     },
     Vote {
         #[arg(short, long)]
-        public_key_path: String,
+        public_key_path: Option<PathBuf>,
+        #[arg(short, long, value_delimiter = ',', num_args = 1..)]
+        vote: Vec<String>,
+        /// number of the ballot in a revoting poll, 0 for the first one
+        #[arg(long, default_value_t = 0)]
+        sequence: u64,
+        /// skip proving and emit an insecure dev-mode proof,
+        /// only accepted by a service started with --insecure-dev-proofs
+        #[arg(long)]
+        dev: bool,
+    },
+    /// generate a vote proof and write it to a file, does not touch the network
+    Prove {
+        #[arg(short, long)]
+        public_key_path: Option<PathBuf>,
+        #[arg(short, long, value_delimiter = ',', num_args = 1..)]
+        vote: Vec<String>,
+        #[arg(long, default_value_t = 0)]
+        sequence: u64,
+        #[arg(long)]
+        dev: bool,
         #[arg(short, long)]
-        vote: String,
+        output: PathBuf,
+    },
+    /// submit a previously generated proof file
+    Submit {
+        #[arg(long)]
+        proof: PathBuf,
+        /// full vote url, defaults to the vote route of the selected poll or relayer
+        #[arg(long)]
+        url: Option<String>,
+    },
+    /// open the committed ballot of the latest vote once a commit-reveal poll is in its reveal phase
+    Reveal {
+        /// reveal file written by vote or prove, defaults to the one in the keystore
+        #[arg(long)]
+        file: Option<PathBuf>,
+    },
+    /// fetch the snapshot of the registered leaf again,
+    /// needed after the operator revoked an earlier registration of the poll
+    Refresh,
+    /// print the events of the poll as they happen, one json object per line
+    Watch,
+    /// decode and display the public outputs of a proof file
+    Inspect {
+        #[arg(long)]
+        proof: PathBuf,
+        /// also verify the proof locally
+        #[arg(long)]
+        verify: bool,
     },
 }
 
+// the keystore keeps the voter in separate files, the spec and weight are read on their own
+fn load_voter(settings: &Settings) -> Voter {
+    let snapshot: VotingTree = serde_json::from_slice(
+        &fs::read(&settings.snapshot_path).expect("Failed to read snapshot file, register first"),
+    )
+    .expect("Failed to decode snapshot file");
+    let nullifier: Nullifier = serde_json::from_slice(
+        &fs::read(&settings.nullifier_path).expect("Failed to read nullifier file"),
+    )
+    .expect("Failed to decode nullifier file");
+    let spec: PollSpec = serde_json::from_slice(
+        &fs::read(&settings.spec_path).expect("Failed to read spec file, register first"),
+    )
+    .expect("Failed to decode spec file");
+    let weight: u64 = match spec.weighted {
+        true => fs::read_to_string(&settings.weight_path)
+            .expect("Failed to read weight file")
+            .trim()
+            .parse()
+            .expect("Failed to decode weight file"),
+        false => 1,
+    };
+    Voter {
+        nullifier,
+        snapshot,
+        spec,
+        weight,
+    }
+}
+
+fn save_voter(settings: &Settings, voter: &Voter) {
+    for path in [
+        &settings.nullifier_path,
+        &settings.snapshot_path,
+        &settings.spec_path,
+        &settings.weight_path,
+    ] {
+        if let Some(keystore) = path.parent() {
+            fs::create_dir_all(keystore).expect("Failed to create keystore");
+        }
+    }
+    fs::write(
+        &settings.nullifier_path,
+        serde_json::to_vec(&voter.nullifier).expect("Failed to serialize nullifier"),
+    )
+    .expect("Failed to write nullifier file");
+    fs::write(
+        &settings.snapshot_path,
+        serde_json::to_vec(&voter.snapshot).expect("Failed to serialize snapshot"),
+    )
+    .expect("Failed to write snapshot file");
+    fs::write(
+        &settings.spec_path,
+        serde_json::to_vec(&voter.spec).expect("Failed to serialize spec"),
+    )
+    .expect("Failed to write spec file");
+    fs::write(&settings.weight_path, voter.weight.to_string())
+        .expect("Failed to write weight file");
+}
+
+// voting requires the exact tree snapshot of the leaf
+async fn prove_vote(
+    sdk: &CypherPollClient,
+    settings: &Settings,
+    voter: &Voter,
+    public_key_path: Option<PathBuf>,
+    vote: Vec<String>,
+    sequence: u64,
+    dev: bool,
+) -> ProofEnvelope {
+    let public_key_path: PathBuf = public_key_path
+        .or(settings.public_key_path.clone())
+        .expect("Missing public key path");
+    let public_key: String =
+        fs::read_to_string(public_key_path).expect("Failed to read public key");
+    let prover: Arc<dyn VoteProver> = if dev {
+        Arc::new(DevProver)
+    } else {
+        Arc::from(default_vote_prover())
+    };
+    let proved: ProvedVote = sdk
+        .prove_vote(voter, &public_key, vote, sequence, prover)
+        .await
+        .unwrap_or_else(|e| panic!("Failed to prove vote: {}", e));
+    if let Some(reveal) = &proved.reveal {
+        fs::write(
+            &settings.reveal_path,
+            serde_json::to_vec(reveal).expect("Failed to serialize reveal"),
+        )
+        .expect("Failed to write reveal file");
+    }
+    proved.proof
+}
+
+fn read_proof(path: &Path) -> ProofEnvelope {
+    let raw: Vec<u8> = fs::read(path).expect("Failed to read proof file");
+    serde_json::from_slice(&raw).expect("Failed to decode proof file")
+}
+
+async fn submit_vote(sdk: &CypherPollClient, url: &str, proof: &ProofEnvelope) {
+    match sdk.submit_vote_to(url, proof).await {
+        Ok(Submission::Accepted { ticket }) => println!("Vote accepted, ticket: {}", ticket),
+        Ok(Submission::Relayed(message)) => println!("{}", message),
+        Err(ClientError::Rejected(reason)) => println!("Vote rejected: {}", reason),
+        Err(e) => println!("Error: {}", e),
+    }
+}
+
 pub fn run(cli: Cli) {
+    tokio::runtime::Runtime::new()
+        .expect("Failed to start runtime")
+        .block_on(execute(cli))
+}
+
+async fn execute(cli: Cli) {
+    let settings: Settings = ClientConfig::load(cli.config)
+        .and_then(|config| {
+            Settings::resolve(
+                &config,
+                cli.profile.as_deref(),
+                cli.server,
+                cli.poll,
+                cli.relay,
+            )
+        })
+        .unwrap_or_else(|e| panic!("Invalid client configuration: {}", e));
+    let sdk: CypherPollClient = CypherPollClient::from_settings(&settings)
+        .unwrap_or_else(|e| panic!("Invalid client configuration: {}", e));
     match cli.command {
-        // data corresponds to a unique challenge for the session,
-        // should be something like H(P, NonceUser, NonceServer)
         Command::Register {
-            data,
             public_key_path,
             private_key_path,
             username,
             random_seed,
             vote,
         } => {
-            // construct the serialized registration payload
-            let public_key_string: String =
+            let public_key_path: PathBuf = public_key_path
+                .or(settings.public_key_path.clone())
+                .expect("Missing public key path");
+            let private_key_path: PathBuf = private_key_path
+                .or(settings.private_key_path.clone())
+                .expect("Missing private key path");
+            let public_key: String =
                 fs::read_to_string(public_key_path).expect("Failed to read public key");
-            let data_serialized: Vec<u8> = data.as_bytes().to_vec();
-            let mut identity: UniqueIdentity = UniqueIdentity {
-                identity: None,
-                nullifier: None,
-            };
-            identity.generate_nullifier(random_seed);
-
-            let mut nullifier_file = File::create(env::var("NULLIFIER_PATH").unwrap()).unwrap();
-            nullifier_file
-                .write(&serde_json::to_vec(&identity.nullifier.clone().unwrap()).unwrap())
-                .unwrap();
-
-            let mut signer = GpgSigner {
-                secret_key_asc_path: Some(PathBuf::from(private_key_path)),
-                public_key_asc_string: Some(public_key_string.clone()),
-                signed_secret_key: None,
-                signed_public_key: None,
-            };
-            signer.init();
-            let signature: Vec<Mpi> = signer.sign_bytes(&data_serialized);
-            let mut signature_serialized: Vec<Vec<u8>> = Vec::new();
-            for mpi in &signature {
-                signature_serialized.push(mpi.as_bytes().to_vec())
-            }
-
-            let mut signature_deserialized: Vec<Mpi> = Vec::new();
-            for series in &signature_serialized {
-                signature_deserialized.push(Mpi::from_slice(series))
+            let voter: Voter = sdk
+                .register(Registration {
+                    username,
+                    public_key,
+                    private_key_path,
+                    random_seed,
+                    vote,
+                })
+                .await
+                .unwrap_or_else(|e| panic!("Failed to register: {}", e));
+            save_voter(&settings, &voter);
+            if voter.spec.weighted {
+                println!("Registered with weight {}", voter.weight);
             }
-            assert_eq!(&signature, &signature_deserialized);
-            identity.compute_public_identity(signer.signed_public_key.unwrap(), vote);
-            // the public identity
-            let public_identity: Identity = identity.identity.unwrap();
-            let payload: IdentityPayload = IdentityPayload {
-                data_serialized,
-                signature_serialized,
-                public_key_string,
-                identity: public_identity,
-                username,
-            };
-            // todo: submit payload to server
-            // should return a tree snapshot
-            // should store that tree snapshot in the
-            // designated file
-            let client: Client = Client::new();
-            let response = client
-                .post("http://127.0.0.1:8080/register")
-                .json(&payload)
-                .send()
-                .expect("Failed to register");
-            assert!(response.status().is_success());
-            let mut snapshot_file = File::create(env::var("SNAPSHOT_PATH").unwrap()).unwrap();
-            snapshot_file.write(&response.bytes().unwrap()).unwrap();
         }
-        // voting requires the exact tree snapshot of the leaf
         Command::Vote {
             public_key_path,
             vote,
+            sequence,
+            dev,
         } => {
-            let snapshot_path: PathBuf = PathBuf::from(env::var("SNAPSHOT_PATH").unwrap());
-            let nullifier_path: PathBuf = PathBuf::from(env::var("NULLIFIER_PATH").unwrap());
-            let mut snapshot_file = File::open(snapshot_path).unwrap();
-            let mut snapshot_json = String::new();
-            snapshot_file.read_to_string(&mut snapshot_json).unwrap();
-            let snapshot: VotingTree = serde_json::from_str(&snapshot_json).unwrap();
-            let root_history: Vec<Vec<u8>> =
-                vec![snapshot.root.clone().expect("Snapshot has no root")];
-            let public_key_string: String =
-                fs::read_to_string(public_key_path).expect("Failed to read public key");
-            let mut nullifier_file: File = File::open(nullifier_path).unwrap();
-            let mut nullifier_json = String::new();
-            nullifier_file.read_to_string(&mut nullifier_json).unwrap();
-            let nullifier: Nullifier = serde_json::from_str(&mut nullifier_json).unwrap();
-            let proof: Receipt = prover(CircuitInputs {
-                root_history,
-                snapshot,
-                nullifier,
+            let voter: Voter = load_voter(&settings);
+            // a proof against a root the service no longer accepts is rejected after proving
+            let roots: Vec<Vec<u8>> = sdk
+                .fetch_roots()
+                .await
+                .unwrap_or_else(|e| panic!("Failed to fetch roots: {}", e));
+            if !voter
+                .snapshot
+                .root
+                .as_ref()
+                .is_some_and(|root| roots.contains(root))
+            {
+                println!("Error: Snapshot is outdated, run refresh first");
+                return;
+            }
+            let proof: ProofEnvelope = prove_vote(
+                &sdk,
+                &settings,
+                &voter,
+                public_key_path,
+                vote,
+                sequence,
+                dev,
+            )
+            .await;
+            submit_vote(&sdk, &sdk.vote_url(), &proof).await;
+        }
+        Command::Prove {
+            public_key_path,
+            vote,
+            sequence,
+            dev,
+            output,
+        } => {
+            let voter: Voter = load_voter(&settings);
+            let proof: ProofEnvelope = prove_vote(
+                &sdk,
+                &settings,
+                &voter,
+                public_key_path,
                 vote,
-                public_key_string,
-            });
-            // todo: submit payload to server
-            let client = Client::builder()
-                .timeout(Duration::from_secs(600))
-                .build()
-                .expect("Failed to create client");
-            let response = client
-                .post("http://127.0.0.1:8080/vote")
-                .json(&proof)
-                .send()
-                .expect("Failed to submit proof");
-            if !response.status().is_success() {
-                println!("Error: Response Status {}", &response.status())
+                sequence,
+                dev,
+            )
+            .await;
+            fs::write(
+                &output,
+                serde_json::to_vec(&proof).expect("Failed to serialize proof"),
+            )
+            .expect("Failed to write proof file");
+            println!("Proof written to {}", output.display());
+        }
+        Command::Submit { proof, url } => {
+            let proof: ProofEnvelope = read_proof(&proof);
+            submit_vote(&sdk, &url.unwrap_or_else(|| sdk.vote_url()), &proof).await;
+        }
+        Command::Reveal { file } => {
+            let file: PathBuf = file.unwrap_or_else(|| settings.reveal_path.clone());
+            let reveal: RevealPayload = serde_json::from_slice(
+                &fs::read(&file).expect("Failed to read reveal file, vote first"),
+            )
+            .expect("Failed to decode reveal file");
+            match sdk.reveal(&reveal).await {
+                Ok(()) if settings.relay.is_some() => println!("Reveal queued for relay"),
+                Ok(()) => println!("Ballot revealed"),
+                Err(e) => println!("Error: {}", e),
+            }
+        }
+        Command::Refresh => {
+            let mut voter: Voter = load_voter(&settings);
+            let changed: bool = sdk
+                .refresh_snapshot(&mut voter)
+                .await
+                .unwrap_or_else(|e| panic!("Failed to fetch snapshot: {}", e));
+            save_voter(&settings, &voter);
+            match changed {
+                true => println!("Snapshot refreshed"),
+                false => println!("Snapshot is up to date"),
+            }
+        }
+        // the stream ends at the request timeout of the client, it is reopened then
+        Command::Watch => loop {
+            match sdk.events().await {
+                Ok(mut events) => loop {
+                    match events.next().await {
+                        Ok(Some(PollUpdate::Event(event))) => {
+                            println!("{}", serde_json::to_string(&event).unwrap())
+                        }
+                        Ok(Some(PollUpdate::Lagged(missed))) => {
+                            println!("Missed {} events", missed)
+                        }
+                        Ok(None) => break,
+                        Err(e) => {
+                            println!("Error: {}", e);
+                            break;
+                        }
+                    }
+                },
+                Err(e) => println!("Error: {}", e),
+            }
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        },
+        Command::Inspect { proof, verify } => {
+            let proof: ProofEnvelope = read_proof(&proof);
+            let verifier = VerifierSet::default().with(Risc0Verifier).with(DevVerifier);
+            let outputs: CircuitOutputs = if verify {
+                verifier.verify(&proof)
+            } else {
+                verifier.inspect(&proof)
+            }
+            .expect("Failed to decode proof");
+            println!("version:   {}", proof.version);
+            println!("backend:   {}", proof.backend);
+            println!("verified:  {}", verify);
+            println!("nullifier: {}", to_hex(&outputs.nullifier));
+            for root in &outputs.root_history {
+                println!("root:      {}", to_hex(root));
+            }
+            println!("kind:      {:?}", outputs.spec.kind);
+            println!("weight:    {}", outputs.weight);
+            println!("sequence:  {}", outputs.sequence);
+            match &outputs.ballot {
+                PublicBallot::Plain(ballot) => println!(
+                    "ballot:    {}",
+                    ballot
+                        .choices()
+                        .iter()
+                        .map(|c| c.as_str())
+                        .collect::<Vec<&str>>()
+                        .join(", ")
+                ),
+                PublicBallot::Encrypted(votes) => {
+                    println!("ballot:    encrypted, {} ciphertexts", votes.len())
+                }
+                PublicBallot::Committed(commitment) => {
+                    println!("ballot:    committed, {}", to_hex(commitment))
+                }
             }
         }
     }
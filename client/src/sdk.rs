@@ -0,0 +1,511 @@
+// async client sdk
+// the typed api of a poll service for bots and web backends, the cli is a thin layer on top.
+// nothing in here reads the environment or writes files, the caller decides where a voter is kept
+use crate::{
+    config::{ConfigError, Settings},
+    types::{
+        Challenge, ChallengeRequest, IdentityPayload, JobStatus, PollEvent, PollInfo, PollPhase,
+        PollResults, RevealPayload, VoteJob, WEIGHT_HEADER,
+    },
+};
+use crypto::{
+    elgamal::random_scalar,
+    from_hex,
+    gpg::{GpgError, GpgSigner},
+    identity::{revote_tag, Identity, Nullifier, UniqueIdentity},
+};
+use pgp::types::Mpi;
+use rand::{rngs::OsRng, RngCore};
+use reqwest::{Client, Response};
+use risc0_types::{Ballot, BallotError, CircuitInputs, PollSpec, SALT_LENGTH};
+use serde::{Deserialize, Serialize};
+use std::{fmt, path::PathBuf, sync::Arc};
+use voting_tree::VotingTree;
+use zk_associated::proof::{ProofEnvelope, ProofError, VoteProver};
+
+// long polling interval for the vote status, the service caps it at 60
+const VOTE_WAIT_SECS: u64 = 30;
+
+#[derive(Debug)]
+pub enum ClientError {
+    Http(reqwest::Error),
+    // the service or relayer answered with an error status
+    Status { status: u16, message: String },
+    // the poll does not accept registrations in this phase
+    Phase(PollPhase),
+    // the GPG keys of the registration could not be read
+    Key(GpgError),
+    InvalidVote(BallotError),
+    Proof(ProofError),
+    // the service verified the proof and refused the vote
+    Rejected(String),
+    Decode(String),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Http(e) => write!(f, "Request failed: {}", e),
+            ClientError::Status { status, message } => {
+                write!(f, "Response Status {}: {}", status, message)
+            }
+            ClientError::Phase(phase) => write!(f, "Poll is in its {:?} phase", phase),
+            ClientError::Key(e) => write!(f, "{}", e),
+            ClientError::InvalidVote(e) => write!(f, "Invalid vote: {}", e),
+            ClientError::Proof(e) => write!(f, "{}", e),
+            ClientError::Rejected(reason) => write!(f, "Vote rejected: {}", reason),
+            ClientError::Decode(reason) => write!(f, "Failed to decode response: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<reqwest::Error> for ClientError {
+    fn from(e: reqwest::Error) -> ClientError {
+        ClientError::Http(e)
+    }
+}
+
+/// Everything needed to register a GitHub user for a poll
+pub struct Registration {
+    pub username: String,
+    // armored, must be one of the GPG keys of the GitHub account
+    pub public_key: String,
+    pub private_key_path: PathBuf,
+    // the nullifier is derived from it, must be secret and random
+    pub random_seed: String,
+    // ignored by revoting polls, which do not bind the ballot at registration
+    pub vote: Vec<String>,
+}
+
+/// The secrets and the tree snapshot of a registered voter, must be kept until the vote
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Voter {
+    pub nullifier: Nullifier,
+    pub snapshot: VotingTree,
+    pub spec: PollSpec,
+    // assigned by the service in a weighted poll, 1 otherwise
+    pub weight: u64,
+}
+
+pub struct ProvedVote {
+    pub proof: ProofEnvelope,
+    // the opening of the committed ballot in a commit-reveal poll, kept until its reveal phase
+    pub reveal: Option<RevealPayload>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Submission {
+    Accepted { ticket: String },
+    // a relayer only acknowledges the submission
+    Relayed(String),
+}
+
+#[derive(Debug)]
+pub enum PollUpdate {
+    Event(PollEvent),
+    // the subscriber fell behind and missed this many events
+    Lagged(u64),
+}
+
+pub struct CypherPollClient {
+    http: Client,
+    server: String,
+    poll: String,
+    relay: Option<String>,
+}
+
+impl CypherPollClient {
+    pub fn new(http: Client, server: &str, poll: &str) -> CypherPollClient {
+        CypherPollClient {
+            http,
+            server: server.trim_end_matches('/').to_string(),
+            poll: poll.to_string(),
+            relay: None,
+        }
+    }
+
+    /// submits votes and reveals through the relayer instead of directly to the service
+    pub fn with_relay(mut self, relay: &str) -> CypherPollClient {
+        self.relay = Some(relay.trim_end_matches('/').to_string());
+        self
+    }
+
+    pub fn from_settings(settings: &Settings) -> Result<CypherPollClient, ConfigError> {
+        let client =
+            CypherPollClient::new(settings.http_client()?, &settings.server, &settings.poll);
+        Ok(match &settings.relay {
+            Some(relay) => client.with_relay(relay),
+            None => client,
+        })
+    }
+
+    pub fn poll(&self) -> &str {
+        &self.poll
+    }
+
+    fn poll_url(&self, route: &str) -> String {
+        format!("{}/polls/{}{}", self.server, self.poll, route)
+    }
+
+    // the relayer exposes the same vote route as the service
+    pub fn vote_url(&self) -> String {
+        match &self.relay {
+            Some(relay) => format!("{}/polls/{}/vote", relay, self.poll),
+            None => self.poll_url("/vote"),
+        }
+    }
+
+    // a reveal names the nullifier of the ballot, it takes the same way as the vote
+    pub fn reveal_url(&self) -> String {
+        match &self.relay {
+            Some(relay) => format!("{}/polls/{}/reveal", relay, self.poll),
+            None => self.poll_url("/reveal"),
+        }
+    }
+
+    pub async fn poll_info(&self) -> Result<PollInfo, ClientError> {
+        let response: Response = checked(self.http.get(self.poll_url("")).send().await?).await?;
+        Ok(response.json().await?)
+    }
+
+    /// the data to sign for a registration, issued by the service for this username and
+    /// identity and valid for one registration
+    pub async fn challenge(
+        &self,
+        username: &str,
+        identity: &Identity,
+    ) -> Result<Vec<u8>, ClientError> {
+        let response: Response = checked(
+            self.http
+                .post(self.poll_url("/challenge"))
+                .json(&ChallengeRequest {
+                    username: username.to_string(),
+                    identity: identity.clone(),
+                })
+                .send()
+                .await?,
+        )
+        .await?;
+        Ok(response.json::<Challenge>().await?.data)
+    }
+
+    /// signs a challenge of the service, inserts the leaf and returns the voter with its
+    /// tree snapshot
+    pub async fn register(&self, registration: Registration) -> Result<Voter, ClientError> {
+        // the leaf commits to the ballot, so its kind and options are needed up front
+        let info: PollInfo = self.poll_info().await?;
+        if !matches!(info.phase, PollPhase::Registration | PollPhase::Open) {
+            return Err(ClientError::Phase(info.phase));
+        }
+        let weight: Option<u64> = match info.spec.weighted {
+            true => None,
+            false => Some(1),
+        };
+        // a revoting poll does not bind the ballot at registration
+        let ballot: Option<Ballot> = match info.spec.revoting {
+            true => None,
+            false => Some(build_ballot(&info.spec, registration.vote, weight)?),
+        };
+        let commitment: String = info
+            .spec
+            .leaf_commitment(ballot.as_ref())
+            .expect("Missing ballot");
+        let mut identity: UniqueIdentity = UniqueIdentity {
+            identity: None,
+            nullifier: None,
+        };
+        identity.generate_nullifier(registration.random_seed);
+        let mut signer = GpgSigner {
+            secret_key_asc_path: Some(registration.private_key_path),
+            public_key_asc_string: Some(registration.public_key.clone()),
+            signed_secret_key: None,
+            signed_public_key: None,
+        };
+        signer.try_init().map_err(ClientError::Key)?;
+        identity.compute_public_identity(signer.signed_public_key.clone().unwrap(), commitment);
+        // the signature covers the poll, the username and the identity through the challenge
+        let challenge: Vec<u8> = self
+            .challenge(&registration.username, identity.identity.as_ref().unwrap())
+            .await?;
+        let signature: Vec<Mpi> = signer.sign_bytes(&challenge);
+        let payload: IdentityPayload = IdentityPayload {
+            data_serialized: challenge,
+            signature_serialized: signature
+                .iter()
+                .map(|mpi| mpi.as_bytes().to_vec())
+                .collect(),
+            public_key_string: registration.public_key,
+            identity: identity.identity.unwrap(),
+            username: registration.username,
+        };
+        let response: Response = checked(
+            self.http
+                .post(self.poll_url("/register"))
+                .json(&payload)
+                .send()
+                .await?,
+        )
+        .await?;
+        let weight: u64 = response
+            .headers()
+            .get(WEIGHT_HEADER)
+            .and_then(|weight| weight.to_str().ok())
+            .and_then(|weight| weight.parse().ok())
+            .unwrap_or(1);
+        Ok(Voter {
+            nullifier: identity.nullifier.unwrap(),
+            snapshot: response.json().await?,
+            spec: info.spec,
+            weight,
+        })
+    }
+
+    /// the tree roots the service accepts in a proof, oldest first
+    pub async fn fetch_roots(&self) -> Result<Vec<Vec<u8>>, ClientError> {
+        let response: Response =
+            checked(self.http.get(self.poll_url("/roots")).send().await?).await?;
+        response
+            .json::<Vec<String>>()
+            .await?
+            .iter()
+            .map(|root| from_hex(root).ok_or_else(|| ClientError::Decode(root.clone())))
+            .collect()
+    }
+
+    /// fetches the snapshot of the leaf of the voter again, needed after the operator revoked an
+    /// earlier registration, returns whether it changed
+    pub async fn refresh_snapshot(&self, voter: &mut Voter) -> Result<bool, ClientError> {
+        // the index counts the leaves up to and including the one of this voter
+        let response: Response = checked(
+            self.http
+                .get(self.poll_url(&format!("/snapshot/{}", voter.snapshot.index)))
+                .send()
+                .await?,
+        )
+        .await?;
+        let refreshed: VotingTree = response.json().await?;
+        let changed: bool = refreshed.root != voter.snapshot.root;
+        voter.snapshot = refreshed;
+        Ok(changed)
+    }
+
+    /// proves the vote against the snapshot of the voter, does not touch the network.
+    /// runs on the blocking pool, full risc0 proving takes minutes
+    pub async fn prove_vote(
+        &self,
+        voter: &Voter,
+        public_key: &str,
+        vote: Vec<String>,
+        sequence: u64,
+        prover: Arc<dyn VoteProver>,
+    ) -> Result<ProvedVote, ClientError> {
+        let spec: PollSpec = voter.spec.clone();
+        let ballot: Ballot = build_ballot(&spec, vote, Some(voter.weight))?;
+        // fresh and secret per option, the few possible plaintexts could be tried otherwise
+        let randomness: Vec<[u8; 32]> = match spec.encryption_key {
+            Some(_) => spec
+                .options
+                .iter()
+                .map(|_| {
+                    let mut random: [u8; 64] = [0; 64];
+                    OsRng.fill_bytes(&mut random);
+                    random_scalar(&random)
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+        // kept until the reveal phase, the ballot could be guessed from an unsalted commitment
+        let reveal: Option<RevealPayload> = spec.commit_reveal.then(|| {
+            let mut salt: Vec<u8> = vec![0; SALT_LENGTH];
+            OsRng.fill_bytes(&mut salt);
+            RevealPayload {
+                nullifier: match spec.revoting {
+                    true => revote_tag(&voter.nullifier, sequence),
+                    false => voter.nullifier.clone(),
+                },
+                ballot: ballot.clone(),
+                salt,
+            }
+        });
+        let inputs = CircuitInputs {
+            root_history: vec![voter.snapshot.root.clone().expect("Snapshot has no root")],
+            snapshot: voter.snapshot.clone(),
+            nullifier: voter.nullifier.clone(),
+            ballot,
+            spec,
+            weight: voter.weight,
+            sequence,
+            randomness,
+            salt: reveal
+                .as_ref()
+                .map(|reveal| reveal.salt.clone())
+                .unwrap_or_default(),
+            public_key_string: public_key.to_string(),
+        };
+        let proof: ProofEnvelope = tokio::task::spawn_blocking(move || prover.prove(inputs))
+            .await
+            .map_err(|e| ClientError::Proof(ProofError::Proving(e.to_string())))?
+            .map_err(ClientError::Proof)?;
+        Ok(ProvedVote { proof, reveal })
+    }
+
+    /// submits the proof to the service or relayer and waits for its verification
+    pub async fn submit_vote(&self, proof: &ProofEnvelope) -> Result<Submission, ClientError> {
+        self.submit_vote_to(&self.vote_url(), proof).await
+    }
+
+    // the service answers with a verification job, which is polled until it is final
+    pub async fn submit_vote_to(
+        &self,
+        url: &str,
+        proof: &ProofEnvelope,
+    ) -> Result<Submission, ClientError> {
+        let response: Response = self
+            .http
+            .post(url)
+            .query(&[("wait", VOTE_WAIT_SECS)])
+            .json(proof)
+            .send()
+            .await?;
+        let status = response.status();
+        let body: String = response.text().await?;
+        let mut job: VoteJob = match serde_json::from_str(&body) {
+            Ok(job) => job,
+            Err(_) if status.is_success() => return Ok(Submission::Relayed(body)),
+            Err(_) => {
+                return Err(ClientError::Status {
+                    status: status.as_u16(),
+                    message: body,
+                })
+            }
+        };
+        while !job.status.is_final() {
+            let response: Response = self
+                .http
+                .get(format!("{}/{}", url, job.job))
+                .query(&[("wait", VOTE_WAIT_SECS)])
+                .send()
+                .await?;
+            job = checked(response).await?.json().await?;
+        }
+        match job.status {
+            JobStatus::Accepted { ticket } => Ok(Submission::Accepted { ticket }),
+            JobStatus::Rejected { reason } => Err(ClientError::Rejected(reason)),
+            _ => unreachable!(),
+        }
+    }
+
+    /// opens a committed ballot once a commit-reveal poll is in its reveal phase
+    pub async fn reveal(&self, reveal: &RevealPayload) -> Result<(), ClientError> {
+        checked(
+            self.http
+                .post(self.reveal_url())
+                .json(reveal)
+                .send()
+                .await?,
+        )
+        .await?;
+        Ok(())
+    }
+
+    pub async fn results(&self) -> Result<PollResults, ClientError> {
+        let response: Response =
+            checked(self.http.get(self.poll_url("/results")).send().await?).await?;
+        Ok(response.json().await?)
+    }
+
+    /// subscribes to the events of the poll,
+    /// the stream ends at the request timeout of the http client
+    pub async fn events(&self) -> Result<EventStream, ClientError> {
+        let response: Response =
+            checked(self.http.get(self.poll_url("/events")).send().await?).await?;
+        Ok(EventStream {
+            response,
+            buffer: Vec::new(),
+        })
+    }
+}
+
+pub struct EventStream {
+    response: Response,
+    buffer: Vec<u8>,
+}
+
+impl EventStream {
+    /// the next update, None once the service closed the stream
+    pub async fn next(&mut self) -> Result<Option<PollUpdate>, ClientError> {
+        loop {
+            while let Some(end) = self.buffer.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = self.buffer.drain(..=end).collect();
+                if let Some(update) = parse_event(&String::from_utf8_lossy(&line)) {
+                    return update.map(Some);
+                }
+            }
+            match self.response.chunk().await? {
+                Some(chunk) => self.buffer.extend_from_slice(&chunk),
+                None => return Ok(None),
+            }
+        }
+    }
+}
+
+// only the data lines matter, the event name is repeated in the json
+fn parse_event(line: &str) -> Option<Result<PollUpdate, ClientError>> {
+    let data: &str = line.strip_prefix("data:")?.trim();
+    Some(match serde_json::from_str::<PollEvent>(data) {
+        Ok(event) => Ok(PollUpdate::Event(event)),
+        // a lagged event carries the number of missed events
+        Err(e) => data
+            .parse()
+            .map(PollUpdate::Lagged)
+            .map_err(|_| ClientError::Decode(e.to_string())),
+    })
+}
+
+// the ballot must match the one committed to at registration,
+// the budget of a weighted quadratic poll is only known after registering
+pub fn build_ballot(
+    spec: &PollSpec,
+    vote: Vec<String>,
+    weight: Option<u64>,
+) -> Result<Ballot, ClientError> {
+    Ballot::from_choices(spec.kind, vote)
+        .and_then(|ballot| ballot.validate(spec).map(|_| ballot))
+        .and_then(|ballot| match weight {
+            Some(weight) => ballot.within_budget(spec, weight).map(|_| ballot),
+            None => Ok(ballot),
+        })
+        .map_err(ClientError::InvalidVote)
+}
+
+async fn checked(response: Response) -> Result<Response, ClientError> {
+    let status = response.status();
+    match status.is_success() {
+        true => Ok(response),
+        false => Err(ClientError::Status {
+            status: status.as_u16(),
+            message: response.text().await.unwrap_or_default(),
+        }),
+    }
+}
+
+#[test]
+fn test_parse_event() {
+    assert!(parse_event("event: phase").is_none());
+    assert!(matches!(
+        parse_event("data: {\"event\":\"phase\",\"phase\":\"closed\"}"),
+        Some(Ok(PollUpdate::Event(PollEvent::Phase {
+            phase: PollPhase::Closed
+        })))
+    ));
+    assert!(matches!(
+        parse_event("data: 12\n"),
+        Some(Ok(PollUpdate::Lagged(12)))
+    ));
+    assert!(matches!(
+        parse_event("data: {\"event\":"),
+        Some(Err(ClientError::Decode(_)))
+    ));
+}
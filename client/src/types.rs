@@ -1,8 +1,33 @@
-use crypto::identity::Identity;
+use crypto::{
+    elgamal::{Ciphertext, DecryptionShare},
+    identity::Identity,
+};
+use risc0_types::{Ballot, BallotKind, PollSpec};
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+// response header of a successful registration, the weight the leaf commits to
+pub const WEIGHT_HEADER: &str = "x-cypher-poll-weight";
+// request headers of a webhook delivery, the signature is sha256=<hex HMAC-SHA256 of the body>
+pub const SIGNATURE_HEADER: &str = "x-cypher-poll-signature";
+pub const DELIVERY_HEADER: &str = "x-cypher-poll-delivery";
+
+// posted to /polls/{poll}/challenge before registering, the identity is computed first
+#[derive(Serialize, Deserialize)]
+pub struct ChallengeRequest {
+    pub username: String,
+    pub identity: Identity,
+}
+
+// the data to sign for the registration, issued for one username and identity and valid once
+#[derive(Serialize, Deserialize)]
+pub struct Challenge {
+    pub data: Vec<u8>,
+}
 
 #[derive(Serialize, Deserialize)]
 pub struct IdentityPayload {
+    // the Challenge issued for this username and identity
     pub data_serialized: Vec<u8>,
     pub signature_serialized: Vec<Vec<u8>>,
     // string since this is a PGP key
@@ -11,4 +36,211 @@ pub struct IdentityPayload {
     pub username: String,
 }
 
-// note: the vote payload is just a serialized risc0 Receipt
+// note: the vote payload is a zk_associated::proof::ProofEnvelope
+
+// votes are verified in the background, a submission returns a job that can be polled
+// under /polls/{poll}/vote/{job}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VoteJob {
+    pub job: String,
+    pub poll: String,
+    #[serde(flatten)]
+    pub status: JobStatus,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,
+    Verifying,
+    // a random ticket, GET /polls/<id>/tickets/<ticket> confirms that the vote was recorded
+    // without revealing the choice
+    Accepted { ticket: String },
+    Rejected { reason: String },
+}
+
+impl JobStatus {
+    pub fn is_final(&self) -> bool {
+        matches!(
+            self,
+            JobStatus::Accepted { .. } | JobStatus::Rejected { .. }
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PollPhase {
+    // voters register, voting is opened through the admin api
+    Registration,
+    #[default]
+    Open,
+    // commit-reveal polls: votes are closed, committed ballots may be opened
+    Reveal,
+    Closed,
+}
+
+// what a client needs to know to build a ballot for the poll
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PollInfo {
+    pub id: String,
+    pub title: String,
+    pub phase: PollPhase,
+    pub spec: PollSpec,
+}
+
+// admin api: a poll with its registrations, see service/src/admin.rs
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PollOverview {
+    pub info: PollInfo,
+    pub registered: Vec<String>,
+    pub ballots: u64,
+    // only these users may register, anyone the eligibility policy admits if unset
+    pub allowlist: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PhaseChange {
+    pub phase: PollPhase,
+}
+
+// an empty allowlist admits nobody, clearing it admits anyone again
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct AllowlistEdit {
+    #[serde(default)]
+    pub add: Vec<String>,
+    #[serde(default)]
+    pub remove: Vec<String>,
+    #[serde(default)]
+    pub clear: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TallyRound {
+    pub counts: BTreeMap<String, u64>,
+    // ballots whose ranked options were all eliminated
+    pub exhausted: u64,
+    // options eliminated at the end of this round
+    pub eliminated: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PollResults {
+    pub poll: String,
+    pub phase: PollPhase,
+    pub sealed: bool,
+    #[serde(default)]
+    pub kind: BallotKind,
+    pub ballots: u64,
+    // None while a sealed poll is still open,
+    // the first preferences of a ranked poll
+    pub tally: Option<HashMap<String, u64>>,
+    // the instant-runoff rounds of a ranked poll
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rounds: Option<Vec<TallyRound>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub winner: Option<String>,
+    // encrypted polls: the sum of the encrypted ballots per option, once the poll is closed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aggregate: Option<Vec<Ciphertext>>,
+    // encrypted polls: the trustees whose decryption shares were accepted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trustees: Option<Vec<u32>>,
+    // commit-reveal polls: committed ballots that were not revealed (yet), never counted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unrevealed: Option<u64>,
+    // the decision under the rules of the poll, once it is closed and its tally is known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub outcome: Option<Outcome>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Outcome {
+    // counted ballots per registered voter
+    pub turnout: f64,
+    pub counted: u64,
+    pub decision: Decision,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum Decision {
+    // too few ballots or too low a turnout, nothing is decided
+    NoQuorum,
+    // the leading option stayed below the required share of the votes
+    NoSupermajority {
+        leader: String,
+        share: f64,
+    },
+    // the poll has no tie-break
+    Tie {
+        options: Vec<String>,
+    },
+    Winner {
+        option: String,
+        share: f64,
+        // won a tie by the tie-break of the poll
+        tie_break: bool,
+    },
+}
+
+// streamed as server-sent events under /polls/{poll}/events, the sse event name is the tag
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum PollEvent {
+    // a leaf was inserted or a registration revoked, root is hex encoded
+    Tree {
+        leaves: u64,
+        root: Option<String>,
+    },
+    Phase {
+        phase: PollPhase,
+    },
+    // the ballot is only shown for plain ballots of a poll that is not sealed
+    Vote {
+        ballots: u64,
+        ballot: Option<Ballot>,
+    },
+    // the tally is known, once a poll closes or its tally was decrypted
+    Results {
+        results: PollResults,
+    },
+}
+
+impl PollEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            PollEvent::Tree { .. } => "tree",
+            PollEvent::Phase { .. } => "phase",
+            PollEvent::Vote { .. } => "vote",
+            PollEvent::Results { .. } => "results",
+        }
+    }
+}
+
+// posted to the webhooks of a poll, the id is the same for every attempt of a delivery
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WebhookPayload {
+    pub id: String,
+    pub poll: String,
+    pub timestamp: u64,
+    #[serde(flatten)]
+    pub event: PollEvent,
+}
+
+// posted to /polls/{poll}/reveal during the reveal phase of a commit-reveal poll,
+// the nullifier is the one published by the proof, the revoting tag in a revoting poll
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RevealPayload {
+    pub nullifier: Vec<u8>,
+    pub ballot: Ballot,
+    pub salt: Vec<u8>,
+}
+
+// posted by a trustee to /polls/{poll}/shares,
+// one share per option in the order of the aggregate ciphertexts
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TrusteeShares {
+    pub index: u32,
+    pub shares: Vec<DecryptionShare>,
+}
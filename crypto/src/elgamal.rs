@@ -0,0 +1,297 @@
+// Exponential ElGamal on ristretto255, additively homomorphic in the plaintext.
+// The secret key is split into Shamir shares held by trustees, t of them decrypt a ciphertext
+// together, every decryption share carries a Chaum-Pedersen proof against the trustee's
+// verification key. Plaintexts are small vote counts, decryption solves a bounded discrete log.
+use curve25519_dalek::{
+    constants::RISTRETTO_BASEPOINT_POINT,
+    ristretto::{CompressedRistretto, RistrettoPoint},
+    scalar::Scalar,
+    traits::Identity,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha512};
+use std::{collections::HashMap, fmt};
+
+/// a compressed ristretto point
+pub type Point = [u8; 32];
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Ciphertext {
+    pub c1: Point,
+    pub c2: Point,
+}
+
+/// f(index) of the Shamir polynomial whose constant term is the secret key
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct KeyShare {
+    pub index: u32,
+    pub secret: [u8; 32],
+}
+
+/// secret * c1 of a trustee, with a proof that it used the secret of its verification key
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct DecryptionShare {
+    pub index: u32,
+    pub share: Point,
+    pub challenge: [u8; 32],
+    pub response: [u8; 32],
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ElGamalError {
+    InvalidPoint,
+    InvalidScalar,
+    InvalidProof,
+    NotEnoughShares,
+    DuplicateShare,
+    // the plaintext is larger than the searched bound
+    NoSolution,
+}
+
+impl fmt::Display for ElGamalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ElGamalError::InvalidPoint => write!(f, "Invalid curve point"),
+            ElGamalError::InvalidScalar => write!(f, "Invalid scalar"),
+            ElGamalError::InvalidProof => write!(f, "Invalid decryption share proof"),
+            ElGamalError::NotEnoughShares => write!(f, "Not enough decryption shares"),
+            ElGamalError::DuplicateShare => write!(f, "Duplicate decryption share"),
+            ElGamalError::NoSolution => write!(f, "Plaintext is out of range"),
+        }
+    }
+}
+
+impl std::error::Error for ElGamalError {}
+
+fn point(bytes: &Point) -> Result<RistrettoPoint, ElGamalError> {
+    CompressedRistretto(*bytes)
+        .decompress()
+        .ok_or(ElGamalError::InvalidPoint)
+}
+
+/// rejects bytes that are not a canonical encoding of a point
+pub fn check_point(bytes: &Point) -> Result<(), ElGamalError> {
+    point(bytes).map(|_| ())
+}
+
+fn scalar(bytes: &[u8; 32]) -> Result<Scalar, ElGamalError> {
+    Option::from(Scalar::from_canonical_bytes(*bytes)).ok_or(ElGamalError::InvalidScalar)
+}
+
+/// a uniformly distributed scalar (secret key, randomness, nonce) from 64 random bytes
+pub fn random_scalar(random: &[u8; 64]) -> [u8; 32] {
+    Scalar::from_bytes_mod_order_wide(random).to_bytes()
+}
+
+pub fn public_key(secret: &[u8; 32]) -> Result<Point, ElGamalError> {
+    Ok((scalar(secret)? * RISTRETTO_BASEPOINT_POINT)
+        .compress()
+        .to_bytes())
+}
+
+pub fn encrypt(
+    public_key: &Point,
+    message: u64,
+    randomness: &[u8; 32],
+) -> Result<Ciphertext, ElGamalError> {
+    let r: Scalar = scalar(randomness)?;
+    Ok(Ciphertext {
+        c1: (r * RISTRETTO_BASEPOINT_POINT).compress().to_bytes(),
+        c2: (Scalar::from(message) * RISTRETTO_BASEPOINT_POINT + r * point(public_key)?)
+            .compress()
+            .to_bytes(),
+    })
+}
+
+impl Ciphertext {
+    /// the encryption of 0 without randomness, the neutral element of `add`
+    pub fn zero() -> Ciphertext {
+        let identity: Point = RistrettoPoint::identity().compress().to_bytes();
+        Ciphertext {
+            c1: identity,
+            c2: identity,
+        }
+    }
+
+    /// encrypts the sum of both plaintexts
+    pub fn add(&self, other: &Ciphertext) -> Result<Ciphertext, ElGamalError> {
+        Ok(Ciphertext {
+            c1: (point(&self.c1)? + point(&other.c1)?).compress().to_bytes(),
+            c2: (point(&self.c2)? + point(&other.c2)?).compress().to_bytes(),
+        })
+    }
+}
+
+/// splits the secret key into `trustees` shares, any `coefficients.len() + 1` of them decrypt
+pub fn split(
+    secret: &[u8; 32],
+    coefficients: &[[u8; 32]],
+    trustees: u32,
+) -> Result<Vec<KeyShare>, ElGamalError> {
+    let mut polynomial: Vec<Scalar> = vec![scalar(secret)?];
+    for coefficient in coefficients {
+        polynomial.push(scalar(coefficient)?);
+    }
+    Ok((1..=trustees)
+        .map(|index| {
+            let x: Scalar = Scalar::from(index as u64);
+            // Horner's method
+            let y: Scalar = polynomial
+                .iter()
+                .rev()
+                .fold(Scalar::ZERO, |y, coefficient| y * x + coefficient);
+            KeyShare {
+                index,
+                secret: y.to_bytes(),
+            }
+        })
+        .collect())
+}
+
+// Fiat-Shamir challenge of the Chaum-Pedersen proof
+fn challenge(points: &[&RistrettoPoint]) -> Scalar {
+    let mut hasher = Sha512::new();
+    hasher.update(b"cypher-poll-decryption-share");
+    for point in points {
+        hasher.update(point.compress().as_bytes());
+    }
+    let mut wide: [u8; 64] = [0; 64];
+    wide.copy_from_slice(&hasher.finalize());
+    Scalar::from_bytes_mod_order_wide(&wide)
+}
+
+/// `nonce` must be fresh randomness, see `random_scalar`
+pub fn decryption_share(
+    key: &KeyShare,
+    ciphertext: &Ciphertext,
+    nonce: &[u8; 32],
+) -> Result<DecryptionShare, ElGamalError> {
+    let (x, k): (Scalar, Scalar) = (scalar(&key.secret)?, scalar(nonce)?);
+    let c1: RistrettoPoint = point(&ciphertext.c1)?;
+    let verification_key: RistrettoPoint = x * RISTRETTO_BASEPOINT_POINT;
+    let share: RistrettoPoint = x * c1;
+    // proves log_G(verification_key) == log_c1(share)
+    let e: Scalar = challenge(&[
+        &verification_key,
+        &c1,
+        &share,
+        &(k * RISTRETTO_BASEPOINT_POINT),
+        &(k * c1),
+    ]);
+    Ok(DecryptionShare {
+        index: key.index,
+        share: share.compress().to_bytes(),
+        challenge: e.to_bytes(),
+        response: (k + e * x).to_bytes(),
+    })
+}
+
+pub fn verify_share(
+    verification_key: &Point,
+    ciphertext: &Ciphertext,
+    share: &DecryptionShare,
+) -> Result<(), ElGamalError> {
+    let (y, c1, d): (RistrettoPoint, RistrettoPoint, RistrettoPoint) = (
+        point(verification_key)?,
+        point(&ciphertext.c1)?,
+        point(&share.share)?,
+    );
+    let (e, s): (Scalar, Scalar) = (scalar(&share.challenge)?, scalar(&share.response)?);
+    let a: RistrettoPoint = s * RISTRETTO_BASEPOINT_POINT - e * y;
+    let b: RistrettoPoint = s * c1 - e * d;
+    match challenge(&[&y, &c1, &d, &a, &b]) == e {
+        true => Ok(()),
+        false => Err(ElGamalError::InvalidProof),
+    }
+}
+
+// the Lagrange coefficient of `index` for interpolating f(0)
+fn lagrange(indices: &[u32], index: u32) -> Scalar {
+    let x_i: Scalar = Scalar::from(index as u64);
+    indices
+        .iter()
+        .filter(|j| **j != index)
+        .fold(Scalar::ONE, |coefficient, j| {
+            let x_j: Scalar = Scalar::from(*j as u64);
+            coefficient * x_j * (x_j - x_i).invert()
+        })
+}
+
+/// decrypts a ciphertext from `threshold` verified shares, the plaintext must be at most `max`
+pub fn combine(
+    ciphertext: &Ciphertext,
+    shares: &[DecryptionShare],
+    threshold: usize,
+    max: u64,
+) -> Result<u64, ElGamalError> {
+    let shares: &[DecryptionShare] = shares
+        .get(..threshold)
+        .ok_or(ElGamalError::NotEnoughShares)?;
+    let indices: Vec<u32> = shares.iter().map(|share| share.index).collect();
+    if (1..indices.len()).any(|i| indices[..i].contains(&indices[i])) {
+        return Err(ElGamalError::DuplicateShare);
+    }
+    let mut secret_c1: RistrettoPoint = RistrettoPoint::identity();
+    for share in shares {
+        secret_c1 += lagrange(&indices, share.index) * point(&share.share)?;
+    }
+    discrete_log(point(&ciphertext.c2)? - secret_c1, max).ok_or(ElGamalError::NoSolution)
+}
+
+// baby-step giant-step for m * G with m <= max
+fn discrete_log(target: RistrettoPoint, max: u64) -> Option<u64> {
+    let step: u64 = ((max + 1) as f64).sqrt().ceil() as u64;
+    let mut baby: HashMap<[u8; 32], u64> = HashMap::new();
+    let mut current: RistrettoPoint = RistrettoPoint::identity();
+    for j in 0..step {
+        baby.insert(current.compress().to_bytes(), j);
+        current += RISTRETTO_BASEPOINT_POINT;
+    }
+    let giant: RistrettoPoint = -(Scalar::from(step) * RISTRETTO_BASEPOINT_POINT);
+    let mut current: RistrettoPoint = target;
+    for i in 0..=step {
+        if let Some(j) = baby.get(current.compress().as_bytes()) {
+            let m: u64 = i * step + j;
+            return (m <= max).then_some(m);
+        }
+        current += giant;
+    }
+    None
+}
+
+#[test]
+fn test_threshold_decryption() {
+    let random = |seed: u8| random_scalar(&[seed; 64]);
+    let secret: [u8; 32] = random(1);
+    let public_key: Point = public_key(&secret).unwrap();
+    // 2 of 3 trustees
+    let shares: Vec<KeyShare> = split(&secret, &[random(2)], 3).unwrap();
+    let ballots: Vec<Ciphertext> = [3u64, 0, 4]
+        .iter()
+        .enumerate()
+        .map(|(i, votes)| encrypt(&public_key, *votes, &random(10 + i as u8)).unwrap())
+        .collect();
+    let total: Ciphertext = ballots
+        .iter()
+        .try_fold(Ciphertext::zero(), |total, ballot| total.add(ballot))
+        .unwrap();
+    let decryption: Vec<DecryptionShare> = [&shares[2], &shares[0]]
+        .iter()
+        .map(|key| decryption_share(key, &total, &random(20)).unwrap())
+        .collect();
+    for (key, share) in [&shares[2], &shares[0]].iter().zip(&decryption) {
+        let verification_key: Point = crate::elgamal::public_key(&key.secret).unwrap();
+        assert_eq!(verify_share(&verification_key, &total, share), Ok(()));
+    }
+    assert_eq!(combine(&total, &decryption, 2, 100), Ok(7));
+    assert_eq!(
+        combine(&total, &decryption[..1], 2, 100),
+        Err(ElGamalError::NotEnoughShares)
+    );
+    // a share made with a different key does not verify
+    let verification_key: Point = crate::elgamal::public_key(&shares[1].secret).unwrap();
+    assert_eq!(
+        verify_share(&verification_key, &total, &decryption[0]),
+        Err(ElGamalError::InvalidProof)
+    );
+}
@@ -2,7 +2,27 @@ use pgp::{
     types::{Mpi, PublicKeyTrait, SecretKeyTrait},
     Deserializable, SignedPublicKey, SignedSecretKey,
 };
-use std::{fs, path::PathBuf};
+use std::{fmt, fs, path::PathBuf};
+
+#[derive(Debug)]
+pub enum GpgError {
+    Missing(&'static str),
+    Io(PathBuf, std::io::Error),
+    Parse(pgp::errors::Error),
+}
+
+impl fmt::Display for GpgError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GpgError::Missing(key) => write!(f, "Missing {}", key),
+            GpgError::Io(path, e) => write!(f, "Failed to read {}: {}", path.display(), e),
+            GpgError::Parse(e) => write!(f, "Invalid key: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for GpgError {}
+
 pub struct GpgSigner {
     pub secret_key_asc_path: Option<PathBuf>,
     pub public_key_asc_string: Option<String>,
@@ -14,32 +34,28 @@ pub struct GpgSigner {
 
 impl GpgSigner {
     pub fn init(&mut self) {
+        self.try_init().expect("Failed to import keys");
+    }
+    /// like init, for keys a user supplied
+    pub fn try_init(&mut self) -> Result<(), GpgError> {
         // import private key from file
-        let secret_key_string: String = fs::read_to_string(
-            &self
-                .secret_key_asc_path
-                .as_ref()
-                .expect("Missing secret key path"),
-        )
-        .expect("Failed to read secret key");
-        self.signed_secret_key = Some(SignedSecretKey::from_string(&secret_key_string).unwrap().0);
-        // import public key from file
-        self.signed_public_key = Some(
-            SignedPublicKey::from_string(
-                &self
-                    .public_key_asc_string
-                    .as_ref()
-                    .expect("Must have public key string"),
-            )
-            .unwrap()
-            .0,
+        let path: &PathBuf = self
+            .secret_key_asc_path
+            .as_ref()
+            .ok_or(GpgError::Missing("secret key path"))?;
+        let secret_key_string: String =
+            fs::read_to_string(path).map_err(|e| GpgError::Io(path.clone(), e))?;
+        self.signed_secret_key = Some(
+            SignedSecretKey::from_string(&secret_key_string)
+                .map_err(GpgError::Parse)?
+                .0,
         );
+        self.try_init_verifier()
     }
     pub fn init_signer(&mut self) {
         // import private key from file
         let secret_key_string: String = fs::read_to_string(
-            &self
-                .secret_key_asc_path
+            self.secret_key_asc_path
                 .as_ref()
                 .expect("Missing secret key path"),
         )
@@ -47,16 +63,21 @@ impl GpgSigner {
         self.signed_secret_key = Some(SignedSecretKey::from_string(&secret_key_string).unwrap().0);
     }
     pub fn init_verifier(&mut self) {
+        self.try_init_verifier()
+            .expect("Failed to import public key");
+    }
+    /// like init_verifier, for keys from untrusted sources
+    pub fn try_init_verifier(&mut self) -> Result<(), GpgError> {
+        let public_key_string: &String = self
+            .public_key_asc_string
+            .as_ref()
+            .ok_or(GpgError::Missing("public key"))?;
         self.signed_public_key = Some(
-            SignedPublicKey::from_string(
-                &self
-                    .public_key_asc_string
-                    .as_ref()
-                    .expect("Must have public key string"),
-            )
-            .unwrap()
-            .0,
+            SignedPublicKey::from_string(public_key_string)
+                .map_err(GpgError::Parse)?
+                .0,
         );
+        Ok(())
     }
     pub fn sign_bytes(&mut self, data: &[u8]) -> Vec<Mpi> {
         assert!(self.signed_secret_key.is_some());
@@ -66,34 +87,28 @@ impl GpgSigner {
             .create_signature(
                 || "1234".to_string(),
                 pgp::crypto::hash::HashAlgorithm::MD5,
-                &data,
+                data,
             )
             .expect("Failed to generate signature")
     }
     pub fn is_valid_signature(&mut self, signature: Vec<Mpi>, data: &[u8]) -> bool {
         assert!(self.signed_public_key.is_some());
-        match self.signed_public_key.as_ref().unwrap().verify_signature(
-            pgp::crypto::hash::HashAlgorithm::MD5,
-            &data,
-            &signature,
-        ) {
-            Ok(_) => true,
-            Err(_) => false,
-        }
+        self.signed_public_key
+            .as_ref()
+            .unwrap()
+            .verify_signature(pgp::crypto::hash::HashAlgorithm::MD5, data, &signature)
+            .is_ok()
     }
 }
 
 #[test]
 fn test() {
     use std::fs;
-    let public_key_asc_path: PathBuf =
-        PathBuf::from("/Users/chef/Desktop/cypher-poll/resources/test/key.asc");
-    let public_key_string: String = fs::read_to_string(public_key_asc_path).unwrap();
+    let keys: PathBuf = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../resources/test");
+    let public_key_string: String = fs::read_to_string(keys.join("key.asc")).unwrap();
 
     let mut signer = GpgSigner {
-        secret_key_asc_path: Some(PathBuf::from(
-            "/Users/chef/Desktop/cypher-poll/resources/test/key.sec.asc",
-        )),
+        secret_key_asc_path: Some(keys.join("key.sec.asc")),
         public_key_asc_string: Some(public_key_string),
         signed_secret_key: None,
         signed_public_key: None,
@@ -104,6 +119,27 @@ fn test() {
     assert!(signer.is_valid_signature(signature, &data));
 }
 
+#[test]
+fn test_invalid_keys() {
+    let mut signer = GpgSigner {
+        secret_key_asc_path: None,
+        public_key_asc_string: Some("not a key".to_string()),
+        signed_secret_key: None,
+        signed_public_key: None,
+    };
+    assert!(matches!(
+        signer.try_init_verifier(),
+        Err(GpgError::Parse(_))
+    ));
+    let mut signer = GpgSigner {
+        secret_key_asc_path: Some(PathBuf::from("/nonexistent/key.sec.asc")),
+        public_key_asc_string: None,
+        signed_secret_key: None,
+        signed_public_key: None,
+    };
+    assert!(matches!(signer.try_init(), Err(GpgError::Io(_, _))));
+}
+
 // to find: gpg --list-keys || gpg --list-secret-keys
 // to export: gpg --armor --export KEY_ID > key.asc || --export-secret-keys KEY_ID
 
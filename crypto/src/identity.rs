@@ -1,11 +1,11 @@
 use pgp::{ser::Serialize, SignedPublicKey};
 // Generate a cryptographic identity, using a nullifier and GPG public key
 use super::{hash, CryptoHasherSha256};
-/// A unique cryptographic identity for this voting system
 
 pub type Nullifier = Vec<u8>;
 pub type Identity = Vec<u8>;
 
+/// A unique cryptographic identity for this voting system
 pub struct UniqueIdentity {
     pub nullifier: Option<Nullifier>,
     pub identity: Option<Identity>,
@@ -20,13 +20,30 @@ impl UniqueIdentity {
     pub fn generate_nullifier(&mut self, random_seed: String) {
         // avoid accidental re-computation
         assert!(self.nullifier.is_none());
-        self.nullifier = Some(hash(CryptoHasherSha256, &random_seed.as_bytes()));
+        self.nullifier = Some(hash(CryptoHasherSha256, random_seed.as_bytes()));
     }
     pub fn compute_public_identity(&mut self, public_key: SignedPublicKey, vote: String) {
         assert!(self.nullifier.is_some() && self.identity.is_none());
         let mut payload = self.nullifier.clone().unwrap();
         payload.append(&mut public_key.to_bytes().unwrap());
-        payload.append(vote.as_bytes().to_vec().as_mut());
+        payload.extend_from_slice(vote.as_bytes());
         self.identity = Some(hash(CryptoHasherSha256, &payload));
     }
 }
+
+/// The public tag of the ballot with this sequence number of a voter in a revoting poll,
+/// a revote names the tag of the ballot it replaces, without revealing the nullifier
+pub fn revote_tag(nullifier: &Nullifier, sequence: u64) -> Vec<u8> {
+    let mut payload: Vec<u8> = b"cypher-poll-revote".to_vec();
+    payload.extend_from_slice(nullifier);
+    payload.extend_from_slice(&sequence.to_be_bytes());
+    hash(CryptoHasherSha256, &payload)
+}
+
+/// The leaf of an identity in a weighted poll, the service appends the weight it assigned
+/// so that the voter can neither choose nor change it
+pub fn weighted_leaf(identity: &Identity, weight: u64) -> Identity {
+    let mut payload: Vec<u8> = identity.clone();
+    payload.extend_from_slice(&weight.to_be_bytes());
+    hash(CryptoHasherSha256, &payload)
+}
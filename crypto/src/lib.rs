@@ -1,6 +1,8 @@
+pub mod elgamal;
 pub mod gpg;
 pub mod identity;
 
+use hmac::{Hmac, Mac};
 use sha2::{Digest, Sha256};
 
 pub trait CryptoHasher {
@@ -32,3 +34,31 @@ pub fn hash_left_right<H: CryptoHasher>(
 ) -> Vec<u8> {
     hasher.hash_left_right(left, right)
 }
+
+// signs webhook payloads
+pub fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+    let mut hex: String = String::with_capacity(bytes.len() * 2);
+    for b in bytes {
+        hex.push(DIGITS[(b >> 4) as usize] as char);
+        hex.push(DIGITS[(b & 0x0f) as usize] as char);
+    }
+    hex
+}
+
+/// None unless the string is an even number of hex digits
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
@@ -0,0 +1,554 @@
+// relays votes to the poll service so that the service never sees the network identity of a voter
+// proofs are verified locally, held back for a random delay and forwarded in shuffled batches,
+// so that neither the arrival time nor the order at the service can be linked to a submission.
+// the reveals of commit-reveal polls take the same way, mixed into the same batches.
+// a vote the service turns away for being busy stays queued until it is delivered
+use axum::{
+    extract::{DefaultBodyLimit, Path},
+    http::header::RETRY_AFTER,
+    response::{IntoResponse, Response},
+    routing::post,
+    Extension, Json, Router,
+};
+use clap::Parser;
+use client::types::{PollInfo, PollPhase, RevealPayload};
+use colored::*;
+use rand::{seq::SliceRandom, Rng};
+use reqwest::StatusCode;
+use risc0_prover::verifier::Risc0Verifier;
+use risc0_types::SALT_LENGTH;
+use std::{
+    collections::VecDeque,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::{Mutex, Semaphore};
+use zk_associated::{
+    dev::DevVerifier,
+    proof::{ProofEnvelope, VerifierSet, VoteVerifier},
+};
+
+#[derive(Parser, Clone)]
+struct Args {
+    #[arg(long, default_value = "127.0.0.1:8081")]
+    bind: SocketAddr,
+    /// url of the poll service
+    #[arg(long, default_value = "http://127.0.0.1:8080")]
+    upstream: String,
+    /// lower bound of the random delay between two batches
+    #[arg(long, default_value_t = 30)]
+    min_delay_secs: u64,
+    /// upper bound of the random delay between two batches
+    #[arg(long, default_value_t = 300)]
+    max_delay_secs: u64,
+    /// hold votes back until at least this many are queued ...
+    #[arg(long, default_value_t = 5)]
+    min_batch: usize,
+    /// ... or the oldest queued vote has waited this long
+    #[arg(long, default_value_t = 3600)]
+    max_hold_secs: u64,
+    #[arg(long, default_value_t = 10000000)]
+    body_limit_bytes: usize,
+    /// proofs verified at the same time
+    #[arg(long, default_value_t = 2)]
+    verification_concurrency: usize,
+    /// proofs waiting for verification, further votes are answered with 503
+    #[arg(long, default_value_t = 16)]
+    verification_queue: usize,
+    /// votes and reveals held for relay, further ones are answered with 503
+    #[arg(long, default_value_t = 10000)]
+    max_queued: usize,
+    /// accept unsound dev-mode proofs, never use this for a real poll
+    #[arg(long)]
+    insecure_dev_proofs: bool,
+}
+
+// attempts per vote and batch, a vote that runs out of them goes back into the queue
+const MAX_ATTEMPTS: u32 = 5;
+const BASE_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+// a reveal is a ballot and a salt, it never needs the body limit of a proof
+const REVEAL_BODY_LIMIT: usize = 64 * 1024;
+// nullifiers and revoting tags are sha256 digests
+const NULLIFIER_LENGTH: usize = 32;
+
+enum Submission {
+    Vote(ProofEnvelope),
+    Reveal(RevealPayload),
+}
+
+struct QueuedVote {
+    poll_id: String,
+    // published by the proof, or named by the reveal
+    nullifier: Vec<u8>,
+    submission: Submission,
+    queued_at: Instant,
+}
+
+impl QueuedVote {
+    fn new(poll_id: String, nullifier: Vec<u8>, submission: Submission) -> QueuedVote {
+        QueuedVote {
+            poll_id,
+            nullifier,
+            submission,
+            queued_at: Instant::now(),
+        }
+    }
+    fn kind(&self) -> &'static str {
+        match self.submission {
+            Submission::Vote(_) => "vote",
+            Submission::Reveal(_) => "reveal",
+        }
+    }
+}
+
+// the same bounds as the verification pool of the service: a slot is held from arrival until
+// the verification finished, a worker only while verifying
+struct Verification {
+    verifier: Arc<dyn VoteVerifier>,
+    slots: Arc<Semaphore>,
+    workers: Arc<Semaphore>,
+}
+
+impl Verification {
+    fn new(verifier: Arc<dyn VoteVerifier>, workers: usize, queue: usize) -> Verification {
+        Verification {
+            verifier,
+            slots: Arc::new(Semaphore::new(workers + queue)),
+            workers: Arc::new(Semaphore::new(workers)),
+        }
+    }
+}
+
+enum Forwarded {
+    Relayed,
+    // the service refused the vote, a retry would not change that
+    Rejected(StatusCode),
+    // the service is busy or unreachable, with its Retry-After
+    Deferred(String, Option<Duration>),
+}
+
+enum Refused {
+    Duplicate,
+    Full,
+}
+
+impl IntoResponse for Refused {
+    fn into_response(self) -> Response {
+        match self {
+            Refused::Duplicate => {
+                (StatusCode::CONFLICT, "Already queued for relay").into_response()
+            }
+            Refused::Full => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                [(RETRY_AFTER, "60")],
+                "Relay queue is full",
+            )
+                .into_response(),
+        }
+    }
+}
+
+struct RelayQueue {
+    votes: Vec<QueuedVote>,
+    capacity: usize,
+}
+
+impl RelayQueue {
+    fn new(capacity: usize) -> RelayQueue {
+        RelayQueue {
+            votes: Vec::new(),
+            capacity,
+        }
+    }
+    fn check_duplicate(&self, poll_id: &str, kind: &str, nullifier: &[u8]) -> Result<(), Refused> {
+        match self.votes.iter().any(|queued| {
+            queued.poll_id == poll_id && queued.kind() == kind && queued.nullifier == nullifier
+        }) {
+            true => Err(Refused::Duplicate),
+            false => Ok(()),
+        }
+    }
+    // a replayed submission is forwarded once, the queue is bounded against floods
+    fn push(&mut self, vote: QueuedVote) -> Result<(), Refused> {
+        self.check_duplicate(&vote.poll_id, vote.kind(), &vote.nullifier)?;
+        if self.votes.len() >= self.capacity {
+            return Err(Refused::Full);
+        }
+        self.votes.push(vote);
+        Ok(())
+    }
+    // the whole queue in random order, once it is large or old enough
+    fn take_batch(&mut self, min_batch: usize, max_hold: Duration) -> Vec<QueuedVote> {
+        let oldest_expired = self
+            .votes
+            .iter()
+            .any(|vote| vote.queued_at.elapsed() >= max_hold);
+        if self.votes.is_empty() || (self.votes.len() < min_batch && !oldest_expired) {
+            return Vec::new();
+        }
+        let mut batch: Vec<QueuedVote> = std::mem::take(&mut self.votes);
+        batch.shuffle(&mut rand::thread_rng());
+        batch
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
+    if args.min_delay_secs > args.max_delay_secs {
+        eprintln!(
+            "{}: --min-delay-secs must not exceed --max-delay-secs",
+            "Error".bold().red()
+        );
+        std::process::exit(2);
+    }
+    if args.verification_concurrency == 0 {
+        eprintln!(
+            "{}: --verification-concurrency must be at least 1",
+            "Error".bold().red()
+        );
+        std::process::exit(2);
+    }
+    let verifier: Arc<dyn VoteVerifier> = if args.insecure_dev_proofs {
+        println!(
+            "{}",
+            " INSECURE: accepting dev-mode proofs "
+                .bold()
+                .white()
+                .on_red()
+        );
+        Arc::new(VerifierSet::default().with(Risc0Verifier).with(DevVerifier))
+    } else {
+        Arc::new(Risc0Verifier)
+    };
+    let verification: Arc<Verification> = Arc::new(Verification::new(
+        verifier,
+        args.verification_concurrency,
+        args.verification_queue,
+    ));
+    let queue: Arc<Mutex<RelayQueue>> = Arc::new(Mutex::new(RelayQueue::new(args.max_queued)));
+    let upstream: Upstream = Upstream {
+        client: reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .expect("Failed to create client"),
+        url: args.upstream.trim_end_matches('/').to_string(),
+    };
+    tokio::spawn(forward(args.clone(), Arc::clone(&queue)));
+    let app = Router::new()
+        .route("/polls/:poll_id/vote", post(relay))
+        .route(
+            "/polls/:poll_id/reveal",
+            post(relay_reveal).layer(DefaultBodyLimit::max(REVEAL_BODY_LIMIT)),
+        )
+        .layer(DefaultBodyLimit::max(args.body_limit_bytes))
+        .layer(Extension(queue))
+        .layer(Extension(verification))
+        .layer(Extension(Arc::new(upstream)));
+    let listener = tokio::net::TcpListener::bind(args.bind).await.unwrap();
+    println!("Relaying votes to {}", &args.upstream);
+    axum::serve(listener, app).await.unwrap();
+}
+
+async fn relay(
+    Path(poll_id): Path<String>,
+    Extension(queue): Extension<Arc<Mutex<RelayQueue>>>,
+    Extension(verification): Extension<Arc<Verification>>,
+    Json(payload): Json<ProofEnvelope>,
+) -> Response {
+    // a proof that is queued already is turned away before the expensive verification
+    let nullifier: Vec<u8> = match verification.verifier.inspect(&payload) {
+        Ok(outputs) => outputs.nullifier,
+        Err(error) => return (StatusCode::BAD_REQUEST, error.to_string()).into_response(),
+    };
+    if let Err(refused) = queue
+        .lock()
+        .await
+        .check_duplicate(&poll_id, "vote", &nullifier)
+    {
+        return refused.into_response();
+    }
+    let Ok(slot) = Arc::clone(&verification.slots).try_acquire_owned() else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(RETRY_AFTER, "1")],
+            "Verification queue is full",
+        )
+            .into_response();
+    };
+    let worker = Arc::clone(&verification.workers)
+        .acquire_owned()
+        .await
+        .expect("Verification workers closed");
+    // don't forward garbage, the service would reject it anyway.
+    // the permits move into the task, a client that hangs up does not free them early
+    let verified = {
+        let verifier = Arc::clone(&verification.verifier);
+        let payload = payload.clone();
+        tokio::task::spawn_blocking(move || {
+            let _permits = (slot, worker);
+            verifier.verify(&payload)
+        })
+        .await
+    };
+    // the verified outputs, the inspected ones were not bound to the proof
+    let nullifier: Vec<u8> = match verified {
+        Ok(Ok(outputs)) => outputs.nullifier,
+        Ok(Err(error)) => return (StatusCode::BAD_REQUEST, error.to_string()).into_response(),
+        Err(_) => {
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Verification failed").into_response()
+        }
+    };
+    let queued = QueuedVote::new(poll_id, nullifier, Submission::Vote(payload));
+    match queue.lock().await.push(queued) {
+        Ok(()) => (StatusCode::ACCEPTED, "Queued for relay").into_response(),
+        Err(refused) => refused.into_response(),
+    }
+}
+
+// the service the relayer forwards to, asked for the phase and spec of a poll
+struct Upstream {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl Upstream {
+    async fn poll_info(&self, poll_id: &str) -> Result<PollInfo, String> {
+        let url: String = format!("{}/polls/{}", self.url, poll_id);
+        let response = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        if !response.status().is_success() {
+            return Err(format!("Response Status {}", response.status()));
+        }
+        response.json().await.map_err(|e| e.to_string())
+    }
+}
+
+// the checks of the service that need no ballot weight, a reveal it would refuse is not queued
+fn check_reveal(info: &PollInfo, payload: &RevealPayload) -> Result<(), String> {
+    if !info.spec.commit_reveal {
+        return Err("Poll is not commit-reveal".to_string());
+    }
+    if info.phase != PollPhase::Reveal {
+        return Err("Poll is not in its reveal phase".to_string());
+    }
+    if payload.salt.len() != SALT_LENGTH {
+        return Err("Invalid salt length".to_string());
+    }
+    if payload.nullifier.len() != NULLIFIER_LENGTH {
+        return Err("Invalid nullifier length".to_string());
+    }
+    payload
+        .ballot
+        .validate(&info.spec)
+        .map_err(|e| format!("Invalid ballot: {}", e))
+}
+
+// a reveal names the nullifier of a ballot, sent directly it would link the ballot to the
+// address of the voter. there is no proof to check, it is checked against the poll instead
+async fn relay_reveal(
+    Path(poll_id): Path<String>,
+    Extension(queue): Extension<Arc<Mutex<RelayQueue>>>,
+    Extension(upstream): Extension<Arc<Upstream>>,
+    Json(payload): Json<RevealPayload>,
+) -> Response {
+    let info: PollInfo = match upstream.poll_info(&poll_id).await {
+        Ok(info) => info,
+        Err(e) => {
+            return (
+                StatusCode::BAD_GATEWAY,
+                format!("Failed to fetch poll {}: {}", poll_id, e),
+            )
+                .into_response()
+        }
+    };
+    if let Err(reason) = check_reveal(&info, &payload) {
+        return (StatusCode::BAD_REQUEST, reason).into_response();
+    }
+    let queued = QueuedVote::new(
+        poll_id,
+        payload.nullifier.clone(),
+        Submission::Reveal(payload),
+    );
+    match queue.lock().await.push(queued) {
+        Ok(()) => (StatusCode::ACCEPTED, "Queued for relay").into_response(),
+        Err(refused) => refused.into_response(),
+    }
+}
+
+// doubles from BASE_BACKOFF, a Retry-After of the service takes precedence
+fn backoff(attempt: u32, retry_after: Option<Duration>) -> Duration {
+    retry_after
+        .unwrap_or_else(|| BASE_BACKOFF * 2u32.saturating_pow(attempt.saturating_sub(1)))
+        .min(MAX_BACKOFF)
+}
+
+async fn send(client: &reqwest::Client, upstream: &str, vote: &QueuedVote) -> Forwarded {
+    let url: String = format!("{}/polls/{}/{}", upstream, vote.poll_id, vote.kind());
+    let request = match &vote.submission {
+        Submission::Vote(proof) => client.post(url).json(proof),
+        Submission::Reveal(reveal) => client.post(url).json(reveal),
+    };
+    match request.send().await {
+        Ok(response) if response.status().is_success() => Forwarded::Relayed,
+        Ok(response)
+            if response.status() == StatusCode::TOO_MANY_REQUESTS
+                || response.status().is_server_error() =>
+        {
+            let retry_after: Option<Duration> = response
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.trim().parse().ok())
+                .map(Duration::from_secs);
+            Forwarded::Deferred(response.status().to_string(), retry_after)
+        }
+        Ok(response) => Forwarded::Rejected(response.status()),
+        Err(e) => Forwarded::Deferred(e.to_string(), None),
+    }
+}
+
+async fn forward(args: Args, queue: Arc<Mutex<RelayQueue>>) {
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(600))
+        .build()
+        .expect("Failed to create client");
+    let upstream: String = args.upstream.trim_end_matches('/').to_string();
+    loop {
+        let delay: u64 = rand::thread_rng().gen_range(args.min_delay_secs..=args.max_delay_secs);
+        tokio::time::sleep(Duration::from_secs(delay)).await;
+        let batch: Vec<QueuedVote> = queue
+            .lock()
+            .await
+            .take_batch(args.min_batch, Duration::from_secs(args.max_hold_secs));
+        let mut pending: VecDeque<QueuedVote> = batch.into();
+        let mut attempt: u32 = 0;
+        while let Some(vote) = pending.pop_front() {
+            attempt += 1;
+            match send(&client, &upstream, &vote).await {
+                Forwarded::Relayed => {
+                    attempt = 0;
+                    println!(
+                        "{}: {} for poll {}",
+                        "Relayed".bold().green(),
+                        vote.kind(),
+                        vote.poll_id
+                    )
+                }
+                Forwarded::Rejected(status) => {
+                    attempt = 0;
+                    println!(
+                        "{}: poll {} responded with {}",
+                        "Rejected".bold().red(),
+                        vote.poll_id,
+                        status
+                    )
+                }
+                // the rest of the batch waits for the next one, shuffled with the newer votes
+                Forwarded::Deferred(reason, _) if attempt >= MAX_ATTEMPTS => {
+                    println!(
+                        "{}: {}, {} submissions stay queued",
+                        "Failed to relay".bold().red(),
+                        reason,
+                        pending.len() + 1
+                    );
+                    pending.push_front(vote);
+                    queue.lock().await.votes.extend(pending.drain(..));
+                }
+                Forwarded::Deferred(reason, retry_after) => {
+                    let wait: Duration = backoff(attempt, retry_after);
+                    println!(
+                        "{}: {}, retrying in {}s",
+                        "Deferred".bold().yellow(),
+                        reason,
+                        wait.as_secs()
+                    );
+                    tokio::time::sleep(wait).await;
+                    pending.push_front(vote);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_take_batch() {
+    use zk_associated::proof::ProofBackend;
+    let mut queue = RelayQueue::new(16);
+    let vote = |poll_id: &str| {
+        QueuedVote::new(
+            poll_id.to_string(),
+            vec![0; 32],
+            Submission::Vote(ProofEnvelope::new(ProofBackend::Dev, Vec::new())),
+        )
+    };
+    queue.votes.push(vote("a"));
+    // not enough votes to hide in yet
+    assert!(queue.take_batch(2, Duration::from_secs(60)).is_empty());
+    queue.votes.push(vote("b"));
+    assert_eq!(queue.take_batch(2, Duration::from_secs(60)).len(), 2);
+    assert!(queue.votes.is_empty());
+    // a single vote is released once it has been held long enough
+    queue.votes.push(vote("c"));
+    assert_eq!(queue.take_batch(2, Duration::ZERO).len(), 1);
+}
+
+#[test]
+fn test_queue_push() {
+    use client::types::RevealPayload;
+    use risc0_types::Ballot;
+    use zk_associated::proof::ProofBackend;
+    let mut queue = RelayQueue::new(2);
+    let vote = |nullifier: u8| {
+        QueuedVote::new(
+            "a".to_string(),
+            vec![nullifier; 32],
+            Submission::Vote(ProofEnvelope::new(ProofBackend::Dev, Vec::new())),
+        )
+    };
+    assert!(queue.push(vote(1)).is_ok());
+    // a replayed proof is forwarded once
+    assert!(matches!(queue.push(vote(1)), Err(Refused::Duplicate)));
+    // the reveal of the same ballot is not a duplicate
+    let reveal = QueuedVote::new(
+        "a".to_string(),
+        vec![1; 32],
+        Submission::Reveal(RevealPayload {
+            nullifier: vec![1; 32],
+            ballot: Ballot::Plurality {
+                choice: "a".to_string(),
+            },
+            salt: vec![0; SALT_LENGTH],
+        }),
+    );
+    assert!(queue.push(reveal).is_ok());
+    assert!(matches!(queue.push(vote(2)), Err(Refused::Full)));
+}
+
+#[test]
+fn test_backoff() {
+    assert_eq!(backoff(1, None), Duration::from_secs(1));
+    assert_eq!(backoff(3, None), Duration::from_secs(4));
+    assert_eq!(backoff(40, None), MAX_BACKOFF);
+    assert_eq!(
+        backoff(3, Some(Duration::from_secs(10))),
+        Duration::from_secs(10)
+    );
+}
+
+#[tokio::test]
+async fn test_verification_slots() {
+    let verification = Verification::new(Arc::new(DevVerifier), 1, 1);
+    let first = Arc::clone(&verification.slots).try_acquire_owned().unwrap();
+    let _second = Arc::clone(&verification.slots).try_acquire_owned().unwrap();
+    // a third vote is turned away instead of piling up
+    assert!(Arc::clone(&verification.slots).try_acquire_owned().is_err());
+    drop(first);
+    assert!(Arc::clone(&verification.slots).try_acquire_owned().is_ok());
+}
@@ -1,6 +1,7 @@
 use methods::VOTING_ELF;
 use risc0_types::CircuitInputs;
 use risc0_zkvm::{default_prover, ExecutorEnv, Receipt};
+use zk_associated::proof::{ProofBackend, ProofEnvelope, ProofError, VoteProver};
 
 pub fn prove_default(inputs: CircuitInputs) -> Receipt {
     let env = ExecutorEnv::builder()
@@ -50,3 +51,46 @@ pub fn prove_groth16(inputs: CircuitInputs) -> Receipt {
         journal,
     )
 }
+
+// risc0 receipts travel as serialized json inside the envelope
+fn envelope(backend: ProofBackend, receipt: &Receipt) -> Result<ProofEnvelope, ProofError> {
+    let proof: Vec<u8> =
+        serde_json::to_vec(receipt).map_err(|e| ProofError::Malformed(e.to_string()))?;
+    Ok(ProofEnvelope::new(backend, proof))
+}
+
+/// Default risc0 prover, emits a STARK receipt
+pub struct Risc0StarkProver;
+impl VoteProver for Risc0StarkProver {
+    fn backend(&self) -> ProofBackend {
+        ProofBackend::Risc0Stark
+    }
+    fn prove(&self, inputs: CircuitInputs) -> Result<ProofEnvelope, ProofError> {
+        envelope(self.backend(), &prove_default(inputs))
+    }
+}
+
+/// Wraps the STARK receipt in a groth16 SNARK, requires docker
+#[cfg(feature = "groth16")]
+pub struct Risc0Groth16Prover;
+#[cfg(feature = "groth16")]
+impl VoteProver for Risc0Groth16Prover {
+    fn backend(&self) -> ProofBackend {
+        ProofBackend::Risc0Groth16
+    }
+    fn prove(&self, inputs: CircuitInputs) -> Result<ProofEnvelope, ProofError> {
+        envelope(self.backend(), &prove_groth16(inputs))
+    }
+}
+
+// the prover selected through the crate features
+pub fn default_vote_prover() -> Box<dyn VoteProver> {
+    #[cfg(feature = "groth16")]
+    {
+        Box::new(Risc0Groth16Prover)
+    }
+    #[cfg(not(feature = "groth16"))]
+    {
+        Box::new(Risc0StarkProver)
+    }
+}
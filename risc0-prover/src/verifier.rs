@@ -1,33 +1,44 @@
-use risc0_types::CircuitOutputs;
-// Verify the receipt, check the journal root_history and add the nullifier to state
-use colored::*;
+// Verify risc0 receipts and decode the journal,
+// root_history and nullifier checks happen against service state
 use methods::VOTING_ID;
+use risc0_types::CircuitOutputs;
 use risc0_zkvm::Receipt;
+use zk_associated::proof::{ProofBackend, ProofEnvelope, ProofError, VoteVerifier};
 
-pub fn verify_vote(
-    receipt: Receipt,
-    root_history: Vec<Vec<u8>>,
-    used_nullifiers: Vec<Vec<u8>>,
-) -> (String, Vec<u8>) {
-    // expect valid proof
-    receipt.verify(VOTING_ID).expect("Failed to verify proof");
-    // decode journal and verify root_history
-    let journal: CircuitOutputs = receipt.journal.decode().expect("Failed to decode journal");
-    if used_nullifiers.contains(&journal.nullifier) {
-        panic!(
-            "{}{}: Duplicate nullifier!",
-            "+++++++++ \n".yellow(),
-            "Rejected".bold().red()
+/// Verifies both STARK and groth16 receipts of the voting circuit
+pub struct Risc0Verifier;
+impl VoteVerifier for Risc0Verifier {
+    fn supports(&self, backend: ProofBackend) -> bool {
+        matches!(
+            backend,
+            ProofBackend::Risc0Stark | ProofBackend::Risc0Groth16
         )
     }
-    for root in journal.root_history {
-        if !root_history.contains(&root) {
-            panic!(
-                "{}{}: Invalid vote!",
-                "+++++++++ \n".yellow(),
-                "Rejected".bold().red()
-            )
+    fn verify(&self, envelope: &ProofEnvelope) -> Result<CircuitOutputs, ProofError> {
+        let receipt: Receipt = self.receipt(envelope)?;
+        receipt
+            .verify(VOTING_ID)
+            .map_err(|e| ProofError::Invalid(e.to_string()))?;
+        decode_journal(&receipt)
+    }
+    fn inspect(&self, envelope: &ProofEnvelope) -> Result<CircuitOutputs, ProofError> {
+        decode_journal(&self.receipt(envelope)?)
+    }
+}
+
+impl Risc0Verifier {
+    fn receipt(&self, envelope: &ProofEnvelope) -> Result<Receipt, ProofError> {
+        envelope.check_version()?;
+        if !self.supports(envelope.backend) {
+            return Err(ProofError::UnsupportedBackend(envelope.backend));
         }
+        serde_json::from_slice(&envelope.proof).map_err(|e| ProofError::Malformed(e.to_string()))
     }
-    (journal.vote, journal.nullifier)
+}
+
+fn decode_journal(receipt: &Receipt) -> Result<CircuitOutputs, ProofError> {
+    receipt
+        .journal
+        .decode()
+        .map_err(|e| ProofError::Malformed(e.to_string()))
 }
@@ -1,17 +1,405 @@
+use crypto::{
+    elgamal::{Ciphertext, Point},
+    hash, CryptoHasherSha256,
+};
 use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, fmt};
 use voting_tree::VotingTree;
 #[derive(Serialize, Deserialize)]
 pub struct CircuitInputs {
     pub root_history: Vec<Vec<u8>>,
     pub snapshot: VotingTree,
     pub nullifier: Vec<u8>,
-    pub vote: String,
+    pub ballot: Ballot,
+    // the ballot is validated against this spec in the circuit,
+    // the service checks that it equals the spec of the poll
+    pub spec: PollSpec,
+    // the weight assigned at registration, 1 unless the poll is weighted
+    pub weight: u64,
+    // counts the ballots of a voter in a revoting poll, 0 otherwise
+    pub sequence: u64,
+    // one encryption randomness per option if the poll is encrypted, empty otherwise
+    pub randomness: Vec<[u8; 32]>,
+    // SALT_LENGTH random bytes in a commit-reveal poll, empty otherwise
+    pub salt: Vec<u8>,
     pub public_key_string: String, // todo: serialize / deserialize pgp public key
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct CircuitOutputs {
+    // the tag derived from the nullifier and the sequence in a revoting poll
     pub nullifier: Vec<u8>,
+    // the tag of the ballot a revote replaces, empty for the first ballot and in other polls
+    #[serde(default)]
+    pub previous: Vec<u8>,
     pub root_history: Vec<Vec<u8>>,
-    pub vote: String,
+    pub ballot: PublicBallot,
+    pub spec: PollSpec,
+    pub weight: u64,
+    pub sequence: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BallotKind {
+    #[default]
+    Plurality,
+    Ranked,
+    Approval,
+    MultiSelect,
+    Quadratic,
+}
+
+/// What a ballot of a poll may contain
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct PollSpec {
+    pub kind: BallotKind,
+    // an empty list accepts any plurality choice
+    pub options: Vec<String>,
+    // the most options a multi-select ballot may pick
+    pub max_selections: Option<u32>,
+    // leaves commit to a weight, see crypto::identity::weighted_leaf
+    pub weighted: bool,
+    // the quadratic credit budget of a voter with weight 1
+    pub credits: Option<u64>,
+    // a later ballot of a voter replaces the earlier one
+    pub revoting: bool,
+    // ballots are published encrypted to this key, see crypto::elgamal
+    pub encryption_key: Option<Point>,
+    // ballots are committed to while the poll is open and revealed afterwards
+    pub commit_reveal: bool,
+}
+
+impl PollSpec {
+    /// what the identity leaf commits to, the ballot can't be bound at registration
+    /// if it may still change, None if the ballot is needed but missing
+    pub fn leaf_commitment(&self, ballot: Option<&Ballot>) -> Option<String> {
+        match self.revoting {
+            true => Some(String::new()),
+            false => ballot.map(Ballot::commitment),
+        }
+    }
+}
+
+// externally tagged, the guest deserializer does not support self-describing formats
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Ballot {
+    Plurality { choice: String },
+    // options in order of preference, unranked options are never counted
+    Ranked { ranking: Vec<String> },
+    // every option the voter supports
+    Approval { approved: Vec<String> },
+    // up to max_selections options
+    MultiSelect { selected: Vec<String> },
+    // n votes for an option cost n^2 credits
+    Quadratic { allocations: Vec<Allocation> },
+}
+
+/// the ballot as published in the journal
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PublicBallot {
+    Plain(Ballot),
+    // per poll option, the encrypted votes the ballot counts for it
+    Encrypted(Vec<Ciphertext>),
+    // see Ballot::reveal_commitment
+    Committed(Vec<u8>),
+}
+
+impl PublicBallot {
+    pub fn plain(&self) -> Option<&Ballot> {
+        match self {
+            PublicBallot::Plain(ballot) => Some(ballot),
+            PublicBallot::Encrypted(_) | PublicBallot::Committed(_) => None,
+        }
+    }
+}
+
+// a fixed length keeps the salt from absorbing a prefix of the ballot
+pub const SALT_LENGTH: usize = 32;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Allocation {
+    pub option: String,
+    pub votes: u64,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum BallotError {
+    WrongKind(BallotKind),
+    Empty,
+    UnknownOption(String),
+    DuplicateOption(String),
+    TooManySelections(u32),
+    InvalidAllocation(String),
+    OverBudget { cost: u64, budget: u64 },
+}
+
+impl fmt::Display for BallotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BallotError::WrongKind(kind) => write!(f, "Poll expects a {:?} ballot", kind),
+            BallotError::Empty => write!(f, "Ballot is empty"),
+            BallotError::UnknownOption(option) => write!(f, "Unknown option {}", option),
+            BallotError::DuplicateOption(option) => write!(f, "Option {} is repeated", option),
+            BallotError::TooManySelections(max) => {
+                write!(f, "Ballot selects more than {} options", max)
+            }
+            BallotError::InvalidAllocation(allocation) => {
+                write!(
+                    f,
+                    "Invalid allocation {}, expected option=votes",
+                    allocation
+                )
+            }
+            BallotError::OverBudget { cost, budget } => {
+                write!(f, "Ballot costs {} credits, the budget is {}", cost, budget)
+            }
+        }
+    }
+}
+
+impl std::error::Error for BallotError {}
+
+impl Ballot {
+    /// builds the ballot of the given kind from the choices in order,
+    /// quadratic choices are option=votes
+    pub fn from_choices(kind: BallotKind, choices: Vec<String>) -> Result<Ballot, BallotError> {
+        match kind {
+            BallotKind::Plurality => match <[String; 1]>::try_from(choices) {
+                Ok([choice]) => Ok(Ballot::Plurality { choice }),
+                Err(_) => Err(BallotError::WrongKind(kind)),
+            },
+            BallotKind::Ranked => Ok(Ballot::Ranked { ranking: choices }),
+            BallotKind::Approval => Ok(Ballot::Approval { approved: choices }),
+            BallotKind::MultiSelect => Ok(Ballot::MultiSelect { selected: choices }),
+            BallotKind::Quadratic => {
+                let mut allocations: Vec<Allocation> = Vec::new();
+                for choice in choices {
+                    let allocation: Option<Allocation> =
+                        choice.split_once('=').and_then(|(option, votes)| {
+                            Some(Allocation {
+                                option: option.to_string(),
+                                votes: votes.parse().ok()?,
+                            })
+                        });
+                    allocations.push(allocation.ok_or(BallotError::InvalidAllocation(choice))?);
+                }
+                Ok(Ballot::Quadratic { allocations })
+            }
+        }
+    }
+
+    pub fn kind(&self) -> BallotKind {
+        match self {
+            Ballot::Plurality { .. } => BallotKind::Plurality,
+            Ballot::Ranked { .. } => BallotKind::Ranked,
+            Ballot::Approval { .. } => BallotKind::Approval,
+            Ballot::MultiSelect { .. } => BallotKind::MultiSelect,
+            Ballot::Quadratic { .. } => BallotKind::Quadratic,
+        }
+    }
+
+    /// the options of the ballot in order
+    pub fn choices(&self) -> Vec<&String> {
+        match self {
+            Ballot::Plurality { choice } => vec![choice],
+            Ballot::Ranked { ranking } => ranking.iter().collect(),
+            Ballot::Approval { approved } => approved.iter().collect(),
+            Ballot::MultiSelect { selected } => selected.iter().collect(),
+            Ballot::Quadratic { allocations } => allocations.iter().map(|a| &a.option).collect(),
+        }
+    }
+
+    /// the choices must be distinct poll options,
+    /// a ranking is therefore a prefix of a permutation of the poll options
+    pub fn validate(&self, spec: &PollSpec) -> Result<(), BallotError> {
+        if self.kind() != spec.kind {
+            return Err(BallotError::WrongKind(spec.kind));
+        }
+        let choices: Vec<&String> = self.choices();
+        if choices.is_empty() || choices.iter().any(|choice| choice.is_empty()) {
+            return Err(BallotError::Empty);
+        }
+        if let (BallotKind::MultiSelect, Some(max)) = (spec.kind, spec.max_selections) {
+            if choices.len() > max as usize {
+                return Err(BallotError::TooManySelections(max));
+            }
+        }
+        let mut seen: HashSet<&String> = HashSet::new();
+        for choice in choices {
+            if !spec.options.is_empty() && !spec.options.contains(choice) {
+                return Err(BallotError::UnknownOption(choice.clone()));
+            }
+            if !seen.insert(choice) {
+                return Err(BallotError::DuplicateOption(choice.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    /// the credits a quadratic ballot spends, saturating
+    pub fn cost(&self) -> u64 {
+        match self {
+            Ballot::Quadratic { allocations } => allocations.iter().fold(0u64, |cost, a| {
+                cost.saturating_add(a.votes.saturating_mul(a.votes))
+            }),
+            _ => 0,
+        }
+    }
+
+    /// the budget of a voter is the credits of the poll times the weight of their leaf
+    pub fn within_budget(&self, spec: &PollSpec, weight: u64) -> Result<(), BallotError> {
+        if spec.kind != BallotKind::Quadratic {
+            return Ok(());
+        }
+        let budget: u64 = spec.credits.unwrap_or(0).saturating_mul(weight);
+        match self.cost() {
+            cost if cost > budget => Err(BallotError::OverBudget { cost, budget }),
+            _ => Ok(()),
+        }
+    }
+
+    /// the votes the ballot counts for each poll option, in the order of the options,
+    /// a ranked ballot counts for its first preference only
+    pub fn option_votes(&self, spec: &PollSpec, weight: u64) -> Vec<u64> {
+        spec.options
+            .iter()
+            .map(|option| match self {
+                // the weight already scaled the credit budget
+                Ballot::Quadratic { allocations } => allocations
+                    .iter()
+                    .filter(|a| &a.option == option)
+                    .map(|a| a.votes)
+                    .sum(),
+                Ballot::Ranked { ranking } if ranking.first() != Some(option) => 0,
+                _ if self.choices().contains(&option) => weight,
+                _ => 0,
+            })
+            .collect()
+    }
+
+    /// H(salt || commitment), published instead of the ballot in a commit-reveal poll
+    pub fn reveal_commitment(&self, salt: &[u8]) -> Vec<u8> {
+        let mut payload: Vec<u8> = b"cypher-poll-reveal".to_vec();
+        payload.extend_from_slice(salt);
+        payload.extend_from_slice(self.commitment().as_bytes());
+        hash(CryptoHasherSha256, &payload)
+    }
+
+    /// what the identity leaf commits to, a plurality ballot commits to the plain choice
+    pub fn commitment(&self) -> String {
+        match self {
+            Ballot::Plurality { choice } => choice.clone(),
+            _ => serde_json::to_string(self).expect("Failed to serialize ballot"),
+        }
+    }
+}
+
+#[test]
+fn test_ranked_validation() {
+    let spec = PollSpec {
+        kind: BallotKind::Ranked,
+        options: vec!["a".to_string(), "b".to_string(), "c".to_string()],
+        ..Default::default()
+    };
+    let ranked = |ranking: &[&str]| Ballot::Ranked {
+        ranking: ranking.iter().map(|o| o.to_string()).collect(),
+    };
+    assert_eq!(ranked(&["b", "a", "c"]).validate(&spec), Ok(()));
+    // a prefix leaves the remaining options unranked
+    assert_eq!(ranked(&["c"]).validate(&spec), Ok(()));
+    assert_eq!(ranked(&[]).validate(&spec), Err(BallotError::Empty));
+    assert_eq!(
+        ranked(&["a", "d"]).validate(&spec),
+        Err(BallotError::UnknownOption("d".to_string()))
+    );
+    assert_eq!(
+        ranked(&["a", "b", "a"]).validate(&spec),
+        Err(BallotError::DuplicateOption("a".to_string()))
+    );
+    assert_eq!(
+        Ballot::Plurality {
+            choice: "a".to_string()
+        }
+        .validate(&spec),
+        Err(BallotError::WrongKind(BallotKind::Ranked))
+    );
+}
+
+#[test]
+fn test_selection_validation() {
+    let options: Vec<String> = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+    let choices =
+        |choices: &[&str]| -> Vec<String> { choices.iter().map(|o| o.to_string()).collect() };
+    let approval = PollSpec {
+        kind: BallotKind::Approval,
+        options: options.clone(),
+        ..Default::default()
+    };
+    let multi_select = PollSpec {
+        kind: BallotKind::MultiSelect,
+        options,
+        max_selections: Some(2),
+        ..Default::default()
+    };
+    let approved = Ballot::from_choices(BallotKind::Approval, choices(&["a", "b", "c"])).unwrap();
+    assert_eq!(approved.validate(&approval), Ok(()));
+    assert_eq!(
+        Ballot::Approval {
+            approved: choices(&["a", "a"])
+        }
+        .validate(&approval),
+        Err(BallotError::DuplicateOption("a".to_string()))
+    );
+    let selected = |picked: &[&str]| Ballot::MultiSelect {
+        selected: choices(picked),
+    };
+    assert_eq!(selected(&["c", "a"]).validate(&multi_select), Ok(()));
+    assert_eq!(
+        selected(&["a", "b", "c"]).validate(&multi_select),
+        Err(BallotError::TooManySelections(2))
+    );
+    assert_eq!(
+        approved.validate(&multi_select),
+        Err(BallotError::WrongKind(BallotKind::MultiSelect))
+    );
+}
+
+#[test]
+fn test_quadratic_budget() {
+    let spec = PollSpec {
+        kind: BallotKind::Quadratic,
+        options: vec!["a".to_string(), "b".to_string()],
+        credits: Some(10),
+        ..Default::default()
+    };
+    let allocate = |choices: &[&str]| {
+        Ballot::from_choices(
+            BallotKind::Quadratic,
+            choices.iter().map(|c| c.to_string()).collect(),
+        )
+    };
+    // 3 votes cost 9 credits, 1 vote costs 1
+    let ballot: Ballot = allocate(&["a=3", "b=1"]).unwrap();
+    assert_eq!(ballot.validate(&spec), Ok(()));
+    assert_eq!(ballot.cost(), 10);
+    assert_eq!(ballot.within_budget(&spec, 1), Ok(()));
+    let expensive: Ballot = allocate(&["a=3", "b=2"]).unwrap();
+    assert_eq!(
+        expensive.within_budget(&spec, 1),
+        Err(BallotError::OverBudget {
+            cost: 13,
+            budget: 10
+        })
+    );
+    // a weight of 2 doubles the budget
+    assert_eq!(expensive.within_budget(&spec, 2), Ok(()));
+    assert_eq!(
+        allocate(&["a"]),
+        Err(BallotError::InvalidAllocation("a".to_string()))
+    );
+    // what an encrypted ballot encrypts per option
+    assert_eq!(ballot.option_votes(&spec, 1), vec![3, 1]);
 }
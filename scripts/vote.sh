@@ -1 +1 @@
-cargo run -p client vote --public-key-path /Users/chef/Desktop/cypher-poll/resources/test/key.asc --vote TimeLord
+cargo run -p client vote --public-key-path resources/test/key.asc --vote TimeLord
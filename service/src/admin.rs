@@ -0,0 +1,191 @@
+// admin api
+// poll creation, phase transitions, allowlist edits and revocations for the operator,
+// every route needs the bearer token from the environment variable named in [admin]
+use crate::{
+    config::PollConfig,
+    state::{AdminError, GitHubUser, PollId, PollState, ServiceState},
+    webhooks::WebhookDispatcher,
+};
+use axum::{
+    body::Bytes,
+    extract::{Path, Request},
+    http::{
+        header::{AUTHORIZATION, CONTENT_TYPE},
+        HeaderMap, StatusCode,
+    },
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post},
+    Extension, Json, Router,
+};
+use client::types::{AllowlistEdit, PhaseChange, PollInfo, PollOverview};
+use crypto::{hash, CryptoHasherSha256};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+// only the hash of the token is kept, requests are compared against it in constant time
+pub struct AdminToken {
+    digest: Vec<u8>,
+}
+
+impl AdminToken {
+    pub fn new(token: &str) -> AdminToken {
+        AdminToken {
+            digest: hash(CryptoHasherSha256, token.as_bytes()),
+        }
+    }
+
+    fn accepts(&self, token: &str) -> bool {
+        hash(CryptoHasherSha256, token.as_bytes())
+            .iter()
+            .zip(&self.digest)
+            .fold(0u8, |difference, (a, b)| difference | (a ^ b))
+            == 0
+    }
+}
+
+pub fn router(token: AdminToken) -> Router {
+    Router::new()
+        .route("/admin/polls", get(list_polls).post(create_poll))
+        .route("/admin/polls/:poll_id", get(poll_overview))
+        .route("/admin/polls/:poll_id/phase", post(change_phase))
+        .route("/admin/polls/:poll_id/allowlist", post(edit_allowlist))
+        .route("/admin/polls/:poll_id/webhooks", get(webhook_deliveries))
+        .route(
+            "/admin/polls/:poll_id/registrations/:username",
+            delete(revoke_registration),
+        )
+        .route_layer(middleware::from_fn(require_token))
+        .layer(Extension(Arc::new(token)))
+}
+
+async fn require_token(
+    Extension(token): Extension<Arc<AdminToken>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let authorized: bool = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|bearer| token.accepts(bearer.trim()));
+    match authorized {
+        true => next.run(request).await,
+        false => {
+            warn!("admin request rejected");
+            (StatusCode::UNAUTHORIZED, "Missing or invalid admin token").into_response()
+        }
+    }
+}
+
+fn rejected(poll_id: &str, error: AdminError) -> Response {
+    info!(poll = %poll_id, reason = error.reason(), "admin request failed");
+    (error.status(), error.to_string()).into_response()
+}
+
+async fn list_polls(Extension(state): Extension<Arc<Mutex<ServiceState>>>) -> Response {
+    let mut polls: Vec<PollOverview> = state
+        .lock()
+        .await
+        .polls()
+        .map(PollState::overview)
+        .collect();
+    polls.sort_by(|a, b| a.info.id.cmp(&b.info.id));
+    Json(polls).into_response()
+}
+
+// a poll definition is a [[polls]] entry of the service config, as TOML or JSON
+async fn create_poll(
+    Extension(state): Extension<Arc<Mutex<ServiceState>>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let is_toml: bool = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.contains("toml"));
+    let config: Result<PollConfig, String> = match is_toml {
+        true => std::str::from_utf8(&body)
+            .map_err(|e| e.to_string())
+            .and_then(|raw| toml::from_str(raw).map_err(|e| e.to_string())),
+        false => serde_json::from_slice(&body).map_err(|e| e.to_string()),
+    };
+    let result: Result<PollInfo, AdminError> = match config {
+        Ok(config) => state.lock().await.create_poll(config),
+        Err(e) => Err(AdminError::InvalidDefinition(e)),
+    };
+    match result {
+        Ok(info) => (StatusCode::CREATED, Json(info)).into_response(),
+        Err(error) => {
+            info!(reason = error.reason(), "poll creation failed");
+            (error.status(), error.to_string()).into_response()
+        }
+    }
+}
+
+async fn poll_overview(
+    Path(poll_id): Path<PollId>,
+    Extension(state): Extension<Arc<Mutex<ServiceState>>>,
+) -> Response {
+    match state.lock().await.poll(&poll_id) {
+        Some(poll) => Json(poll.overview()).into_response(),
+        None => rejected(&poll_id, AdminError::UnknownPoll),
+    }
+}
+
+async fn change_phase(
+    Path(poll_id): Path<PollId>,
+    Extension(state): Extension<Arc<Mutex<ServiceState>>>,
+    Json(change): Json<PhaseChange>,
+) -> Response {
+    let result: Result<(), AdminError> = state.lock().await.transition(&poll_id, change.phase);
+    match result {
+        Ok(()) => StatusCode::OK.into_response(),
+        Err(error) => rejected(&poll_id, error),
+    }
+}
+
+async fn edit_allowlist(
+    Path(poll_id): Path<PollId>,
+    Extension(state): Extension<Arc<Mutex<ServiceState>>>,
+    Json(edit): Json<AllowlistEdit>,
+) -> Response {
+    let mut state = state.lock().await;
+    match state.edit_allowlist(&poll_id, edit) {
+        Ok(()) => Json(state.poll(&poll_id).map(PollState::overview)).into_response(),
+        Err(error) => rejected(&poll_id, error),
+    }
+}
+
+// the delivery log since the service started
+async fn webhook_deliveries(
+    Path(poll_id): Path<PollId>,
+    Extension(state): Extension<Arc<Mutex<ServiceState>>>,
+    Extension(dispatcher): Extension<Arc<WebhookDispatcher>>,
+) -> Response {
+    if state.lock().await.poll(&poll_id).is_none() {
+        return rejected(&poll_id, AdminError::UnknownPoll);
+    }
+    Json(dispatcher.deliveries(&poll_id)).into_response()
+}
+
+async fn revoke_registration(
+    Path((poll_id, username)): Path<(PollId, GitHubUser)>,
+    Extension(state): Extension<Arc<Mutex<ServiceState>>>,
+) -> Response {
+    let result: Result<(), AdminError> = state.lock().await.revoke(&poll_id, &username);
+    match result {
+        Ok(()) => StatusCode::OK.into_response(),
+        Err(error) => rejected(&poll_id, error),
+    }
+}
+
+#[test]
+fn test_admin_token() {
+    let token = AdminToken::new("0123456789abcdef");
+    assert!(token.accepts("0123456789abcdef"));
+    assert!(!token.accepts("0123456789abcdeF"));
+    assert!(!token.accepts(""));
+}
@@ -0,0 +1,177 @@
+// admission control for the expensive routes
+// /register costs an outbound GitHub call and /vote a full proof verification, so both sit
+// behind a per-ip and a global token bucket, verification is bounded by the jobs::VerificationPool
+use crate::{config::Limits, metrics::metrics};
+use axum::{
+    extract::{ConnectInfo, Request},
+    http::{header::RETRY_AFTER, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension,
+};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+use tracing::debug;
+
+// idle buckets are dropped once this many addresses are tracked
+const MAX_TRACKED_IPS: usize = 100_000;
+
+pub struct TokenBucket {
+    capacity: f64,
+    per_sec: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(per_sec: f64, burst: u32, now: Instant) -> TokenBucket {
+        TokenBucket {
+            capacity: burst as f64,
+            per_sec,
+            tokens: burst as f64,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed: f64 = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.per_sec).min(self.capacity);
+        self.updated = now;
+    }
+
+    pub fn try_take(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn is_full(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        self.tokens >= self.capacity
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Rejection {
+    PerIp,
+    Global,
+    Queue,
+}
+
+impl Rejection {
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Rejection::PerIp => "per_ip",
+            Rejection::Global => "global",
+            Rejection::Queue => "queue",
+        }
+    }
+}
+
+impl IntoResponse for Rejection {
+    fn into_response(self) -> Response {
+        metrics().admission_rejections(self.reason());
+        let (status, message) = match self {
+            Rejection::PerIp => (StatusCode::TOO_MANY_REQUESTS, "Too many requests"),
+            Rejection::Global => (StatusCode::SERVICE_UNAVAILABLE, "Service is busy"),
+            Rejection::Queue => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Verification queue is full",
+            ),
+        };
+        (status, [(RETRY_AFTER, "1")], message).into_response()
+    }
+}
+
+pub struct RateLimiter {
+    limits: Limits,
+    global: Mutex<TokenBucket>,
+    per_ip: Mutex<HashMap<IpAddr, TokenBucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: Limits) -> RateLimiter {
+        RateLimiter {
+            global: Mutex::new(TokenBucket::new(
+                limits.global_per_sec,
+                limits.global_burst,
+                Instant::now(),
+            )),
+            per_ip: Mutex::new(HashMap::new()),
+            limits,
+        }
+    }
+
+    pub fn check(&self, ip: IpAddr, now: Instant) -> Result<(), Rejection> {
+        if !self.limits.exempt_ips.contains(&ip) {
+            let mut per_ip = self.per_ip.lock().unwrap();
+            if per_ip.len() >= MAX_TRACKED_IPS {
+                per_ip.retain(|_, bucket| !bucket.is_full(now));
+            }
+            let bucket = per_ip.entry(ip).or_insert_with(|| {
+                TokenBucket::new(self.limits.per_ip_per_sec, self.limits.per_ip_burst, now)
+            });
+            if !bucket.try_take(now) {
+                return Err(Rejection::PerIp);
+            }
+        }
+        if !self.global.lock().unwrap().try_take(now) {
+            return Err(Rejection::Global);
+        }
+        Ok(())
+    }
+}
+
+// route layer for /register and /vote
+pub async fn rate_limit(
+    Extension(limiter): Extension<Arc<RateLimiter>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    match limiter.check(peer.ip(), Instant::now()) {
+        Ok(()) => next.run(request).await,
+        Err(rejection) => {
+            debug!(client_ip = %peer.ip(), reason = rejection.reason(), "request rate limited");
+            rejection.into_response()
+        }
+    }
+}
+
+#[test]
+fn test_rate_limits() {
+    use std::time::Duration;
+    let limiter = RateLimiter::new(Limits {
+        per_ip_per_sec: 1.0,
+        per_ip_burst: 2,
+        global_per_sec: 10.0,
+        global_burst: 3,
+        exempt_ips: vec!["10.0.0.1".parse().unwrap()],
+        ..Limits::default()
+    });
+    let now = Instant::now();
+    let (a, b): (IpAddr, IpAddr) = ("127.0.0.1".parse().unwrap(), "127.0.0.2".parse().unwrap());
+    assert_eq!(limiter.check(a, now), Ok(()));
+    assert_eq!(limiter.check(a, now), Ok(()));
+    assert_eq!(limiter.check(a, now), Err(Rejection::PerIp));
+    // the global bucket is shared by all addresses
+    assert_eq!(limiter.check(b, now), Ok(()));
+    assert_eq!(limiter.check(b, now), Err(Rejection::Global));
+    // one token per second per ip, ten per second globally
+    let later = now + Duration::from_secs(1);
+    assert_eq!(limiter.check(a, later), Ok(()));
+    assert_eq!(limiter.check(a, later), Err(Rejection::PerIp));
+    let exempt: IpAddr = "10.0.0.1".parse().unwrap();
+    // exempt addresses still count against the global limit
+    assert_eq!(limiter.check(exempt, later), Ok(()));
+    assert_eq!(limiter.check(exempt, later), Ok(()));
+    assert_eq!(limiter.check(exempt, later), Err(Rejection::Global));
+}
@@ -0,0 +1,991 @@
+// service configuration
+// a TOML file (see service.example.toml) that can be overridden through the command line,
+// everything is validated at startup so that a bad config never surfaces as a panic in a route
+use crate::logging::LogFormat;
+use clap::Parser;
+use crypto::{
+    elgamal::{check_point, Point},
+    from_hex,
+};
+use risc0_types::{BallotKind, PollSpec};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    env, fmt, fs,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+};
+use tracing_subscriber::EnvFilter;
+
+pub const DEFAULT_BIND: &str = "127.0.0.1:8080";
+pub const DEFAULT_POLL: &str = "default";
+pub const DEFAULT_TREE_DEPTH: usize = 5;
+// a risc0 receipt serialized as json is several MB
+pub const DEFAULT_BODY_LIMIT: usize = 10_000_000;
+pub const MAX_TREE_DEPTH: usize = 32;
+pub const DEFAULT_LOG_LEVEL: &str = "info";
+
+#[derive(Parser)]
+pub struct Args {
+    /// service config file
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+    /// address to listen on, overrides the config file
+    #[arg(long)]
+    pub bind: Option<SocketAddr>,
+    /// directory for persisted poll state, overrides the config file
+    #[arg(long)]
+    pub storage_path: Option<PathBuf>,
+    /// log filter in RUST_LOG syntax, e.g. "info" or "warn,service=debug", overrides the config file
+    #[arg(long)]
+    pub log_level: Option<String>,
+    /// pretty or json, overrides the config file
+    #[arg(long)]
+    pub log_format: Option<LogFormat>,
+    /// accept unsound dev-mode proofs, never use this for a real poll
+    #[arg(long)]
+    pub insecure_dev_proofs: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServiceConfig {
+    #[serde(default = "default_bind")]
+    pub bind: SocketAddr,
+    #[serde(default)]
+    pub limits: Limits,
+    pub storage_path: Option<PathBuf>,
+    #[serde(default = "default_log_level")]
+    pub log_level: String,
+    #[serde(default)]
+    pub log_format: LogFormat,
+    #[serde(default)]
+    pub github: GitHubConfig,
+    #[serde(default = "default_polls")]
+    pub polls: Vec<PollConfig>,
+    // the admin api is only served with this table
+    pub admin: Option<AdminConfig>,
+    #[serde(skip)]
+    pub insecure_dev_proofs: bool,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    pub body_limit_bytes: usize,
+    // token buckets for /register and /vote, refilled continuously
+    pub per_ip_per_sec: f64,
+    pub per_ip_burst: u32,
+    pub global_per_sec: f64,
+    pub global_burst: u32,
+    // verification worker threads, further proofs wait in a queue of this length
+    pub verification_concurrency: usize,
+    pub verification_queue: usize,
+    // not rate limited per ip, e.g. a relayer that forwards the votes of many clients
+    pub exempt_ips: Vec<IpAddr>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GitHubConfig {
+    #[serde(default = "default_github_api")]
+    pub api_url: String,
+    // name of the environment variable that holds the api token, the token never goes in the file
+    #[serde(default = "default_github_token_env")]
+    pub token_env: String,
+    #[serde(default = "default_user_agent")]
+    pub user_agent: String,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AdminConfig {
+    // name of the environment variable that holds the bearer token of the operator
+    #[serde(default = "default_admin_token_env")]
+    pub token_env: String,
+}
+
+// polls created through the admin api are persisted with their config
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct PollConfig {
+    pub id: String,
+    #[serde(default)]
+    pub title: String,
+    #[serde(default = "default_tree_depth")]
+    pub tree_depth: usize,
+    // an empty list accepts any vote
+    #[serde(default)]
+    pub options: Vec<String>,
+    // plurality, ranked, approval, multi_select or quadratic,
+    // ranked polls are tallied by instant runoff
+    #[serde(default)]
+    pub ballot: BallotKind,
+    // the most options a multi_select ballot may pick
+    pub max_selections: Option<u32>,
+    // the credit budget of a quadratic ballot, multiplied by the voter weight
+    pub credits: Option<u64>,
+    // voters may replace their ballot until the poll closes, only the latest one is counted
+    #[serde(default)]
+    pub revoting: bool,
+    // votes are neither logged nor tallied publicly before the poll closes
+    #[serde(default)]
+    pub sealed: bool,
+    // voters only register until the poll is opened through the admin api,
+    // registrations may be revoked until then
+    #[serde(default)]
+    pub registration_phase: bool,
+    // unix timestamp (seconds) of the closing transition
+    pub closes_at: Option<u64>,
+    // votes only commit to the ballot, at closes_at the poll moves to a reveal phase
+    // until reveal_closes_at in which the ballots are opened
+    #[serde(default)]
+    pub commit_reveal: bool,
+    pub reveal_closes_at: Option<u64>,
+    // who may register besides owning a GPG key on GitHub, anyone if unset
+    pub eligibility: Option<EligibilityConfig>,
+    // every vote counts once if unset
+    pub weights: Option<WeightsConfig>,
+    // ballots are encrypted to the trustees, only the final tally is ever decrypted
+    pub encryption: Option<EncryptionConfig>,
+    // how the outcome is decided once the poll closes
+    #[serde(default)]
+    pub rules: OutcomeRules,
+    // notified of phase transitions and published results
+    #[serde(default)]
+    pub webhooks: Vec<WebhookConfig>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookConfig {
+    pub url: String,
+    // name of the environment variable that holds the HMAC secret
+    pub secret_env: String,
+    #[serde(default = "default_webhook_events")]
+    pub events: Vec<WebhookEvent>,
+    // attempts of a delivery, the backoff doubles after every failed one
+    #[serde(default = "default_webhook_attempts")]
+    pub max_attempts: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEvent {
+    Phase,
+    Results,
+}
+
+// without rules the option with the most votes wins, a tie has no winner
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutcomeRules {
+    // fraction of the registered voters whose ballot must be counted
+    pub min_turnout: Option<f64>,
+    pub min_ballots: Option<u64>,
+    // fraction of the counted votes the winner needs at least, e.g. 0.6667
+    pub supermajority: Option<f64>,
+    pub tie_break: TieBreak,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TieBreak {
+    #[default]
+    None,
+    // the tied option listed first in the poll options
+    OptionOrder,
+    // drawn by lot from the accepted nullifiers, the last voters can still bias it by
+    // choosing whether to vote
+    Lot,
+}
+
+// written by `trustee keygen`, points are hex encoded
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct EncryptionConfig {
+    pub public_key: String,
+    // decryption shares needed for the tally
+    pub threshold: usize,
+    // of trustee 1, 2, ..., n in order
+    pub verification_keys: Vec<String>,
+}
+
+// the weight of a voter is assigned at registration: an entry in the table,
+// otherwise the first matching rule, otherwise the default
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct WeightsConfig {
+    #[serde(default = "default_weight")]
+    pub default: u64,
+    #[serde(default)]
+    pub users: HashMap<String, u64>,
+    // a TOML file of username = weight, merged into users at startup
+    pub table: Option<PathBuf>,
+    #[serde(default)]
+    pub rules: Vec<WeightRule>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct WeightRule {
+    pub weight: u64,
+    pub policy: EligibilityConfig,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum EligibilityConfig {
+    OrgMember { org: String },
+    TeamMember { org: String, team: String },
+    // repo is owner/name
+    Contributor { repo: String },
+    MinAccountAge { days: u64 },
+    Allow { users: Vec<String> },
+    Deny { users: Vec<String> },
+    All { policies: Vec<EligibilityConfig> },
+    Any { policies: Vec<EligibilityConfig> },
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, e) => write!(f, "Failed to read {}: {}", path.display(), e),
+            ConfigError::Parse(path, e) => write!(f, "Invalid config {}: {}", path.display(), e),
+            ConfigError::Invalid(reason) => write!(f, "Invalid config: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+fn default_bind() -> SocketAddr {
+    DEFAULT_BIND.parse().unwrap()
+}
+fn default_log_level() -> String {
+    DEFAULT_LOG_LEVEL.to_string()
+}
+fn default_github_api() -> String {
+    "https://api.github.com".to_string()
+}
+fn default_github_token_env() -> String {
+    "GITHUB_TOKEN".to_string()
+}
+fn default_user_agent() -> String {
+    "Acropolis V2 (jonaspauli089@gmail.com)".to_string()
+}
+fn default_admin_token_env() -> String {
+    "CYPHER_POLL_ADMIN_TOKEN".to_string()
+}
+fn default_webhook_events() -> Vec<WebhookEvent> {
+    vec![WebhookEvent::Phase, WebhookEvent::Results]
+}
+fn default_webhook_attempts() -> u32 {
+    5
+}
+fn default_tree_depth() -> usize {
+    DEFAULT_TREE_DEPTH
+}
+fn default_weight() -> u64 {
+    1
+}
+fn default_polls() -> Vec<PollConfig> {
+    vec![PollConfig::default()]
+}
+
+// the same as an entry that only sets the id, which defaults to the default poll
+impl Default for PollConfig {
+    fn default() -> Self {
+        PollConfig {
+            id: DEFAULT_POLL.to_string(),
+            title: String::new(),
+            tree_depth: default_tree_depth(),
+            options: Vec::new(),
+            ballot: BallotKind::default(),
+            max_selections: None,
+            credits: None,
+            revoting: false,
+            sealed: false,
+            registration_phase: false,
+            closes_at: None,
+            commit_reveal: false,
+            reveal_closes_at: None,
+            eligibility: None,
+            weights: None,
+            encryption: None,
+            rules: OutcomeRules::default(),
+            webhooks: Vec::new(),
+        }
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            body_limit_bytes: DEFAULT_BODY_LIMIT,
+            per_ip_per_sec: 1.0,
+            per_ip_burst: 10,
+            global_per_sec: 50.0,
+            global_burst: 100,
+            verification_concurrency: 2,
+            verification_queue: 16,
+            exempt_ips: Vec::new(),
+        }
+    }
+}
+
+impl Default for GitHubConfig {
+    fn default() -> Self {
+        GitHubConfig {
+            api_url: default_github_api(),
+            token_env: default_github_token_env(),
+            user_agent: default_user_agent(),
+        }
+    }
+}
+
+impl Limits {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.body_limit_bytes == 0 {
+            return Err(ConfigError::Invalid(
+                "limits.body_limit_bytes must be positive".to_string(),
+            ));
+        }
+        // NaN fails both comparisons
+        if !(self.per_ip_per_sec > 0.0 && self.global_per_sec > 0.0) {
+            return Err(ConfigError::Invalid(
+                "limits.per_ip_per_sec and limits.global_per_sec must be positive".to_string(),
+            ));
+        }
+        if self.per_ip_burst == 0 || self.global_burst == 0 {
+            return Err(ConfigError::Invalid(
+                "limits.per_ip_burst and limits.global_burst must be positive".to_string(),
+            ));
+        }
+        if self.verification_concurrency == 0 {
+            return Err(ConfigError::Invalid(
+                "limits.verification_concurrency must be positive".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl GitHubConfig {
+    pub fn token(&self) -> Result<String, ConfigError> {
+        match env::var(&self.token_env) {
+            Ok(token) if !token.trim().is_empty() => Ok(token),
+            _ => Err(ConfigError::Invalid(format!(
+                "GitHub token environment variable {} is not set",
+                self.token_env
+            ))),
+        }
+    }
+}
+
+impl AdminConfig {
+    pub fn token(&self) -> Result<String, ConfigError> {
+        match env::var(&self.token_env) {
+            Ok(token) if token.trim().len() >= 16 => Ok(token.trim().to_string()),
+            _ => Err(ConfigError::Invalid(format!(
+                "admin token environment variable {} must hold at least 16 characters",
+                self.token_env
+            ))),
+        }
+    }
+}
+
+impl ServiceConfig {
+    /// reads the config file (if any) and applies the command line overrides
+    pub fn load(args: &Args) -> Result<ServiceConfig, ConfigError> {
+        let mut config: ServiceConfig = match &args.config {
+            Some(path) => {
+                let raw: String =
+                    fs::read_to_string(path).map_err(|e| ConfigError::Io(path.clone(), e))?;
+                toml::from_str(&raw).map_err(|e| ConfigError::Parse(path.clone(), e))?
+            }
+            None => toml::from_str("").expect("Empty config must be valid"),
+        };
+        if let Some(bind) = args.bind {
+            config.bind = bind;
+        }
+        if let Some(storage_path) = &args.storage_path {
+            config.storage_path = Some(storage_path.clone());
+        }
+        if let Some(log_level) = &args.log_level {
+            config.log_level = log_level.clone();
+        }
+        if let Some(log_format) = args.log_format {
+            config.log_format = log_format;
+        }
+        config.insecure_dev_proofs = args.insecure_dev_proofs;
+        for poll in &mut config.polls {
+            if let Some(weights) = &mut poll.weights {
+                weights.load_table()?;
+            }
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if let Err(e) = EnvFilter::try_new(&self.log_level) {
+            return Err(ConfigError::Invalid(format!(
+                "log_level {}: {}",
+                self.log_level, e
+            )));
+        }
+        self.limits.validate()?;
+        if !self.github.api_url.starts_with("http") {
+            return Err(ConfigError::Invalid(format!(
+                "github.api_url {} is not a http(s) url",
+                self.github.api_url
+            )));
+        }
+        self.github.token()?;
+        if let Some(admin) = &self.admin {
+            admin.token()?;
+        }
+        // without the admin api there would be no way to add a poll
+        if self.polls.is_empty() && self.admin.is_none() {
+            return Err(ConfigError::Invalid("no polls configured".to_string()));
+        }
+        let mut ids: HashSet<&str> = HashSet::new();
+        for poll in &self.polls {
+            poll.validate()?;
+            if !ids.insert(&poll.id) {
+                return Err(ConfigError::Invalid(format!(
+                    "duplicate poll id {}",
+                    poll.id
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl PollConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        // poll ids are part of the route
+        if self.id.is_empty()
+            || !self
+                .id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(ConfigError::Invalid(format!(
+                "poll id '{}' must be non-empty and only contain [a-zA-Z0-9_-]",
+                self.id
+            )));
+        }
+        if self.sealed && self.closes_at.is_none() {
+            return Err(ConfigError::Invalid(format!(
+                "poll {}: a sealed poll needs closes_at, its tally is never published otherwise",
+                self.id
+            )));
+        }
+        if self.tree_depth == 0 || self.tree_depth > MAX_TREE_DEPTH {
+            return Err(ConfigError::Invalid(format!(
+                "poll {}: tree_depth must be between 1 and {}",
+                self.id, MAX_TREE_DEPTH
+            )));
+        }
+        let mut options: HashSet<&str> = HashSet::new();
+        for option in &self.options {
+            if option.is_empty() || !options.insert(option) {
+                return Err(ConfigError::Invalid(format!(
+                    "poll {}: options must be non-empty and unique",
+                    self.id
+                )));
+            }
+        }
+        if self.ballot != BallotKind::Plurality && self.options.len() < 2 {
+            return Err(ConfigError::Invalid(format!(
+                "poll {}: a {:?} poll needs at least two options",
+                self.id, self.ballot
+            )));
+        }
+        if let Some(eligibility) = &self.eligibility {
+            eligibility.validate().map_err(|e| {
+                ConfigError::Invalid(format!("poll {}: eligibility {}", self.id, e))
+            })?;
+        }
+        if let Some(weights) = &self.weights {
+            weights
+                .validate()
+                .map_err(|e| ConfigError::Invalid(format!("poll {}: weights {}", self.id, e)))?;
+            // the weight is public next to the ballot, a plain ballot would be linked to it
+            if self.encryption.is_none() {
+                return Err(ConfigError::Invalid(format!(
+                    "poll {}: a weighted poll needs encryption",
+                    self.id
+                )));
+            }
+        }
+        if let Some(encryption) = &self.encryption {
+            encryption
+                .validate()
+                .map_err(|e| ConfigError::Invalid(format!("poll {}: encryption {}", self.id, e)))?;
+            // the runoff needs every ranking, a sum of encrypted votes can't be eliminated
+            if self.ballot == BallotKind::Ranked || self.options.is_empty() {
+                return Err(ConfigError::Invalid(format!(
+                    "poll {}: encryption needs a fixed option list and a ballot other than ranked",
+                    self.id
+                )));
+            }
+        }
+        self.rules
+            .validate()
+            .map_err(|e| ConfigError::Invalid(format!("poll {}: rules {}", self.id, e)))?;
+        for webhook in &self.webhooks {
+            webhook
+                .validate()
+                .map_err(|e| ConfigError::Invalid(format!("poll {}: webhook {}", self.id, e)))?;
+        }
+        match (self.commit_reveal, self.closes_at, self.reveal_closes_at) {
+            (true, Some(closes_at), Some(reveal_closes_at)) if reveal_closes_at > closes_at => {}
+            (true, _, _) => {
+                return Err(ConfigError::Invalid(format!(
+                    "poll {}: a commit_reveal poll needs closes_at and a later reveal_closes_at",
+                    self.id
+                )))
+            }
+            (false, _, Some(_)) => {
+                return Err(ConfigError::Invalid(format!(
+                    "poll {}: reveal_closes_at only applies to commit_reveal polls",
+                    self.id
+                )))
+            }
+            (false, _, None) => {}
+        }
+        if self.commit_reveal && self.encryption.is_some() {
+            return Err(ConfigError::Invalid(format!(
+                "poll {}: a poll is either encrypted or commit_reveal",
+                self.id
+            )));
+        }
+        match (self.ballot, self.max_selections) {
+            (BallotKind::MultiSelect, Some(max))
+                if max >= 1 && max as usize <= self.options.len() => {}
+            (BallotKind::MultiSelect, _) => {
+                return Err(ConfigError::Invalid(format!(
+                    "poll {}: a multi_select poll needs max_selections between 1 and the number of options",
+                    self.id
+                )))
+            }
+            (_, Some(_)) => {
+                return Err(ConfigError::Invalid(format!(
+                    "poll {}: max_selections only applies to multi_select polls",
+                    self.id
+                )))
+            }
+            (_, None) => {}
+        }
+        match (self.ballot, self.credits) {
+            (BallotKind::Quadratic, Some(credits)) if credits > 0 => {}
+            (BallotKind::Quadratic, _) => {
+                return Err(ConfigError::Invalid(format!(
+                    "poll {}: a quadratic poll needs positive credits",
+                    self.id
+                )))
+            }
+            (_, Some(_)) => {
+                return Err(ConfigError::Invalid(format!(
+                    "poll {}: credits only apply to quadratic polls",
+                    self.id
+                )))
+            }
+            (_, None) => {}
+        }
+        Ok(())
+    }
+
+    /// what the ballots of this poll are validated against, in the circuit and in the service
+    pub fn spec(&self) -> PollSpec {
+        PollSpec {
+            kind: self.ballot,
+            options: self.options.clone(),
+            max_selections: self.max_selections,
+            weighted: self.weights.is_some(),
+            credits: self.credits,
+            revoting: self.revoting,
+            encryption_key: self.encryption.as_ref().map(EncryptionConfig::public_key),
+            commit_reveal: self.commit_reveal,
+        }
+    }
+
+    /// the maximum amount of identities the tree of this poll can store
+    pub fn capacity(&self) -> usize {
+        1usize << self.tree_depth
+    }
+}
+
+impl EligibilityConfig {
+    fn validate(&self) -> Result<(), String> {
+        // names become part of GitHub api urls
+        let is_name = |name: &str| {
+            !name.is_empty()
+                && name.chars().any(|c| c != '.')
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+        };
+        match self {
+            EligibilityConfig::OrgMember { org } if !is_name(org) => {
+                Err(format!("org '{}' is not a valid name", org))
+            }
+            EligibilityConfig::TeamMember { org, team } if !is_name(org) || !is_name(team) => {
+                Err(format!("team '{}/{}' is not a valid name", org, team))
+            }
+            EligibilityConfig::Contributor { repo }
+                if !repo
+                    .split_once('/')
+                    .is_some_and(|(owner, name)| is_name(owner) && is_name(name)) =>
+            {
+                Err(format!("repo '{}' must be owner/name", repo))
+            }
+            EligibilityConfig::All { policies } | EligibilityConfig::Any { policies } => {
+                if policies.is_empty() {
+                    return Err("all and any need at least one policy".to_string());
+                }
+                policies.iter().try_for_each(EligibilityConfig::validate)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+fn point(hex: &str) -> Option<Point> {
+    let point: Point = from_hex(hex)?.try_into().ok()?;
+    check_point(&point).ok().map(|_| point)
+}
+
+impl EncryptionConfig {
+    fn validate(&self) -> Result<(), String> {
+        if point(&self.public_key).is_none()
+            || self
+                .verification_keys
+                .iter()
+                .any(|key| point(key).is_none())
+        {
+            return Err("keys must be hex encoded ristretto points".to_string());
+        }
+        if self.threshold == 0 || self.threshold > self.verification_keys.len() {
+            return Err("threshold must be between 1 and the number of trustees".to_string());
+        }
+        Ok(())
+    }
+
+    // only called on a validated config
+    pub fn public_key(&self) -> Point {
+        point(&self.public_key).expect("Invalid encryption key")
+    }
+
+    /// the verification key of trustee `index`, counted from 1
+    pub fn verification_key(&self, index: u32) -> Option<Point> {
+        let key: &String = self
+            .verification_keys
+            .get((index as usize).checked_sub(1)?)?;
+        point(key)
+    }
+}
+
+impl WebhookConfig {
+    pub fn secret(&self) -> Result<String, String> {
+        match env::var(&self.secret_env) {
+            Ok(secret) if !secret.trim().is_empty() => Ok(secret),
+            _ => Err(format!(
+                "secret environment variable {} is not set",
+                self.secret_env
+            )),
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if !self.url.starts_with("http") {
+            return Err(format!("{} is not a http(s) url", self.url));
+        }
+        if self.events.is_empty() || self.max_attempts == 0 || self.max_attempts > 10 {
+            return Err(format!(
+                "{} needs at least one event and between 1 and 10 attempts",
+                self.url
+            ));
+        }
+        self.secret().map(|_| ())
+    }
+}
+
+impl OutcomeRules {
+    fn validate(&self) -> Result<(), String> {
+        // NaN fails the comparison
+        let fraction = |f: &f64| *f > 0.0 && *f <= 1.0;
+        if !self
+            .min_turnout
+            .iter()
+            .chain(&self.supermajority)
+            .all(fraction)
+        {
+            return Err("min_turnout and supermajority must be fractions in (0, 1]".to_string());
+        }
+        Ok(())
+    }
+}
+
+impl WeightsConfig {
+    // entries in the config file take precedence over the table
+    fn load_table(&mut self) -> Result<(), ConfigError> {
+        let Some(path) = &self.table else {
+            return Ok(());
+        };
+        let raw: String = fs::read_to_string(path).map_err(|e| ConfigError::Io(path.clone(), e))?;
+        let table: HashMap<String, u64> =
+            toml::from_str(&raw).map_err(|e| ConfigError::Parse(path.clone(), e))?;
+        for (user, weight) in table {
+            self.users.entry(user).or_insert(weight);
+        }
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        // a weight of zero would register voters whose votes are never counted
+        if self.default == 0
+            || self.users.values().any(|weight| *weight == 0)
+            || self.rules.iter().any(|rule| rule.weight == 0)
+        {
+            return Err("must be positive".to_string());
+        }
+        self.rules
+            .iter()
+            .try_for_each(|rule| rule.policy.validate())
+    }
+}
+
+#[test]
+fn test_config_validation() {
+    env::set_var("CYPHER_POLL_TEST_TOKEN", "token");
+    // the default poll is a valid one
+    let minimal: PollConfig = toml::from_str("id = \"default\"").unwrap();
+    assert_eq!(minimal.tree_depth, PollConfig::default().tree_depth);
+    assert!(PollConfig::default().validate().is_ok());
+    let config: ServiceConfig = toml::from_str(
+        r#"
+        bind = "0.0.0.0:9000"
+        log_level = "warn,service=debug"
+        log_format = "json"
+        [github]
+        token_env = "CYPHER_POLL_TEST_TOKEN"
+        [[polls]]
+        id = "board-2024"
+        tree_depth = 10
+        options = ["alice", "bob"]
+        ballot = "ranked"
+        [polls.eligibility]
+        type = "any"
+        policies = [
+            { type = "team_member", org = "acme", team = "maintainers" },
+            { type = "allow", users = ["octocat"] },
+        ]
+        "#,
+    )
+    .unwrap();
+    assert!(config.validate().is_ok());
+    assert_eq!(config.polls[0].capacity(), 1024);
+    assert_eq!(config.limits.body_limit_bytes, DEFAULT_BODY_LIMIT);
+    assert_eq!(config.log_format, LogFormat::Json);
+    assert_eq!(config.polls[0].spec().kind, BallotKind::Ranked);
+    assert!(matches!(
+        config.polls[0].eligibility,
+        Some(EligibilityConfig::Any { .. })
+    ));
+
+    let invalid: ServiceConfig = toml::from_str(
+        r#"
+        [github]
+        token_env = "CYPHER_POLL_TEST_TOKEN"
+        [[polls]]
+        id = "a"
+        [[polls]]
+        id = "a"
+        tree_depth = 40
+        "#,
+    )
+    .unwrap();
+    assert!(invalid.validate().is_err());
+    // unknown keys are rejected instead of silently ignored
+    assert!(toml::from_str::<ServiceConfig>("bnid = \"127.0.0.1:1\"").is_err());
+}
+
+// trustees with one placeholder key
+#[cfg(test)]
+fn test_encryption(threshold: usize) -> EncryptionConfig {
+    let key: String =
+        "e2f2ae0a6abc4e71a884a961c500515f58e30b6aa582dd8db6a65945e08d2d76".to_string();
+    EncryptionConfig {
+        public_key: key.clone(),
+        threshold,
+        verification_keys: vec![key.clone(), key],
+    }
+}
+
+#[test]
+fn test_contributor_validation() {
+    let contributors = |repo: &str| PollConfig {
+        id: "contributors".to_string(),
+        eligibility: Some(EligibilityConfig::Contributor {
+            repo: repo.to_string(),
+        }),
+        ..Default::default()
+    };
+    assert!(contributors("acme/widgets").validate().is_ok());
+    assert!(contributors("acme/../widgets").validate().is_err());
+}
+
+#[test]
+fn test_weighted_needs_encryption() {
+    let weighted: PollConfig = toml::from_str(
+        r#"
+        id = "governance"
+        options = ["yes", "no"]
+        [weights]
+        users = { octocat = 5 }
+        rules = [{ weight = 3, policy = { type = "org_member", org = "acme" } }]
+        "#,
+    )
+    .unwrap();
+    assert_eq!(weighted.weights.as_ref().unwrap().default, 1);
+    assert!(weighted.validate().is_err());
+    let encrypted = PollConfig {
+        encryption: Some(test_encryption(1)),
+        ..weighted
+    };
+    assert!(encrypted.validate().is_ok());
+    assert!(encrypted.spec().weighted);
+}
+
+#[test]
+fn test_encryption_validation() {
+    let encrypted = |ballot: BallotKind, threshold: usize| PollConfig {
+        id: "secret".to_string(),
+        options: vec!["a".to_string(), "b".to_string()],
+        ballot,
+        encryption: Some(test_encryption(threshold)),
+        ..Default::default()
+    };
+    assert!(encrypted(BallotKind::Approval, 2).validate().is_ok());
+    assert!(encrypted(BallotKind::Approval, 2)
+        .spec()
+        .encryption_key
+        .is_some());
+    assert!(encrypted(BallotKind::Approval, 3).validate().is_err());
+}
+
+#[test]
+fn test_encryption_forbids_ranked() {
+    let ranked = PollConfig {
+        id: "secret".to_string(),
+        options: vec!["a".to_string(), "b".to_string()],
+        ballot: BallotKind::Ranked,
+        encryption: Some(test_encryption(1)),
+        ..Default::default()
+    };
+    assert!(ranked.validate().is_err());
+}
+
+#[test]
+fn test_commit_reveal_window() {
+    let commit_reveal = |closes_at: u64, reveal_closes_at: Option<u64>| PollConfig {
+        id: "reveal".to_string(),
+        closes_at: Some(closes_at),
+        commit_reveal: true,
+        reveal_closes_at,
+        ..Default::default()
+    };
+    assert!(commit_reveal(100, Some(200)).validate().is_ok());
+    assert!(commit_reveal(100, Some(100)).validate().is_err());
+    assert!(commit_reveal(100, None).validate().is_err());
+    let plain = PollConfig {
+        commit_reveal: false,
+        ..commit_reveal(100, Some(200))
+    };
+    assert!(plain.validate().is_err());
+}
+
+#[test]
+fn test_encryption_forbids_commit_reveal() {
+    let both = PollConfig {
+        id: "reveal".to_string(),
+        options: vec!["a".to_string(), "b".to_string()],
+        closes_at: Some(100),
+        commit_reveal: true,
+        reveal_closes_at: Some(200),
+        encryption: Some(test_encryption(1)),
+        ..Default::default()
+    };
+    assert!(both.validate().is_err());
+}
+
+#[test]
+fn test_multi_select_validation() {
+    let multi_select = |max_selections: Option<u32>| PollConfig {
+        id: "pick".to_string(),
+        options: vec!["a".to_string(), "b".to_string(), "c".to_string()],
+        ballot: BallotKind::MultiSelect,
+        max_selections,
+        ..Default::default()
+    };
+    assert!(multi_select(Some(2)).validate().is_ok());
+    assert!(multi_select(Some(4)).validate().is_err());
+    assert!(multi_select(None).validate().is_err());
+}
+
+#[test]
+fn test_quadratic_validation() {
+    let quadratic = |ballot: BallotKind, credits: Option<u64>| PollConfig {
+        id: "budget".to_string(),
+        options: vec!["a".to_string(), "b".to_string()],
+        ballot,
+        credits,
+        ..Default::default()
+    };
+    assert!(quadratic(BallotKind::Quadratic, Some(25))
+        .validate()
+        .is_ok());
+    assert!(quadratic(BallotKind::Quadratic, Some(0))
+        .validate()
+        .is_err());
+    assert!(quadratic(BallotKind::Quadratic, None).validate().is_err());
+    assert!(quadratic(BallotKind::Approval, Some(25))
+        .validate()
+        .is_err());
+}
+
+#[test]
+fn test_rules_validation() {
+    let ruled: PollConfig = toml::from_str(
+        r#"
+        id = "charter"
+        [rules]
+        min_turnout = 0.5
+        supermajority = 0.6667
+        tie_break = "lot"
+        "#,
+    )
+    .unwrap();
+    assert!(ruled.validate().is_ok());
+    assert_eq!(ruled.rules.tie_break, TieBreak::Lot);
+    let overruled = PollConfig {
+        rules: OutcomeRules {
+            supermajority: Some(1.5),
+            ..Default::default()
+        },
+        ..ruled
+    };
+    assert!(overruled.validate().is_err());
+}
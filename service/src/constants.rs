@@ -1 +1,6 @@
-pub const GIT_GPG_URL: &str = "https://api.github.com/user/gpg_keys";
+// the public gpg keys of a user, relative to github.api_url
+pub const GIT_GPG_PATH: &str = "/users/{username}/gpg_keys";
+pub const GIT_USER_PATH: &str = "/users/{username}";
+pub const GIT_ORG_MEMBER_PATH: &str = "/orgs/{org}/members/{username}";
+pub const GIT_TEAM_MEMBER_PATH: &str = "/orgs/{org}/teams/{team}/memberships/{username}";
+pub const GIT_CONTRIBUTORS_PATH: &str = "/repos/{repo}/contributors";
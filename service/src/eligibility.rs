@@ -0,0 +1,281 @@
+// eligibility policies
+// owning a GPG key on GitHub proves the identity of a voter, a policy decides whether that
+// identity may take part in the poll, policies are built from the poll config per registration
+use crate::{config::EligibilityConfig, gauth::GitHubApi, state::RegistrationError};
+use async_trait::async_trait;
+use std::{
+    collections::HashSet,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const SECS_PER_DAY: u64 = 86_400;
+
+#[async_trait]
+pub trait EligibilityPolicy: Send + Sync {
+    /// `RegistrationError::Ineligible` if the user may not register,
+    /// `RegistrationError::GitHub` if the decision could not be made
+    async fn check(&self, github: &dyn GitHubApi, username: &str) -> Result<(), RegistrationError>;
+}
+
+fn ineligible(reason: String) -> Result<(), RegistrationError> {
+    Err(RegistrationError::Ineligible(reason))
+}
+
+pub struct OrgMember {
+    pub org: String,
+}
+
+#[async_trait]
+impl EligibilityPolicy for OrgMember {
+    async fn check(&self, github: &dyn GitHubApi, username: &str) -> Result<(), RegistrationError> {
+        match github.is_org_member(&self.org, username).await {
+            Ok(true) => Ok(()),
+            Ok(false) => ineligible(format!("not a member of {}", self.org)),
+            Err(e) => Err(RegistrationError::GitHub(e)),
+        }
+    }
+}
+
+pub struct TeamMember {
+    pub org: String,
+    pub team: String,
+}
+
+#[async_trait]
+impl EligibilityPolicy for TeamMember {
+    async fn check(&self, github: &dyn GitHubApi, username: &str) -> Result<(), RegistrationError> {
+        match github.is_team_member(&self.org, &self.team, username).await {
+            Ok(true) => Ok(()),
+            Ok(false) => ineligible(format!("not a member of {}/{}", self.org, self.team)),
+            Err(e) => Err(RegistrationError::GitHub(e)),
+        }
+    }
+}
+
+pub struct Contributor {
+    pub repo: String,
+}
+
+#[async_trait]
+impl EligibilityPolicy for Contributor {
+    async fn check(&self, github: &dyn GitHubApi, username: &str) -> Result<(), RegistrationError> {
+        match github.is_contributor(&self.repo, username).await {
+            Ok(true) => Ok(()),
+            Ok(false) => ineligible(format!("not a contributor to {}", self.repo)),
+            Err(e) => Err(RegistrationError::GitHub(e)),
+        }
+    }
+}
+
+// keeps freshly created sock puppet accounts out
+pub struct MinAccountAge {
+    pub days: u64,
+}
+
+#[async_trait]
+impl EligibilityPolicy for MinAccountAge {
+    async fn check(&self, github: &dyn GitHubApi, username: &str) -> Result<(), RegistrationError> {
+        let created_at: u64 = github
+            .created_at(username)
+            .await
+            .map_err(RegistrationError::GitHub)?;
+        let now: u64 = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        if now.saturating_sub(created_at) < self.days * SECS_PER_DAY {
+            return ineligible(format!("account is younger than {} days", self.days));
+        }
+        Ok(())
+    }
+}
+
+// GitHub logins are case insensitive
+fn logins(users: &[String]) -> HashSet<String> {
+    users.iter().map(|user| user.to_ascii_lowercase()).collect()
+}
+
+pub struct AllowList {
+    users: HashSet<String>,
+}
+
+#[async_trait]
+impl EligibilityPolicy for AllowList {
+    async fn check(&self, _: &dyn GitHubApi, username: &str) -> Result<(), RegistrationError> {
+        match self.users.contains(&username.to_ascii_lowercase()) {
+            true => Ok(()),
+            false => ineligible("not on the allow list".to_string()),
+        }
+    }
+}
+
+pub struct DenyList {
+    users: HashSet<String>,
+}
+
+#[async_trait]
+impl EligibilityPolicy for DenyList {
+    async fn check(&self, _: &dyn GitHubApi, username: &str) -> Result<(), RegistrationError> {
+        match self.users.contains(&username.to_ascii_lowercase()) {
+            true => ineligible("on the deny list".to_string()),
+            false => Ok(()),
+        }
+    }
+}
+
+// every policy must pass, evaluated in order
+pub struct All(pub Vec<Box<dyn EligibilityPolicy>>);
+
+#[async_trait]
+impl EligibilityPolicy for All {
+    async fn check(&self, github: &dyn GitHubApi, username: &str) -> Result<(), RegistrationError> {
+        for policy in &self.0 {
+            policy.check(github, username).await?;
+        }
+        Ok(())
+    }
+}
+
+// one policy must pass, evaluated in order
+pub struct Any(pub Vec<Box<dyn EligibilityPolicy>>);
+
+#[async_trait]
+impl EligibilityPolicy for Any {
+    async fn check(&self, github: &dyn GitHubApi, username: &str) -> Result<(), RegistrationError> {
+        let mut rejection: Option<RegistrationError> = None;
+        for policy in &self.0 {
+            match policy.check(github, username).await {
+                Ok(()) => return Ok(()),
+                // a failed lookup must not be reported as ineligibility
+                Err(error @ RegistrationError::GitHub(_)) => rejection = Some(error),
+                Err(error) => {
+                    if !matches!(rejection, Some(RegistrationError::GitHub(_))) {
+                        rejection = Some(error)
+                    }
+                }
+            }
+        }
+        Err(rejection.unwrap_or(RegistrationError::Ineligible(
+            "no policy matched".to_string(),
+        )))
+    }
+}
+
+pub fn build(config: &EligibilityConfig) -> Box<dyn EligibilityPolicy> {
+    match config {
+        EligibilityConfig::OrgMember { org } => Box::new(OrgMember { org: org.clone() }),
+        EligibilityConfig::TeamMember { org, team } => Box::new(TeamMember {
+            org: org.clone(),
+            team: team.clone(),
+        }),
+        EligibilityConfig::Contributor { repo } => Box::new(Contributor { repo: repo.clone() }),
+        EligibilityConfig::MinAccountAge { days } => Box::new(MinAccountAge { days: *days }),
+        EligibilityConfig::Allow { users } => Box::new(AllowList {
+            users: logins(users),
+        }),
+        EligibilityConfig::Deny { users } => Box::new(DenyList {
+            users: logins(users),
+        }),
+        EligibilityConfig::All { policies } => Box::new(All(policies.iter().map(build).collect())),
+        EligibilityConfig::Any { policies } => Box::new(Any(policies.iter().map(build).collect())),
+    }
+}
+
+#[tokio::test]
+async fn test_eligibility_policies() {
+    struct MockGitHub;
+    #[async_trait]
+    impl GitHubApi for MockGitHub {
+        async fn gpg_keys(&self, _: &str) -> Result<Vec<String>, String> {
+            Ok(Vec::new())
+        }
+        async fn created_at(&self, username: &str) -> Result<u64, String> {
+            match username {
+                "newcomer" => Ok(SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs()),
+                _ => Ok(0),
+            }
+        }
+        async fn is_org_member(&self, org: &str, username: &str) -> Result<bool, String> {
+            match org {
+                "down" => Err("GitHub api responded with 502".to_string()),
+                _ => Ok(org == "acme" && username != "outsider"),
+            }
+        }
+        async fn is_team_member(
+            &self,
+            _: &str,
+            team: &str,
+            username: &str,
+        ) -> Result<bool, String> {
+            Ok(team == "maintainers" && username == "octocat")
+        }
+        async fn is_contributor(&self, repo: &str, username: &str) -> Result<bool, String> {
+            Ok(repo == "acme/widgets" && username == "newcomer")
+        }
+    }
+    let users = |users: &[&str]| -> Vec<String> { users.iter().map(|u| u.to_string()).collect() };
+    // maintainers, or established org members that are not banned
+    let policy = build(&EligibilityConfig::Any {
+        policies: vec![
+            EligibilityConfig::TeamMember {
+                org: "acme".to_string(),
+                team: "maintainers".to_string(),
+            },
+            EligibilityConfig::All {
+                policies: vec![
+                    EligibilityConfig::OrgMember {
+                        org: "acme".to_string(),
+                    },
+                    EligibilityConfig::MinAccountAge { days: 30 },
+                    EligibilityConfig::Deny {
+                        users: users(&["Banned"]),
+                    },
+                ],
+            },
+        ],
+    });
+    let reason = |result: Result<(), RegistrationError>| result.err().map(|e| e.reason());
+    assert_eq!(reason(policy.check(&MockGitHub, "octocat").await), None);
+    assert_eq!(reason(policy.check(&MockGitHub, "member").await), None);
+    assert_eq!(
+        reason(policy.check(&MockGitHub, "newcomer").await),
+        Some("ineligible")
+    );
+    assert_eq!(
+        reason(policy.check(&MockGitHub, "banned").await),
+        Some("ineligible")
+    );
+    assert_eq!(
+        reason(policy.check(&MockGitHub, "outsider").await),
+        Some("ineligible")
+    );
+    let contributors = build(&EligibilityConfig::Contributor {
+        repo: "acme/widgets".to_string(),
+    });
+    assert_eq!(
+        reason(contributors.check(&MockGitHub, "newcomer").await),
+        None
+    );
+    let allowed = build(&EligibilityConfig::Allow {
+        users: users(&["OctoCat"]),
+    });
+    assert_eq!(reason(allowed.check(&MockGitHub, "octocat").await), None);
+    // an outage is reported as such, not as ineligibility
+    let unavailable = build(&EligibilityConfig::Any {
+        policies: vec![
+            EligibilityConfig::OrgMember {
+                org: "down".to_string(),
+            },
+            EligibilityConfig::Allow {
+                users: users(&["someone"]),
+            },
+        ],
+    });
+    assert_eq!(
+        reason(unavailable.check(&MockGitHub, "octocat").await),
+        Some("github")
+    );
+}
@@ -0,0 +1,52 @@
+// poll event stream
+// dashboards and clients subscribe to /polls/{poll}/events instead of polling the results,
+// the events come from the broadcast channel of ServiceState and never carry more than the
+// public routes would show at the same time
+use crate::state::{PollId, ServiceState};
+use axum::{
+    extract::Path,
+    http::StatusCode,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    Extension,
+};
+use futures_util::stream;
+use std::sync::Arc;
+use tokio::sync::{broadcast::error::RecvError, Mutex};
+
+pub async fn poll_events(
+    Path(poll_id): Path<PollId>,
+    Extension(state): Extension<Arc<Mutex<ServiceState>>>,
+) -> Response {
+    let receiver = {
+        let state = state.lock().await;
+        if state.poll(&poll_id).is_none() {
+            return (StatusCode::NOT_FOUND, format!("Unknown poll {}", poll_id)).into_response();
+        }
+        state.subscribe()
+    };
+    let events = stream::unfold(receiver, move |mut receiver| {
+        let poll_id: PollId = poll_id.clone();
+        async move {
+            loop {
+                let event: Result<Event, axum::Error> = match receiver.recv().await {
+                    Ok((id, event)) if id == poll_id => {
+                        Event::default().event(event.name()).json_data(&event)
+                    }
+                    Ok(_) => continue,
+                    // the subscriber missed events, it should fetch the current state again
+                    Err(RecvError::Lagged(missed)) => {
+                        Ok(Event::default().event("lagged").data(missed.to_string()))
+                    }
+                    Err(RecvError::Closed) => return None,
+                };
+                return Some((event, receiver));
+            }
+        }
+    });
+    Sse::new(events)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
@@ -1,52 +1,193 @@
 // GitHub authentication
-// should check the Github API for associated GPG keys and return them as a Vec
-use crate::constants::GIT_GPG_URL;
-use reqwest::header::{HeaderMap, HeaderValue, ACCEPT, AUTHORIZATION, USER_AGENT};
+// should check the Github API for associated GPG keys and return them as a Vec,
+// the eligibility policies query memberships and account details through the same trait
+use crate::config::{ConfigError, GitHubConfig};
+use crate::constants::{
+    GIT_CONTRIBUTORS_PATH, GIT_GPG_PATH, GIT_ORG_MEMBER_PATH, GIT_TEAM_MEMBER_PATH, GIT_USER_PATH,
+};
+use async_trait::async_trait;
+use chrono::DateTime;
+use reqwest::{
+    header::{HeaderMap, HeaderValue, ACCEPT, AUTHORIZATION, USER_AGENT},
+    Response, StatusCode,
+};
 use serde_json::Value;
 
-pub async fn query_user_gpg_keys(token: String) -> Vec<String> {
-    let client = reqwest::Client::new();
-    // Headers
-    let mut headers = HeaderMap::new();
-    headers.insert(
-        ACCEPT,
-        HeaderValue::from_static("application/vnd.github+json"),
-    );
-    headers.insert(
-        AUTHORIZATION,
-        HeaderValue::from_str(&format!("Bearer {}", token)).unwrap(),
-    );
-    headers.insert(
-        USER_AGENT,
-        HeaderValue::from_static("Acropolis V2 (jonaspauli089@gmail.com)"),
-    );
-    // Custom GitHub API version header
-    headers.insert(
-        "X-GitHub-Api-Version",
-        HeaderValue::from_static("2022-11-28"),
-    );
-
-    // Making the GET request
-    let response = client
-        .get(GIT_GPG_URL)
-        .headers(headers)
-        .send()
+// contributors are listed in pages of this size
+const CONTRIBUTORS_PER_PAGE: usize = 100;
+// upper bound for the pages fetched per lookup
+const MAX_CONTRIBUTOR_PAGES: usize = 50;
+
+/// the GitHub queries of the service, mocked in tests
+#[async_trait]
+pub trait GitHubApi: Send + Sync {
+    // the raw, ascii armored public keys that the user associated with their account
+    async fn gpg_keys(&self, username: &str) -> Result<Vec<String>, String>;
+    // unix timestamp (seconds) of the account creation
+    async fn created_at(&self, username: &str) -> Result<u64, String>;
+    async fn is_org_member(&self, org: &str, username: &str) -> Result<bool, String>;
+    async fn is_team_member(&self, org: &str, team: &str, username: &str) -> Result<bool, String>;
+    // repo is owner/name
+    async fn is_contributor(&self, repo: &str, username: &str) -> Result<bool, String>;
+}
+
+#[derive(Clone)]
+pub struct GitHubClient {
+    api_url: String,
+    client: reqwest::Client,
+}
+
+impl GitHubClient {
+    pub fn new(config: &GitHubConfig) -> Result<GitHubClient, ConfigError> {
+        let token: String = config.token()?;
+        // Headers
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            ACCEPT,
+            HeaderValue::from_static("application/vnd.github+json"),
+        );
+        headers.insert(
+            AUTHORIZATION,
+            HeaderValue::from_str(&format!("Bearer {}", token))
+                .map_err(|_| ConfigError::Invalid("GitHub token is not a valid header".into()))?,
+        );
+        headers.insert(
+            USER_AGENT,
+            HeaderValue::from_str(&config.user_agent).map_err(|_| {
+                ConfigError::Invalid("github.user_agent is not a valid header".into())
+            })?,
+        );
+        // Custom GitHub API version header
+        headers.insert(
+            "X-GitHub-Api-Version",
+            HeaderValue::from_static("2022-11-28"),
+        );
+        let client = reqwest::Client::builder()
+            .default_headers(headers)
+            .build()
+            .map_err(|e| ConfigError::Invalid(e.to_string()))?;
+        Ok(GitHubClient {
+            api_url: config.api_url.trim_end_matches('/').to_string(),
+            client,
+        })
+    }
+
+    async fn get(&self, path: &str) -> Result<Response, String> {
+        self.client
+            .get(format!("{}{}", self.api_url, path))
+            .send()
+            .await
+            .map_err(|e| format!("Failed to get response from GitHub api: {}", e))
+    }
+
+    async fn get_json(&self, path: &str) -> Result<Value, String> {
+        let response = self.get(path).await?;
+        if !response.status().is_success() {
+            return Err(format!("GitHub api responded with {}", response.status()));
+        }
+        let response = response
+            .text()
+            .await
+            .map_err(|e| format!("Failed to unwrap response: {}", e))?;
+        serde_json::from_str(&response).map_err(|e| e.to_string())
+    }
+
+    // membership routes answer 2xx for members and 404 otherwise
+    async fn is_member(&self, path: &str) -> Result<bool, String> {
+        let response = self.get(path).await?;
+        match response.status() {
+            status if status.is_success() => Ok(true),
+            StatusCode::NOT_FOUND => Ok(false),
+            status => Err(format!("GitHub api responded with {}", status)),
+        }
+    }
+}
+
+#[async_trait]
+impl GitHubApi for GitHubClient {
+    async fn gpg_keys(&self, username: &str) -> Result<Vec<String>, String> {
+        let json_keys: Value = self
+            .get_json(&GIT_GPG_PATH.replace("{username}", username))
+            .await?;
+        let mut raw_keys: Vec<String> = Vec::new();
+        if let Some(array) = json_keys.as_array() {
+            for item in array {
+                let raw_key = item["raw_key"].to_string();
+                let mut formatted_key = raw_key
+                    .replace("\\r\\n", "\n")
+                    .trim_start_matches('"')
+                    .trim_end_matches('"')
+                    .to_string();
+                formatted_key.push('\n');
+                raw_keys.push(formatted_key);
+            }
+        }
+        Ok(raw_keys)
+    }
+
+    async fn created_at(&self, username: &str) -> Result<u64, String> {
+        let user: Value = self
+            .get_json(&GIT_USER_PATH.replace("{username}", username))
+            .await?;
+        let created_at: &str = user["created_at"]
+            .as_str()
+            .ok_or("GitHub user has no created_at")?;
+        let created_at = DateTime::parse_from_rfc3339(created_at).map_err(|e| e.to_string())?;
+        Ok(created_at.timestamp().max(0) as u64)
+    }
+
+    async fn is_org_member(&self, org: &str, username: &str) -> Result<bool, String> {
+        self.is_member(
+            &GIT_ORG_MEMBER_PATH
+                .replace("{org}", org)
+                .replace("{username}", username),
+        )
         .await
-        .expect("Failed to get response from GitHub api");
-    let response = response.text().await.expect("Failed to unwrap response");
-    let json_keys: Value = serde_json::from_str(&response).unwrap();
-    let mut raw_keys: Vec<String> = Vec::new();
-    if let Some(array) = json_keys.as_array() {
-        for item in array {
-            let raw_key = item["raw_key"].to_string();
-            let mut formatted_key = raw_key
-                .replace("\\r\\n", "\n")
-                .trim_start_matches('"')
-                .trim_end_matches('"')
-                .to_string();
-            formatted_key.push_str("\n");
-            raw_keys.push(formatted_key);
+    }
+
+    async fn is_team_member(&self, org: &str, team: &str, username: &str) -> Result<bool, String> {
+        let path: String = GIT_TEAM_MEMBER_PATH
+            .replace("{org}", org)
+            .replace("{team}", team)
+            .replace("{username}", username);
+        let response = self.get(&path).await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(false);
+        }
+        // pending invitations are not memberships yet
+        let membership: Value = match response.status().is_success() {
+            true => response.json().await.map_err(|e| e.to_string())?,
+            false => return Err(format!("GitHub api responded with {}", response.status())),
+        };
+        Ok(membership["state"] == "active")
+    }
+
+    async fn is_contributor(&self, repo: &str, username: &str) -> Result<bool, String> {
+        for page in 1..=MAX_CONTRIBUTOR_PAGES {
+            let contributors: Value = self
+                .get_json(&format!(
+                    "{}?per_page={}&page={}",
+                    GIT_CONTRIBUTORS_PATH.replace("{repo}", repo),
+                    CONTRIBUTORS_PER_PAGE,
+                    page
+                ))
+                .await?;
+            let contributors: &Vec<Value> = match contributors.as_array() {
+                Some(contributors) => contributors,
+                None => return Err("GitHub api returned no contributor list".to_string()),
+            };
+            // logins are case insensitive
+            if contributors.iter().any(|contributor| {
+                contributor["login"]
+                    .as_str()
+                    .is_some_and(|login| login.eq_ignore_ascii_case(username))
+            }) {
+                return Ok(true);
+            }
+            if contributors.len() < CONTRIBUTORS_PER_PAGE {
+                break;
+            }
         }
+        Ok(false)
     }
-    raw_keys
 }
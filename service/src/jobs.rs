@@ -0,0 +1,177 @@
+// vote verification jobs
+// proofs are verified on a fixed pool of OS threads so that a slow STARK verification never
+// blocks the tokio runtime, the job table tracks every submission until it is collected
+use crate::{admission::Rejection, metrics::metrics};
+use client::types::JobStatus;
+use crypto::to_hex;
+use risc0_types::CircuitOutputs;
+use std::{
+    collections::HashMap,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        mpsc::{self, Receiver, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use tokio::sync::{oneshot, watch};
+use zk_associated::proof::{ProofEnvelope, ProofError, VoteVerifier};
+
+pub type JobId = String;
+
+// finished jobs can be collected for this long
+const JOB_TTL: Duration = Duration::from_secs(600);
+// upper bound for ?wait= on the vote routes
+pub const MAX_VOTE_WAIT: Duration = Duration::from_secs(60);
+
+struct Job {
+    poll: String,
+    status: watch::Sender<JobStatus>,
+    finished: Option<Instant>,
+}
+
+#[derive(Default)]
+pub struct Jobs {
+    jobs: Mutex<HashMap<JobId, Job>>,
+}
+
+impl Jobs {
+    pub fn create(&self, poll: &str) -> (JobId, watch::Receiver<JobStatus>) {
+        // job ids are unguessable, the status of an accepted vote contains its ticket
+        let id: JobId = to_hex(&rand::random::<[u8; 16]>());
+        let (status, receiver) = watch::channel(JobStatus::Queued);
+        let mut jobs = self.jobs.lock().unwrap();
+        jobs.retain(|_, job| job.finished.map_or(true, |t| t.elapsed() < JOB_TTL));
+        jobs.insert(
+            id.clone(),
+            Job {
+                poll: poll.to_string(),
+                status,
+                finished: None,
+            },
+        );
+        (id, receiver)
+    }
+
+    pub fn set(&self, id: &str, status: JobStatus) {
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(id) {
+            if status.is_final() {
+                job.finished = Some(Instant::now());
+            }
+            job.status.send_replace(status);
+        }
+    }
+
+    pub fn remove(&self, id: &str) {
+        self.jobs.lock().unwrap().remove(id);
+    }
+
+    // jobs are scoped to their poll
+    pub fn subscribe(&self, poll: &str, id: &str) -> Option<watch::Receiver<JobStatus>> {
+        self.jobs
+            .lock()
+            .unwrap()
+            .get(id)
+            .filter(|job| job.poll == poll)
+            .map(|job| job.status.subscribe())
+    }
+}
+
+/// waits until the job is final or the timeout passed, returns the latest status
+pub async fn wait_for(mut status: watch::Receiver<JobStatus>, timeout: Duration) -> JobStatus {
+    let _ = tokio::time::timeout(timeout, status.wait_for(JobStatus::is_final)).await;
+    let latest: JobStatus = status.borrow().clone();
+    latest
+}
+
+type Verified = Result<CircuitOutputs, ProofError>;
+
+struct Task {
+    envelope: ProofEnvelope,
+    started: Box<dyn FnOnce() + Send>,
+    reply: oneshot::Sender<Verified>,
+}
+
+pub struct VerificationPool {
+    sender: SyncSender<Task>,
+}
+
+impl VerificationPool {
+    /// `queue` tasks wait for one of the `workers` threads, further submissions are rejected
+    pub fn start(verifier: Arc<dyn VoteVerifier>, workers: usize, queue: usize) -> Self {
+        let (sender, receiver) = mpsc::sync_channel::<Task>(queue);
+        let receiver: Arc<Mutex<Receiver<Task>>> = Arc::new(Mutex::new(receiver));
+        for worker in 0..workers {
+            let receiver = Arc::clone(&receiver);
+            let verifier = Arc::clone(&verifier);
+            thread::Builder::new()
+                .name(format!("verifier-{}", worker))
+                .spawn(move || loop {
+                    // the guard is dropped before the task runs
+                    let task: Task = match receiver.lock().unwrap().recv() {
+                        Ok(task) => task,
+                        Err(_) => return,
+                    };
+                    (task.started)();
+                    let verification = metrics()
+                        .verification_seconds
+                        .with_label_values(&[&task.envelope.backend.to_string()])
+                        .start_timer();
+                    // a panicking verifier must not take the worker down with it
+                    let verified: Verified =
+                        panic::catch_unwind(AssertUnwindSafe(|| verifier.verify(&task.envelope)))
+                            .unwrap_or_else(|_| {
+                                Err(ProofError::Invalid("Verifier panicked".to_string()))
+                            });
+                    verification.observe_duration();
+                    let _ = task.reply.send(verified);
+                })
+                .expect("Failed to spawn verification worker");
+        }
+        VerificationPool { sender }
+    }
+
+    pub fn submit(
+        &self,
+        envelope: ProofEnvelope,
+        started: impl FnOnce() + Send + 'static,
+    ) -> Result<oneshot::Receiver<Verified>, Rejection> {
+        let (reply, verified) = oneshot::channel();
+        match self.sender.try_send(Task {
+            envelope,
+            started: Box::new(started),
+            reply,
+        }) {
+            Ok(()) => Ok(verified),
+            Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => {
+                Err(Rejection::Queue)
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_job_status() {
+    let jobs = Jobs::default();
+    let (id, status) = jobs.create("default");
+    assert!(jobs.subscribe("other", &id).is_none());
+    assert_eq!(
+        wait_for(status.clone(), Duration::from_millis(10)).await,
+        JobStatus::Queued
+    );
+    jobs.set(&id, JobStatus::Verifying);
+    let waiting = tokio::spawn(wait_for(
+        jobs.subscribe("default", &id).unwrap(),
+        Duration::from_secs(10),
+    ));
+    jobs.set(
+        &id,
+        JobStatus::Accepted {
+            ticket: "ticket".to_string(),
+        },
+    );
+    assert!(waiting.await.unwrap().is_final());
+    jobs.remove(&id);
+    assert!(jobs.subscribe("default", &id).is_none());
+}
@@ -0,0 +1,89 @@
+// structured logging
+// events go through `tracing`, formatted as human readable lines or as one JSON object per line.
+// A redaction filter sits in front of the formatter: a GitHub username, a network address and
+// a vote choice together are enough to deanonymize a voter, so an event may carry at most one
+// of these classes and spans (whose fields are repeated on every nested event) may carry none.
+use clap::ValueEnum;
+use serde::Deserialize;
+use tracing::Metadata;
+use tracing_subscriber::{filter::filter_fn, fmt, prelude::*, EnvFilter};
+
+#[derive(Deserialize, ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Pretty,
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Sensitive {
+    Identity,
+    Network,
+    Choice,
+}
+
+// field names are the contract, log sensitive values only under one of these names
+fn classify(field: &str) -> Option<Sensitive> {
+    match field {
+        "username" => Some(Sensitive::Identity),
+        "client_ip" | "peer" => Some(Sensitive::Network),
+        "vote" | "choice" | "ballot" | "tally" => Some(Sensitive::Choice),
+        _ => None,
+    }
+}
+
+/// false for spans with a sensitive field and for events that mix sensitive classes
+pub fn is_loggable(metadata: &Metadata<'_>) -> bool {
+    fields_loggable(
+        metadata.fields().iter().map(|field| field.name()),
+        metadata.is_span(),
+    )
+}
+
+fn fields_loggable<'a>(fields: impl Iterator<Item = &'a str>, is_span: bool) -> bool {
+    let mut classes: Vec<Sensitive> = Vec::new();
+    for class in fields.filter_map(classify) {
+        if !classes.contains(&class) {
+            classes.push(class);
+        }
+    }
+    if is_span {
+        classes.is_empty()
+    } else {
+        classes.len() <= 1
+    }
+}
+
+/// installs the global subscriber, `filter` uses the RUST_LOG syntax, e.g. "info,service=debug"
+pub fn init(filter: &str, format: LogFormat) {
+    let filter: EnvFilter = EnvFilter::try_new(filter).expect("Log filter was validated");
+    let registry = tracing_subscriber::registry().with(filter);
+    match format {
+        LogFormat::Pretty => registry
+            .with(fmt::layer().with_filter(filter_fn(is_loggable)))
+            .init(),
+        LogFormat::Json => registry
+            .with(
+                fmt::layer()
+                    .json()
+                    .with_current_span(true)
+                    .with_span_list(false)
+                    .with_filter(filter_fn(is_loggable)),
+            )
+            .init(),
+    }
+}
+
+#[test]
+fn test_redaction() {
+    let event = |fields: &[&'static str]| fields_loggable(fields.iter().copied(), false);
+    assert!(event(&["poll", "reason"]));
+    assert!(event(&["poll", "username"]));
+    assert!(event(&["poll", "vote", "tally"]));
+    assert!(!event(&["username", "vote"]));
+    assert!(!event(&["client_ip", "username", "message"]));
+    // span fields end up on every event inside the span
+    assert!(fields_loggable(["method", "path"].into_iter(), true));
+    assert!(!fields_loggable(["method", "client_ip"].into_iter(), true));
+}
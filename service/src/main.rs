@@ -1,329 +1,643 @@
 // responsible for maintaining state
 // accepts proof payloads (Routes)
 // verifies proofs
+mod admin;
+mod admission;
+mod config;
 mod constants;
+mod eligibility;
+mod events;
 pub mod gauth;
+mod jobs;
+mod logging;
+mod metrics;
+mod outcome;
+mod state;
+mod storage;
+mod tally;
+mod webhooks;
+mod weights;
+use admin::AdminToken;
+use admission::{rate_limit, RateLimiter};
 use axum::{
-    extract::DefaultBodyLimit,
-    response::IntoResponse,
+    extract::{DefaultBodyLimit, Path, Query, Request},
+    http::header::CONTENT_TYPE,
+    middleware,
+    response::{IntoResponse, Response},
     routing::{get, post},
     Extension, Json, Router,
 };
-use crossterm::{execute, terminal::Clear};
-use gauth::query_user_gpg_keys;
+use clap::Parser;
+use config::{Args, ServiceConfig};
+use eligibility::EligibilityPolicy;
+use gauth::{GitHubApi, GitHubClient};
+use jobs::{wait_for, JobId, Jobs, VerificationPool, MAX_VOTE_WAIT};
+use metrics::{metrics, ACCEPTED};
 use reqwest::StatusCode;
-use risc0_zkvm::Receipt;
-use std::{collections::HashMap, env, io};
-use std::{collections::HashSet, sync::Arc};
+use serde::Deserialize;
+use state::{
+    DecryptionError, GitHubUser, PollId, PollState, RegistrationError, RevealError, ServiceState,
+    VoteError,
+};
+use std::{
+    env, fmt,
+    net::SocketAddr,
+    process,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use storage::Storage;
+use webhooks::WebhookDispatcher;
 // registers voters / inserts new identities into the tree
 // if the signature is valid
 // if the account is unique
 // if the public key corresponds to the associated github keys
 // for the user
-use client::types::IdentityPayload;
-use colored::*;
-use crypto::{
-    gpg::GpgSigner,
-    hash,
-    identity::{Identity, Nullifier},
-    CryptoHasherSha256,
+use client::types::{
+    Challenge, ChallengeRequest, IdentityPayload, JobStatus, RevealPayload, TrusteeShares, VoteJob,
+    WEIGHT_HEADER,
 };
+use crypto::{gpg::GpgSigner, to_hex};
 use pgp::types::Mpi;
-use risc0_prover::verifier::verify_vote;
-use tokio::sync::Mutex;
+use risc0_prover::verifier::Risc0Verifier;
+use risc0_types::CircuitOutputs;
+use tokio::sync::{oneshot, Mutex};
+use tower_http::trace::{DefaultOnResponse, TraceLayer};
+use tracing::{info, info_span, warn, Level, Span};
 use voting_tree::VotingTree;
-use zk_associated::storage::TreeState;
+use zk_associated::{
+    dev::DevVerifier,
+    proof::{ProofEnvelope, ProofError, VerifierSet, VoteVerifier},
+    storage::TreeState,
+};
 
-type GitHubUser = String;
+// risc0 accepts fake receipts when RISC0_DEV_MODE is enabled
+fn risc0_dev_mode() -> bool {
+    matches!(
+        env::var("RISC0_DEV_MODE")
+            .unwrap_or_default()
+            .to_lowercase()
+            .as_str(),
+        "1" | "true" | "yes"
+    )
+}
 
-#[derive(Clone)]
-struct InMemoryTreeState {
-    tree_state: TreeState,
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
 }
-impl InMemoryTreeState {
-    fn insert_nullifier(&mut self, identity: Identity) {
-        let new_state = self.tree_state.insert_nullifier(identity);
-        self.tree_state = new_state;
+
+// closes polls once their closes_at has passed
+async fn close_expired_polls(state: Arc<Mutex<ServiceState>>) {
+    loop {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        state.lock().await.close_expired(unix_now());
     }
-    fn insert_used_nullifier(&mut self, nullifier: Nullifier) {
-        self.tree_state.used_nullifiers.push(nullifier);
+}
+
+// `&'static str` becomes a `200 OK` with `content-type: text/plain; charset=utf-8`
+async fn ping() -> &'static str {
+    "pong"
+}
+
+// used before and after the subscriber is installed, so it writes to stderr directly
+fn exit_with(error: impl fmt::Display) -> ! {
+    eprintln!("Error: {}", error);
+    process::exit(2)
+}
+
+// spans are repeated on every nested event, so they only carry the method, path and a request id
+fn request_span(request: &Request) -> Span {
+    static REQUEST_ID: AtomicU64 = AtomicU64::new(0);
+    info_span!(
+        "request",
+        id = REQUEST_ID.fetch_add(1, Ordering::Relaxed),
+        method = %request.method(),
+        path = %request.uri().path(),
+    )
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
+    let config: ServiceConfig = ServiceConfig::load(&args).unwrap_or_else(|e| exit_with(e));
+    logging::init(&config.log_level, config.log_format);
+    let github: Arc<dyn GitHubApi> =
+        Arc::new(GitHubClient::new(&config.github).unwrap_or_else(|e| exit_with(e)));
+    let storage: Option<Storage> = config.storage_path.as_ref().map(|path| {
+        Storage::open(path)
+            .unwrap_or_else(|e| exit_with(format!("storage {}: {}", path.display(), e)))
+    });
+    let admin_token: Option<AdminToken> = config
+        .admin
+        .as_ref()
+        .map(|admin| AdminToken::new(&admin.token().unwrap_or_else(|e| exit_with(e))));
+    let service_state: ServiceState = ServiceState::new(config.polls.clone(), storage)
+        .unwrap_or_else(|e| exit_with(format!("Failed to restore state: {}", e)));
+    if !config.insecure_dev_proofs && risc0_dev_mode() {
+        exit_with(
+            "RISC0_DEV_MODE is set, restart with --insecure-dev-proofs to accept dev receipts",
+        );
     }
-    fn get(&self) -> TreeState {
-        // must be mutable, will be inserted -> clone
-        self.tree_state.clone()
+    info!(
+        bind = %config.bind,
+        polls = config.polls.len(),
+        persistent = config.storage_path.is_some(),
+        admin = admin_token.is_some(),
+        "starting cypher-poll service"
+    );
+    let shared_state = Arc::new(Mutex::new(service_state));
+    tokio::spawn(close_expired_polls(Arc::clone(&shared_state)));
+    let dispatcher: Arc<WebhookDispatcher> =
+        Arc::new(WebhookDispatcher::new(Duration::from_secs(1)));
+    tokio::spawn(Arc::clone(&dispatcher).run(Arc::clone(&shared_state)));
+    let verifier: Arc<dyn VoteVerifier> = if config.insecure_dev_proofs {
+        warn!("INSECURE: accepting dev-mode proofs");
+        Arc::new(VerifierSet::default().with(Risc0Verifier).with(DevVerifier))
+    } else {
+        Arc::new(Risc0Verifier)
+    };
+    let limiter: Arc<RateLimiter> = Arc::new(RateLimiter::new(config.limits.clone()));
+    let pool: Arc<VerificationPool> = Arc::new(VerificationPool::start(
+        Arc::clone(&verifier),
+        config.limits.verification_concurrency,
+        config.limits.verification_queue,
+    ));
+    let jobs: Arc<Jobs> = Arc::new(Jobs::default());
+    // only the expensive routes are rate limited
+    let limited = Router::new()
+        .route("/polls/:poll_id/challenge", post(challenge))
+        .route("/polls/:poll_id/register", post(register))
+        .route("/polls/:poll_id/vote", post(vote))
+        .route("/polls/:poll_id/reveal", post(reveal))
+        .route_layer(middleware::from_fn(rate_limit));
+    let app = Router::new()
+        .route("/ping", get(ping))
+        .merge(limited)
+        .route("/polls/:poll_id", get(poll_info))
+        .route("/polls/:poll_id/vote/:job", get(vote_status))
+        .route("/polls/:poll_id/results", get(results))
+        .route("/polls/:poll_id/shares", post(decryption_shares))
+        .route("/polls/:poll_id/snapshot/:index", get(snapshot))
+        .route("/polls/:poll_id/roots", get(roots))
+        .route("/polls/:poll_id/tickets/:ticket", get(ticket))
+        .route("/polls/:poll_id/events", get(events::poll_events))
+        .route("/metrics", get(prometheus_metrics));
+    let app = match admin_token {
+        Some(token) => app.merge(admin::router(token)),
+        None => app,
+    };
+    let app = app
+        .layer(DefaultBodyLimit::max(config.limits.body_limit_bytes))
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(request_span)
+                .on_response(DefaultOnResponse::new().level(Level::INFO)),
+        )
+        .layer(Extension(shared_state))
+        .layer(Extension(verifier))
+        .layer(Extension(pool))
+        .layer(Extension(jobs))
+        .layer(Extension(limiter))
+        .layer(Extension(dispatcher))
+        .layer(Extension(github));
+    let listener = tokio::net::TcpListener::bind(config.bind)
+        .await
+        .unwrap_or_else(|e| exit_with(format!("Failed to bind {}: {}", config.bind, e)));
+    // the peer address is needed for the per-ip limits
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .unwrap();
+}
+
+// proves that the public key belongs to the GitHub user and signed the challenge,
+// then applies the eligibility policy of the poll
+async fn verify_registration(
+    github: &dyn GitHubApi,
+    policy: Option<&dyn EligibilityPolicy>,
+    signature: Vec<Mpi>,
+    data: &[u8],
+    public_key: String,
+    username: &str,
+) -> Result<(), RegistrationError> {
+    let mut signer = GpgSigner {
+        secret_key_asc_path: None,
+        public_key_asc_string: Some(public_key),
+        signed_secret_key: None,
+        signed_public_key: None,
+    };
+    signer
+        .try_init_verifier()
+        .map_err(|_| RegistrationError::InvalidKey)?;
+    // verify that the key exists in the Username's Raw Key List
+    let lookup = metrics().github_seconds.start_timer();
+    let raw_gpg_keys: Result<Vec<String>, String> = github.gpg_keys(username).await;
+    lookup.observe_duration();
+    let raw_gpg_keys: Vec<String> = raw_gpg_keys.map_err(RegistrationError::GitHub)?;
+    if !raw_gpg_keys.contains(&signer.public_key_asc_string.clone().unwrap()) {
+        return Err(RegistrationError::UnknownKey);
+    }
+    if !signer.is_valid_signature(signature, data) {
+        return Err(RegistrationError::InvalidSignature);
+    }
+    match policy {
+        Some(policy) => policy.check(github, username).await,
+        None => Ok(()),
     }
 }
 
-#[derive(Clone)]
-struct InMemoryGitHubUserState {
-    github_users: HashSet<GitHubUser>,
+// the registration signs this instead of data of its own choosing, the signature then covers
+// the poll, the login and the identity and can't be replayed
+async fn challenge(
+    Path(poll_id): Path<PollId>,
+    Extension(state): Extension<Arc<Mutex<ServiceState>>>,
+    Json(request): Json<ChallengeRequest>,
+) -> Response {
+    let username: GitHubUser = state::login(&request.username);
+    let result: Result<Vec<u8>, RegistrationError> =
+        state
+            .lock()
+            .await
+            .issue_challenge(&poll_id, &username, &request.identity);
+    match result {
+        Ok(data) => Json(Challenge { data }).into_response(),
+        Err(error) => {
+            info!(poll = %poll_id, reason = %error, "challenge refused");
+            (error.status(), error.to_string()).into_response()
+        }
+    }
 }
-impl InMemoryGitHubUserState {
-    fn insert(&mut self, user: String) {
-        self.github_users.insert(user);
+
+async fn register(
+    Path(poll_id): Path<PollId>,
+    Extension(state): Extension<Arc<Mutex<ServiceState>>>,
+    Extension(github): Extension<Arc<dyn GitHubApi>>,
+    Json(payload): Json<IdentityPayload>,
+) -> Response {
+    // one account may not register twice by changing the case of its login
+    let username: GitHubUser = state::login(&payload.username);
+    let mut deserialized_signature: Vec<Mpi> = Vec::new();
+    for series in &payload.signature_serialized {
+        deserialized_signature.push(Mpi::from_slice(series))
+    }
+    // cheap checks first, the lock is not held during the GitHub lookup
+    // and `register` repeats them atomically with the insert.
+    // the challenge is redeemed before the lookup, a replayed registration is never verified
+    let check = {
+        let mut state = state.lock().await;
+        let redeemed = state
+            .check_registration(&poll_id, &username)
+            .and_then(|()| {
+                state.redeem_challenge(
+                    &poll_id,
+                    &username,
+                    &payload.identity,
+                    &payload.data_serialized,
+                )
+            });
+        redeemed.map(|()| {
+            let poll: Option<&PollState> = state.poll(&poll_id);
+            (
+                poll.and_then(PollState::eligibility),
+                poll.and_then(|poll| poll.config.weights.clone()),
+            )
+        })
+    };
+    let result: Result<(VotingTree, u64), RegistrationError> = async {
+        let (policy, weights) = check?;
+        verify_registration(
+            github.as_ref(),
+            policy.as_deref(),
+            deserialized_signature,
+            &payload.data_serialized,
+            payload.public_key_string,
+            &username,
+        )
+        .await?;
+        let weight: u64 = match &weights {
+            Some(weights) => weights::assign(weights, github.as_ref(), &username).await?,
+            None => 1,
+        };
+        state
+            .lock()
+            .await
+            .register(&poll_id, &username, payload.identity, weight)
+            .map(|snapshot| (snapshot, weight))
     }
-    fn get(&self, user: &String) -> Option<&String> {
-        match self.github_users.get(user) {
-            Some(user) => Some(user),
-            None => None,
+    .await;
+    match result {
+        Ok((snapshot, weight)) => {
+            metrics().registration(&poll_id, ACCEPTED);
+            let snapshot_serialized: Vec<u8> =
+                serde_json::to_vec(&snapshot).expect("Failed to serialize snapshot");
+            // the voter needs the weight to prove their leaf
+            (
+                StatusCode::OK,
+                [(WEIGHT_HEADER, weight.to_string())],
+                snapshot_serialized,
+            )
+                .into_response()
+        }
+        Err(error) => {
+            // unknown poll ids are not used as metric labels
+            if !matches!(error, RegistrationError::UnknownPoll) {
+                metrics().registration(&poll_id, error.reason());
+            }
+            info!(poll = %poll_id, reason = %error, "registration rejected");
+            (error.status(), error.to_string().into_bytes()).into_response()
         }
     }
 }
 
-#[derive(Clone)]
-struct InMemoryVoteState {
-    votes: HashMap<String, u64>,
+#[derive(Deserialize)]
+struct VoteQuery {
+    // seconds to wait for the verification before answering with the pending job
+    #[serde(default)]
+    wait: u64,
 }
-impl InMemoryVoteState {
-    fn insert(&mut self, vote: String) {
-        if self.votes.contains_key(&vote) {
-            self.votes.insert(vote.clone(), self.get(&vote) + 1);
-        } else {
-            self.votes.insert(vote, 1u64);
-        }
+
+async fn vote(
+    Path(poll_id): Path<PollId>,
+    Query(query): Query<VoteQuery>,
+    Extension(state): Extension<Arc<Mutex<ServiceState>>>,
+    Extension(verifier): Extension<Arc<dyn VoteVerifier>>,
+    Extension(pool): Extension<Arc<VerificationPool>>,
+    Extension(jobs): Extension<Arc<Jobs>>,
+    Json(payload): Json<ProofEnvelope>,
+) -> Response {
+    // unknown poll ids are answered first, they are never used as metric labels
+    if state.lock().await.poll(&poll_id).is_none() {
+        return (StatusCode::NOT_FOUND, format!("Unknown poll {}", poll_id)).into_response();
     }
-    fn get(&self, vote: &String) -> &u64 {
-        self.votes
-            .get(vote)
-            .expect(format!("Failed to get votes for {}", vote).as_str())
+    // cheap pre-checks on the unverified journal, before the proof is queued
+    let checked: Result<(), VoteError> = match verifier.inspect(&payload) {
+        Ok(outputs) => state.lock().await.check_vote(&poll_id, &outputs),
+        Err(error) => Err(VoteError::Proof(error)),
+    };
+    if let Err(error) = checked {
+        let message: String = error.to_string();
+        let status: StatusCode = error.status();
+        rejected(&poll_id, error);
+        return (status, message).into_response();
     }
+    let (job, status) = jobs.create(&poll_id);
+    let started = {
+        let jobs = Arc::clone(&jobs);
+        let job = job.clone();
+        move || jobs.set(&job, JobStatus::Verifying)
+    };
+    let verified = match pool.submit(payload, started) {
+        Ok(verified) => verified,
+        Err(rejection) => {
+            jobs.remove(&job);
+            return rejection.into_response();
+        }
+    };
+    tokio::spawn(finish_vote(
+        state,
+        Arc::clone(&jobs),
+        poll_id.clone(),
+        job.clone(),
+        verified,
+    ));
+    let status: JobStatus =
+        wait_for(status, Duration::from_secs(query.wait).min(MAX_VOTE_WAIT)).await;
+    let code: StatusCode = match &status {
+        JobStatus::Accepted { .. } => StatusCode::OK,
+        JobStatus::Rejected { .. } => StatusCode::BAD_REQUEST,
+        _ => StatusCode::ACCEPTED,
+    };
+    let job: VoteJob = VoteJob {
+        job,
+        poll: poll_id,
+        status,
+    };
+    (code, Json(job)).into_response()
 }
 
-#[derive(Clone)]
-struct ServiceState {
-    github_users: InMemoryGitHubUserState,
-    tree_state: InMemoryTreeState,
-    votes: InMemoryVoteState,
-}
-impl ServiceState {
-    // register a voter, takes a risc0 receipt as input (currently not prover-generic)
-    // todo: check that the GPG key is actually in the list of the GitHub User's associated Keys
-    // using the GitHub API
-    async fn process_registration_request(
-        &mut self,
-        signature: Vec<Mpi>,
-        data: Vec<u8>,
-        public_key: String,
-        identity: Identity,
-        username: GitHubUser,
-    ) {
-        let mut signer = GpgSigner {
-            secret_key_asc_path: None,
-            public_key_asc_string: Some(public_key),
-            signed_secret_key: None,
-            signed_public_key: None,
-        };
-        signer.init_verifier();
-        // verify that the key exists in the Username's Raw Key List
-        let raw_gpg_keys: Vec<String> =
-            query_user_gpg_keys(env::var("GITHUB_TOKEN").unwrap()).await;
-        assert!(raw_gpg_keys.contains(&signer.public_key_asc_string.clone().unwrap()));
-        assert!(signer.is_valid_signature(signature, &data));
-        if self.github_users.get(&username).is_some() {
-            panic!("{}: Duplicate Github User", "Receted".red().bold())
-        };
-        self.github_users.insert(username.clone());
-        self.tree_state.insert_nullifier(identity);
-        println!(
-            "{}{}: Github@{}",
-            "+++++++++ \n".yellow(),
-            "Accepted".bold().green(),
-            &username.bold().green()
-        )
+async fn vote_status(
+    Path((poll_id, job)): Path<(PollId, JobId)>,
+    Query(query): Query<VoteQuery>,
+    Extension(jobs): Extension<Arc<Jobs>>,
+) -> Response {
+    match jobs.subscribe(&poll_id, &job) {
+        Some(status) => {
+            let status: JobStatus =
+                wait_for(status, Duration::from_secs(query.wait).min(MAX_VOTE_WAIT)).await;
+            Json(VoteJob {
+                job,
+                poll: poll_id,
+                status,
+            })
+            .into_response()
+        }
+        None => (StatusCode::NOT_FOUND, "Unknown job").into_response(),
     }
 }
 
-fn default_tree_state() -> TreeState {
-    let mut voting_tree: VotingTree = VotingTree {
-        zero_node: hash(CryptoHasherSha256, &vec![0; 32]),
-        zero_levels: Vec::new(),
-        // size must equal tree depth
-        filled: vec![vec![]; 5],
-        root: None,
-        index: 0,
-        // the maximum amount of identities this tree can store
-        // is 2^depth (depth:5 => max_identity_count:32)
-        depth: 5,
+// runs once the worker pool verified the proof, `accept_vote` repeats the root and nullifier
+// checks since other votes may have been accepted during the verification
+async fn finish_vote(
+    state: Arc<Mutex<ServiceState>>,
+    jobs: Arc<Jobs>,
+    poll_id: PollId,
+    job: JobId,
+    verified: oneshot::Receiver<Result<CircuitOutputs, ProofError>>,
+) {
+    let accepted: Result<String, VoteError> = match verified.await {
+        Ok(Ok(outputs)) => state.lock().await.accept_vote(&poll_id, outputs),
+        Ok(Err(error)) => Err(VoteError::Proof(error)),
+        Err(_) => Err(VoteError::Proof(ProofError::Invalid(
+            "Verification worker failed".to_string(),
+        ))),
     };
-    voting_tree.calculate_zero_levels();
+    let status: JobStatus = match accepted {
+        Ok(ticket) => {
+            metrics().vote(&poll_id, ACCEPTED);
+            JobStatus::Accepted { ticket }
+        }
+        Err(error) => rejected(&poll_id, error),
+    };
+    jobs.set(&job, status);
+}
 
-    TreeState {
-        root_history: Vec::new(),
-        used_nullifiers: Vec::new(),
-        voting_tree,
-        leafs: Vec::new(),
+async fn prometheus_metrics(Extension(state): Extension<Arc<Mutex<ServiceState>>>) -> Response {
+    for poll in state.lock().await.polls() {
+        let tree_state: &TreeState = poll.tree();
+        metrics().set_tree(
+            &poll.config.id,
+            tree_state.leafs.len(),
+            poll.config.capacity(),
+            tree_state.root_history.len(),
+        );
+    }
+    match metrics().encode() {
+        Ok(body) => ([(CONTENT_TYPE, prometheus::TEXT_FORMAT)], body).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
     }
 }
 
-// `&'static str` becomes a `200 OK` with `content-type: text/plain; charset=utf-8`
-async fn ping() -> &'static str {
-    "pong"
+fn rejected(poll_id: &str, error: VoteError) -> JobStatus {
+    if !matches!(error, VoteError::UnknownPoll) {
+        metrics().vote(poll_id, error.reason());
+    }
+    info!(poll = %poll_id, reason = %error, "vote rejected");
+    JobStatus::Rejected {
+        reason: error.to_string(),
+    }
 }
 
-#[tokio::main]
-async fn main() {
-    let mut stdout = io::stdout();
-    execute!(stdout, Clear(crossterm::terminal::ClearType::All)).unwrap();
-    print!(
-        "{}",
-        r#"
- ██████╗██╗   ██╗██████╗ ██╗  ██╗███████╗██████╗     ██████╗  ██████╗ ██╗     ██╗     
-██╔════╝╚██╗ ██╔╝██╔══██╗██║  ██║██╔════╝██╔══██╗    ██╔══██╗██╔═══██╗██║     ██║     
-██║      ╚████╔╝ ██████╔╝███████║█████╗  ██████╔╝    ██████╔╝██║   ██║██║     ██║     
-██║       ╚██╔╝  ██╔═══╝ ██╔══██║██╔══╝  ██╔══██╗    ██╔═══╝ ██║   ██║██║     ██║     
-╚██████╗   ██║   ██║     ██║  ██║███████╗██║  ██║    ██║     ╚██████╔╝███████╗███████╗
- ╚═════╝   ╚═╝   ╚═╝     ╚═╝  ╚═╝╚══════╝╚═╝  ╚═╝    ╚═╝      ╚═════╝ ╚══════╝╚══════╝
-"#
-        .red()
-    );
-    println!(
-        "{}",
-        " by Jonas Pauli, Casper Association "
-            .bold()
-            .white()
-            .bold()
-            .on_red()
-    );
-    let tree_state: TreeState = default_tree_state();
-    let service_state: ServiceState = ServiceState {
-        github_users: InMemoryGitHubUserState {
-            github_users: HashSet::new(),
-        },
-        tree_state: InMemoryTreeState { tree_state },
-        votes: InMemoryVoteState {
-            votes: HashMap::new(),
+async fn poll_info(
+    Path(poll_id): Path<PollId>,
+    Extension(state): Extension<Arc<Mutex<ServiceState>>>,
+) -> Response {
+    match state.lock().await.poll(&poll_id) {
+        Some(poll) => Json(poll.info()).into_response(),
+        None => (StatusCode::NOT_FOUND, format!("Unknown poll {}", poll_id)).into_response(),
+    }
+}
+
+async fn results(
+    Path(poll_id): Path<PollId>,
+    Extension(state): Extension<Arc<Mutex<ServiceState>>>,
+) -> Response {
+    match state.lock().await.poll(&poll_id) {
+        Some(poll) => Json(poll.results()).into_response(),
+        None => (StatusCode::NOT_FOUND, format!("Unknown poll {}", poll_id)).into_response(),
+    }
+}
+
+// the snapshot of a leaf as of the current tree, registrations revoked before voting opened
+// change the roots of all later leaves
+async fn snapshot(
+    Path((poll_id, index)): Path<(PollId, usize)>,
+    Extension(state): Extension<Arc<Mutex<ServiceState>>>,
+) -> Response {
+    match state.lock().await.poll(&poll_id) {
+        Some(poll) => match poll.snapshot(index) {
+            Some(snapshot) => Json(snapshot).into_response(),
+            None => (StatusCode::NOT_FOUND, "Unknown leaf").into_response(),
         },
-    };
-    let shared_state = Arc::new(Mutex::new(service_state));
-    let app = Router::new()
-        .route(
-            "/ping",
-            get({
-                //let shared_state = Arc::clone(&shared_state);
-                move || ping()
-            }),
-        )
-        .route("/register", post(register))
-        .route("/vote", post(vote))
-        .layer(DefaultBodyLimit::max(10000000))
-        .layer(Extension(shared_state));
-    let listener = tokio::net::TcpListener::bind("127.0.0.1:8080")
-        .await
-        .unwrap();
-    axum::serve(listener, app).await.unwrap();
+        None => (StatusCode::NOT_FOUND, format!("Unknown poll {}", poll_id)).into_response(),
+    }
 }
 
-async fn register(
+// lets a voter confirm that their vote was recorded without revealing which one it is
+async fn ticket(
+    Path((poll_id, ticket)): Path<(PollId, String)>,
     Extension(state): Extension<Arc<Mutex<ServiceState>>>,
-    Json(payload): Json<IdentityPayload>,
-) -> impl IntoResponse {
-    let mut deserialized_signature: Vec<Mpi> = Vec::new();
-    for series in &payload.signature_serialized {
-        deserialized_signature.push(Mpi::from_slice(series))
+) -> Response {
+    match state.lock().await.poll(&poll_id) {
+        Some(poll) if poll.has_ticket(&ticket) => (StatusCode::OK, "Vote recorded").into_response(),
+        Some(_) => (StatusCode::NOT_FOUND, "Unknown ticket").into_response(),
+        None => (StatusCode::NOT_FOUND, format!("Unknown poll {}", poll_id)).into_response(),
     }
-    state
-        .lock()
-        .await
-        .tree_state
-        .get()
-        .voting_tree
-        .add_leaf(payload.identity.clone());
-    state
-        .lock()
-        .await
-        .process_registration_request(
-            deserialized_signature,
-            payload.data_serialized,
-            payload.public_key_string,
-            payload.identity.clone(),
-            payload.username,
+}
+
+// the accepted tree roots, oldest first, a proof may only reference these
+async fn roots(
+    Path(poll_id): Path<PollId>,
+    Extension(state): Extension<Arc<Mutex<ServiceState>>>,
+) -> Response {
+    match state.lock().await.poll(&poll_id) {
+        Some(poll) => Json(
+            poll.tree()
+                .root_history
+                .iter()
+                .map(|root| to_hex(root))
+                .collect::<Vec<String>>(),
         )
-        .await;
-    let snapshot_serialized: Vec<u8> =
-        serde_json::to_vec(&state.lock().await.tree_state.get().voting_tree)
-            .expect("Failed to serialize snapshot");
-    (StatusCode::OK, snapshot_serialized)
+        .into_response(),
+        None => (StatusCode::NOT_FOUND, format!("Unknown poll {}", poll_id)).into_response(),
+    }
 }
 
-async fn vote(
+async fn reveal(
+    Path(poll_id): Path<PollId>,
     Extension(state): Extension<Arc<Mutex<ServiceState>>>,
-    Json(payload): Json<Receipt>,
-) -> impl IntoResponse {
-    let current_state = state.lock().await.tree_state.get();
-    let (vote, nullifier): (String, Vec<u8>) = verify_vote(
-        payload,
-        current_state.root_history,
-        current_state.used_nullifiers,
-    );
-    state.lock().await.votes.insert(vote.clone());
-    state
-        .lock()
-        .await
-        .tree_state
-        .insert_used_nullifier(nullifier);
-    println!(
-        "{}{}: Anonymous User voted for {}",
-        "+++++++++ \n".yellow(),
-        "Accepted".bold().green(),
-        &vote.bold().red()
-    );
-    println!(
-        "{}Current State of the Election: {:?}",
-        "+++++++++ \n".yellow(),
-        &state.lock().await.votes.votes
-    );
-    (
-        StatusCode::OK,
-        format!("Accepted: Anonymous User voted for {}", &vote),
-    )
+    Json(payload): Json<RevealPayload>,
+) -> Response {
+    let result: Result<(), RevealError> = state.lock().await.reveal(&poll_id, payload);
+    match result {
+        Ok(()) => StatusCode::OK.into_response(),
+        Err(error) => {
+            info!(poll = %poll_id, reason = %error, "reveal rejected");
+            (error.status(), error.to_string()).into_response()
+        }
+    }
+}
+
+// the shares carry their own proofs, a trustee needs no further authentication
+async fn decryption_shares(
+    Path(poll_id): Path<PollId>,
+    Extension(state): Extension<Arc<Mutex<ServiceState>>>,
+    Json(shares): Json<TrusteeShares>,
+) -> Response {
+    let trustee: u32 = shares.index;
+    let result: Result<(), DecryptionError> = state.lock().await.submit_shares(&poll_id, shares);
+    match result {
+        Ok(()) => StatusCode::OK.into_response(),
+        Err(error) => {
+            info!(poll = %poll_id, trustee, reason = %error, "decryption shares rejected");
+            (error.status(), error.to_string()).into_response()
+        }
+    }
 }
 
+// looks the test key up on GitHub and proves with risc0
 #[tokio::test]
+#[ignore = "needs GITHUB_TOKEN and network access"]
 async fn submit_zk_vote() {
+    use config::{GitHubConfig, PollConfig};
     use crypto::identity::UniqueIdentity;
-    #[cfg(not(feature = "groth16"))]
-    use risc0_prover::prover::prove_default as prover;
-    #[cfg(feature = "groth16")]
-    use risc0_prover::prover::prove_groth16 as prover;
-    use risc0_types::CircuitInputs;
-    use risc0_zkvm::Receipt;
-    use std::{collections::HashSet, fs, path::PathBuf};
+    use risc0_prover::prover::default_vote_prover;
+    use risc0_types::{Ballot, CircuitInputs};
+    use std::{fs, path::PathBuf};
     // initialize tree_state and service_state
-    // process a registration request using the default keypair in ~/resources/test/
+    // process a registration request using the default keypair in resources/test/
     // generate a vote proof
     // verify the vote proof and apply the vote to tree_state
-    let tree_state: TreeState = default_tree_state();
-    let mut service_state: ServiceState = ServiceState {
-        github_users: InMemoryGitHubUserState {
-            github_users: HashSet::new(),
-        },
-        tree_state: InMemoryTreeState {
-            tree_state: tree_state,
-        },
-        votes: InMemoryVoteState {
-            votes: HashMap::new(),
-        },
-    };
+    let github: GitHubClient =
+        GitHubClient::new(&GitHubConfig::default()).expect("Missing GITHUB_TOKEN");
+    let mut service_state: ServiceState = state::test_poll(PollConfig::default());
     let mut identity: UniqueIdentity = UniqueIdentity {
         identity: None,
         nullifier: None,
     };
     identity.generate_nullifier("Hello".to_string());
 
-    let private_key_path_str = "/Users/chef/Desktop/cypher-poll/resources/test/key.sec.asc";
-    let public_key_path_str = "/Users/chef/Desktop/cypher-poll/resources/test/key.asc";
-
+    let keys: PathBuf = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../resources/test");
     let public_key_string: String =
-        fs::read_to_string(public_key_path_str).expect("Failed to read public key");
+        fs::read_to_string(keys.join("key.asc")).expect("Failed to read public key");
 
     let mut signer = GpgSigner {
-        secret_key_asc_path: Some(PathBuf::from(private_key_path_str)),
+        secret_key_asc_path: Some(keys.join("key.sec.asc")),
         public_key_asc_string: Some(public_key_string.clone()),
         signed_secret_key: None,
         signed_public_key: None,
     };
     signer.init();
-    let data: Vec<u8> = vec![0u8];
+    identity.compute_public_identity(
+        signer.signed_public_key.clone().unwrap(),
+        "Overlord".to_string(),
+    );
+    let username: String = "jonas089".to_string();
+    // the identity is computed first, the challenge is bound to it
+    let data: Vec<u8> = service_state
+        .issue_challenge(
+            "test",
+            &username,
+            identity.identity.as_ref().expect("Missing identity"),
+        )
+        .expect("Failed to issue challenge");
     let signature: Vec<Mpi> = signer.sign_bytes(&data);
 
     let mut serialized_signature: Vec<Vec<u8>> = Vec::new();
@@ -338,28 +652,62 @@ async fn submit_zk_vote() {
     assert_eq!(&signature, &deserialized_signature);
 
     assert!(signer.is_valid_signature(signature.clone(), &data));
-    identity.compute_public_identity(signer.signed_public_key.unwrap(), "Overlord".to_string());
     // register the voter
     service_state
-        .process_registration_request(
-            signature,
-            data,
-            public_key_string.clone(),
+        .check_registration("test", &username)
+        .expect("Failed to register");
+    service_state
+        .redeem_challenge(
+            "test",
+            &username,
+            identity.identity.as_ref().expect("Missing identity"),
+            &data,
+        )
+        .expect("Failed to redeem challenge");
+    verify_registration(
+        &github,
+        None,
+        signature,
+        &data,
+        public_key_string.clone(),
+        &username,
+    )
+    .await
+    .expect("Failed to register");
+    let snapshot: VotingTree = service_state
+        .register(
+            "test",
+            &username,
             identity.identity.clone().expect("Missing identity"),
-            "jonas089".to_string(),
+            1,
         )
-        .await;
+        .expect("Failed to register");
     // generate a proof -> redeem the nullifier
-    let proof: Receipt = prover(CircuitInputs {
-        root_history: service_state.tree_state.get().root_history,
-        snapshot: service_state.tree_state.get().voting_tree,
-        nullifier: identity.nullifier.clone().expect("Missing Nullifier"),
-        vote: "Overlord".to_string(),
-        public_key_string: public_key_string.clone(),
-    });
-    verify_vote(
-        proof,
-        service_state.tree_state.get().root_history,
-        service_state.tree_state.get().used_nullifiers,
-    );
+    let proof: ProofEnvelope = default_vote_prover()
+        .prove(CircuitInputs {
+            root_history: service_state
+                .poll("test")
+                .unwrap()
+                .tree()
+                .root_history
+                .clone(),
+            snapshot,
+            nullifier: identity.nullifier.clone().expect("Missing Nullifier"),
+            ballot: Ballot::Plurality {
+                choice: "Overlord".to_string(),
+            },
+            spec: service_state.poll("test").unwrap().config.spec(),
+            weight: 1,
+            sequence: 0,
+            randomness: Vec::new(),
+            salt: Vec::new(),
+            public_key_string: public_key_string.clone(),
+        })
+        .expect("Failed to generate proof");
+    let outputs = Risc0Verifier
+        .verify(&proof)
+        .expect("Failed to verify proof");
+    service_state
+        .accept_vote("test", outputs)
+        .expect("Rejected vote");
 }
@@ -0,0 +1,148 @@
+// prometheus metrics, served under /metrics
+// labels are limited to configured poll ids, fixed rejection reasons and proof backends,
+// vote choices are never part of a metric
+use prometheus::{
+    Histogram, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use std::sync::OnceLock;
+
+pub const ACCEPTED: &str = "accepted";
+
+pub struct Metrics {
+    registry: Registry,
+    pub registrations: IntCounterVec,
+    pub votes: IntCounterVec,
+    pub admission: IntCounterVec,
+    pub verification_seconds: HistogramVec,
+    pub github_seconds: Histogram,
+    pub tree_leaves: IntGaugeVec,
+    pub tree_capacity: IntGaugeVec,
+    pub root_history: IntGaugeVec,
+}
+
+static METRICS: OnceLock<Metrics> = OnceLock::new();
+
+pub fn metrics() -> &'static Metrics {
+    METRICS.get_or_init(Metrics::new)
+}
+
+impl Metrics {
+    fn new() -> Metrics {
+        let registry: Registry = Registry::new_custom(Some("cypher_poll".to_string()), None)
+            .expect("Valid metric prefix");
+        let registrations = IntCounterVec::new(
+            Opts::new(
+                "registrations_total",
+                "Registration requests by poll and outcome",
+            ),
+            &["poll", "outcome"],
+        )
+        .unwrap();
+        let votes = IntCounterVec::new(
+            Opts::new("votes_total", "Vote submissions by poll and outcome"),
+            &["poll", "outcome"],
+        )
+        .unwrap();
+        let admission = IntCounterVec::new(
+            Opts::new(
+                "admission_rejections_total",
+                "Requests rejected by rate limits or a full verification queue",
+            ),
+            &["reason"],
+        )
+        .unwrap();
+        // stark verification takes from a few hundred ms up to several seconds
+        let verification_seconds = HistogramVec::new(
+            HistogramOpts::new("proof_verification_seconds", "Proof verification latency").buckets(
+                vec![0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0],
+            ),
+            &["backend"],
+        )
+        .unwrap();
+        let github_seconds = Histogram::with_opts(HistogramOpts::new(
+            "github_lookup_seconds",
+            "GitHub gpg key lookup latency",
+        ))
+        .unwrap();
+        let tree_leaves = IntGaugeVec::new(
+            Opts::new("tree_leaves", "Registered identities per poll"),
+            &["poll"],
+        )
+        .unwrap();
+        let tree_capacity = IntGaugeVec::new(
+            Opts::new("tree_capacity", "Maximum identities per poll"),
+            &["poll"],
+        )
+        .unwrap();
+        let root_history = IntGaugeVec::new(
+            Opts::new("root_history_size", "Accepted tree roots per poll"),
+            &["poll"],
+        )
+        .unwrap();
+        registry.register(Box::new(registrations.clone())).unwrap();
+        registry.register(Box::new(votes.clone())).unwrap();
+        registry.register(Box::new(admission.clone())).unwrap();
+        registry
+            .register(Box::new(verification_seconds.clone()))
+            .unwrap();
+        registry.register(Box::new(github_seconds.clone())).unwrap();
+        registry.register(Box::new(tree_leaves.clone())).unwrap();
+        registry.register(Box::new(tree_capacity.clone())).unwrap();
+        registry.register(Box::new(root_history.clone())).unwrap();
+        Metrics {
+            registry,
+            registrations,
+            votes,
+            admission,
+            verification_seconds,
+            github_seconds,
+            tree_leaves,
+            tree_capacity,
+            root_history,
+        }
+    }
+
+    pub fn registration(&self, poll: &str, outcome: &str) {
+        self.registrations.with_label_values(&[poll, outcome]).inc();
+    }
+
+    pub fn vote(&self, poll: &str, outcome: &str) {
+        self.votes.with_label_values(&[poll, outcome]).inc();
+    }
+
+    pub fn admission_rejections(&self, reason: &str) {
+        self.admission.with_label_values(&[reason]).inc();
+    }
+
+    // gauges are refreshed from the poll state on every scrape
+    pub fn set_tree(&self, poll: &str, leaves: usize, capacity: usize, roots: usize) {
+        self.tree_leaves
+            .with_label_values(&[poll])
+            .set(leaves as i64);
+        self.tree_capacity
+            .with_label_values(&[poll])
+            .set(capacity as i64);
+        self.root_history
+            .with_label_values(&[poll])
+            .set(roots as i64);
+    }
+
+    /// the prometheus text exposition format
+    pub fn encode(&self) -> Result<String, prometheus::Error> {
+        TextEncoder::new().encode_to_string(&self.registry.gather())
+    }
+}
+
+#[test]
+fn test_metrics_encoding() {
+    let metrics: &Metrics = metrics();
+    metrics.registration("test", ACCEPTED);
+    metrics.vote("test", "duplicate_nullifier");
+    metrics.set_tree("test", 3, 32, 3);
+    let encoded: String = metrics.encode().unwrap();
+    assert!(encoded.contains("cypher_poll_registrations_total{outcome=\"accepted\",poll=\"test\"}"));
+    assert!(
+        encoded.contains("cypher_poll_votes_total{outcome=\"duplicate_nullifier\",poll=\"test\"}")
+    );
+    assert!(encoded.contains("cypher_poll_tree_capacity{poll=\"test\"} 32"));
+}
@@ -0,0 +1,160 @@
+// outcome rules
+// decide a closed poll from its final counts: quorum first, then the leader, a tie-break if
+// the leaders are tied, and the supermajority the winner needs
+use crate::config::{OutcomeRules, TieBreak};
+use client::types::{Decision, Outcome};
+use crypto::{hash, CryptoHasherSha256};
+use std::collections::HashMap;
+
+// the final counts of a poll, the last runoff round of a ranked poll
+pub struct FinalCount<'a> {
+    pub counts: &'a HashMap<String, u64>,
+    // what the share of the winner is relative to
+    pub total: u64,
+    // ballots that were counted, unrevealed ballots are not
+    pub ballots: u64,
+    pub registered: u64,
+}
+
+pub fn evaluate(
+    rules: &OutcomeRules,
+    count: &FinalCount,
+    options: &[String],
+    // what a lot is drawn from, a hash of the accepted nullifiers
+    seed: &[u8],
+) -> Outcome {
+    let turnout: f64 = match count.registered {
+        0 => 0.0,
+        registered => count.ballots as f64 / registered as f64,
+    };
+    let outcome = |decision: Decision| Outcome {
+        turnout,
+        counted: count.ballots,
+        decision,
+    };
+    if count.ballots == 0
+        || rules.min_turnout.is_some_and(|min| turnout < min)
+        || rules.min_ballots.is_some_and(|min| count.ballots < min)
+    {
+        return outcome(Decision::NoQuorum);
+    }
+    let most: u64 = count.counts.values().copied().max().unwrap_or(0);
+    let mut leaders: Vec<&String> = count
+        .counts
+        .iter()
+        .filter(|(_, votes)| **votes == most)
+        .map(|(option, _)| option)
+        .collect();
+    leaders.sort();
+    let tie_break: bool = leaders.len() > 1;
+    let winner: &String = match (tie_break, rules.tie_break) {
+        (false, _) => leaders[0],
+        (true, TieBreak::None) => {
+            return outcome(Decision::Tie {
+                options: leaders.into_iter().cloned().collect(),
+            })
+        }
+        // open plurality polls have no option list, the leaders are sorted
+        (true, TieBreak::OptionOrder) => options
+            .iter()
+            .find(|option| leaders.contains(option))
+            .unwrap_or(leaders[0]),
+        (true, TieBreak::Lot) => leaders
+            .into_iter()
+            .min_by_key(|option| {
+                let mut payload: Vec<u8> = b"cypher-poll-lot".to_vec();
+                payload.extend_from_slice(seed);
+                payload.extend_from_slice(option.as_bytes());
+                hash(CryptoHasherSha256, &payload)
+            })
+            .unwrap(),
+    };
+    let share: f64 = match count.total {
+        0 => 0.0,
+        total => most as f64 / total as f64,
+    };
+    if rules.supermajority.is_some_and(|min| share < min) {
+        return outcome(Decision::NoSupermajority {
+            leader: winner.clone(),
+            share,
+        });
+    }
+    outcome(Decision::Winner {
+        option: winner.clone(),
+        share,
+        tie_break,
+    })
+}
+
+#[test]
+fn test_outcome_rules() {
+    let options: Vec<String> = ["a", "b", "c"].iter().map(|o| o.to_string()).collect();
+    let counts = |votes: &[u64]| -> HashMap<String, u64> {
+        options.iter().cloned().zip(votes.iter().copied()).collect()
+    };
+    let decide = |rules: &OutcomeRules, votes: &[u64], registered: u64| {
+        let counts = counts(votes);
+        let count = FinalCount {
+            counts: &counts,
+            total: votes.iter().sum(),
+            ballots: votes.iter().sum(),
+            registered,
+        };
+        evaluate(rules, &count, &options, &[7; 32])
+    };
+    let plain = OutcomeRules::default();
+    let decision = decide(&plain, &[5, 3, 2], 20);
+    assert_eq!(decision.turnout, 0.5);
+    assert_eq!(
+        decision.decision,
+        Decision::Winner {
+            option: "a".to_string(),
+            share: 0.5,
+            tie_break: false
+        }
+    );
+    // quorum
+    let quorum = OutcomeRules {
+        min_turnout: Some(0.6),
+        ..Default::default()
+    };
+    assert_eq!(decide(&quorum, &[5, 3, 2], 20).decision, Decision::NoQuorum);
+    assert_eq!(decide(&plain, &[0, 0, 0], 20).decision, Decision::NoQuorum);
+    // two thirds
+    let supermajority = OutcomeRules {
+        supermajority: Some(0.6667),
+        ..Default::default()
+    };
+    assert!(matches!(
+        decide(&supermajority, &[5, 3, 2], 20).decision,
+        Decision::NoSupermajority { .. }
+    ));
+    assert!(matches!(
+        decide(&supermajority, &[7, 2, 0], 20).decision,
+        Decision::Winner { .. }
+    ));
+    // ties
+    assert_eq!(
+        decide(&plain, &[1, 4, 4], 20).decision,
+        Decision::Tie {
+            options: vec!["b".to_string(), "c".to_string()]
+        }
+    );
+    let option_order = OutcomeRules {
+        tie_break: TieBreak::OptionOrder,
+        ..Default::default()
+    };
+    assert!(matches!(
+        decide(&option_order, &[1, 4, 4], 20).decision,
+        Decision::Winner { option, tie_break: true, .. } if option == "b"
+    ));
+    // the lot is reproducible from the final root
+    let lot = OutcomeRules {
+        tie_break: TieBreak::Lot,
+        ..Default::default()
+    };
+    assert_eq!(
+        decide(&lot, &[1, 4, 4], 20).decision,
+        decide(&lot, &[1, 4, 4], 20).decision
+    );
+}
@@ -0,0 +1,1752 @@
+// poll state
+// every change to a poll goes through one of the check-and-apply operations of ServiceState,
+// the caller holds the service lock for the whole operation, so two concurrent requests can
+// never both pass the duplicate checks before either of them is applied
+use crate::{
+    config::PollConfig,
+    eligibility::{self, EligibilityPolicy},
+    outcome::{self, FinalCount},
+    storage::Storage,
+    tally::{self, WeightedBallot},
+};
+use client::types::{
+    AllowlistEdit, Outcome, PollEvent, PollInfo, PollOverview, PollPhase, PollResults,
+    RevealPayload, TrusteeShares,
+};
+use crypto::{
+    elgamal::{combine, verify_share, Ciphertext, DecryptionShare, ElGamalError, Point},
+    hash,
+    identity::{weighted_leaf, Identity, Nullifier},
+    to_hex, CryptoHasherSha256,
+};
+use reqwest::StatusCode;
+use risc0_types::{Ballot, BallotError, BallotKind, CircuitOutputs, PublicBallot, SALT_LENGTH};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fmt, io,
+    time::{Duration, Instant},
+};
+use tokio::sync::broadcast;
+use tracing::{error, info, warn};
+use voting_tree::VotingTree;
+use zk_associated::{
+    proof::{check_outputs, ProofError},
+    storage::TreeState,
+};
+
+pub type GitHubUser = String;
+pub type PollId = String;
+
+// events a slow subscriber may fall behind by before it misses some
+const EVENT_BUFFER: usize = 1024;
+// a registration challenge is signed and submitted within this time, or requested again
+const CHALLENGE_TTL: Duration = Duration::from_secs(300);
+// unredeemed challenges kept at once, further requests wait for older ones to expire
+const MAX_CHALLENGES: usize = 10000;
+
+// issued for one registration, redeemed by it and never again
+struct IssuedChallenge {
+    poll_id: PollId,
+    username: GitHubUser,
+    identity: Identity,
+    issued_at: Instant,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct InMemoryTreeState {
+    tree_state: TreeState,
+}
+impl InMemoryTreeState {
+    fn insert_nullifier(&mut self, identity: Identity) {
+        let new_state = self.tree_state.insert_nullifier(identity);
+        self.tree_state = new_state;
+    }
+    fn insert_used_nullifier(&mut self, nullifier: Nullifier) {
+        self.tree_state.used_nullifiers.push(nullifier);
+    }
+}
+
+/// GitHub logins are case insensitive, users are keyed by the lowercase login
+pub fn login(username: &str) -> GitHubUser {
+    username.to_ascii_lowercase()
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct InMemoryGitHubUserState {
+    github_users: HashSet<GitHubUser>,
+    // the position of the leaf of every user, registrations are revoked by username
+    #[serde(default)]
+    positions: HashMap<GitHubUser, usize>,
+}
+impl InMemoryGitHubUserState {
+    fn insert(&mut self, user: &str, position: usize) {
+        self.positions.insert(login(user), position);
+        self.github_users.insert(login(user));
+    }
+    fn remove(&mut self, user: &str) -> Option<usize> {
+        self.github_users.remove(&login(user));
+        self.positions.remove(&login(user))
+    }
+    fn contains(&self, user: &str) -> bool {
+        self.github_users.contains(&login(user))
+    }
+}
+
+// the ballots themselves are kept, a ranked poll can't be tallied from running counts
+#[derive(Clone, Serialize, Deserialize)]
+struct InMemoryVoteState {
+    ballots: Vec<WeightedBallot>,
+    // revoting polls: hex tag -> (sequence, index in ballots) of the latest ballot of each voter
+    #[serde(default)]
+    latest: HashMap<String, (u64, usize)>,
+    // commit-reveal polls without revoting: hex nullifier -> index in ballots
+    #[serde(default)]
+    committed: HashMap<String, usize>,
+    // the tickets of all accepted ballots, random and not stored next to the ballot
+    #[serde(default)]
+    tickets: HashSet<String>,
+}
+impl InMemoryVoteState {
+    fn insert(&mut self, ballot: WeightedBallot) {
+        self.ballots.push(ballot);
+    }
+    fn commit(&mut self, nullifier: &[u8], ballot: WeightedBallot) {
+        self.committed.insert(to_hex(nullifier), self.ballots.len());
+        self.ballots.push(ballot);
+    }
+    // the index of the (latest) ballot published under a nullifier or tag
+    fn position(&self, tag: &[u8]) -> Option<usize> {
+        let tag: String = to_hex(tag);
+        self.latest
+            .get(&tag)
+            .map(|(_, index)| *index)
+            .or_else(|| self.committed.get(&tag).copied())
+    }
+    // the sequence of the ballot published under a tag, if it is still the latest of its voter
+    fn latest_sequence(&self, tag: &[u8]) -> Option<u64> {
+        self.latest.get(&to_hex(tag)).map(|(sequence, _)| *sequence)
+    }
+    // replaces the previous ballot of the voter in place, false if this is their first ballot.
+    // the ballot is kept under its own tag, which the next revote names as its previous one
+    fn revote(
+        &mut self,
+        tag: &[u8],
+        previous: &[u8],
+        sequence: u64,
+        ballot: WeightedBallot,
+    ) -> bool {
+        match self.latest.remove(&to_hex(previous)) {
+            Some((_, index)) => {
+                self.ballots[index] = ballot;
+                self.latest.insert(to_hex(tag), (sequence, index));
+                true
+            }
+            None => {
+                self.latest
+                    .insert(to_hex(tag), (sequence, self.ballots.len()));
+                self.ballots.push(ballot);
+                false
+            }
+        }
+    }
+}
+
+// the decryption shares of an encrypted poll, one share per option for each trustee
+#[derive(Clone, Default, Serialize, Deserialize)]
+struct InMemoryDecryptionState {
+    shares: BTreeMap<u32, Vec<DecryptionShare>>,
+    // decrypted once threshold trustees contributed
+    tally: Option<HashMap<String, u64>>,
+}
+
+#[derive(Debug)]
+pub enum RegistrationError {
+    UnknownPoll,
+    InvalidUsername,
+    GitHub(String),
+    // the public key could not be parsed
+    InvalidKey,
+    // the signed data is not an unused challenge issued for this registration
+    InvalidChallenge,
+    TooManyChallenges,
+    UnknownKey,
+    InvalidSignature,
+    // rejected by the eligibility policy of the poll
+    Ineligible(String),
+    DuplicateUser,
+    TreeFull,
+    Closed,
+}
+
+impl RegistrationError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            RegistrationError::UnknownPoll => StatusCode::NOT_FOUND,
+            RegistrationError::GitHub(_) => StatusCode::BAD_GATEWAY,
+            RegistrationError::TooManyChallenges => StatusCode::SERVICE_UNAVAILABLE,
+            RegistrationError::Ineligible(_) => StatusCode::FORBIDDEN,
+            RegistrationError::DuplicateUser
+            | RegistrationError::TreeFull
+            | RegistrationError::Closed => StatusCode::CONFLICT,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+    pub fn reason(&self) -> &'static str {
+        match self {
+            RegistrationError::UnknownPoll => "unknown_poll",
+            RegistrationError::InvalidUsername => "invalid_username",
+            RegistrationError::GitHub(_) => "github",
+            RegistrationError::InvalidKey => "invalid_key",
+            RegistrationError::InvalidChallenge => "invalid_challenge",
+            RegistrationError::TooManyChallenges => "too_many_challenges",
+            RegistrationError::UnknownKey => "unknown_key",
+            RegistrationError::InvalidSignature => "invalid_signature",
+            RegistrationError::Ineligible(_) => "ineligible",
+            RegistrationError::DuplicateUser => "duplicate_user",
+            RegistrationError::TreeFull => "tree_full",
+            RegistrationError::Closed => "closed",
+        }
+    }
+}
+
+impl fmt::Display for RegistrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegistrationError::UnknownPoll => write!(f, "Unknown poll"),
+            RegistrationError::InvalidUsername => write!(f, "Invalid GitHub username"),
+            RegistrationError::GitHub(e) => write!(f, "GitHub lookup failed: {}", e),
+            RegistrationError::InvalidKey => write!(f, "Invalid public key"),
+            RegistrationError::InvalidChallenge => {
+                write!(f, "Unknown, expired or already used challenge")
+            }
+            RegistrationError::TooManyChallenges => write!(f, "Too many pending challenges"),
+            RegistrationError::UnknownKey => {
+                write!(f, "Public key is not associated with the GitHub account")
+            }
+            RegistrationError::InvalidSignature => write!(f, "Invalid signature"),
+            RegistrationError::Ineligible(reason) => write!(f, "Not eligible: {}", reason),
+            RegistrationError::DuplicateUser => write!(f, "Duplicate Github User"),
+            RegistrationError::TreeFull => write!(f, "Poll has no free leaves left"),
+            RegistrationError::Closed => write!(f, "Poll is closed"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum VoteError {
+    UnknownPoll,
+    // the poll is still in its registration phase
+    NotOpen,
+    Closed,
+    Proof(ProofError),
+    // the proof was made for a different ballot kind or option list
+    SpecMismatch,
+    InvalidBallot(BallotError),
+    // a revoting voter must number their ballots 0, 1, 2, ...
+    Sequence { expected: u64 },
+    // the ballot a revote replaces was replaced already, or never accepted
+    NotLatest,
+}
+
+impl VoteError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            VoteError::UnknownPoll => StatusCode::NOT_FOUND,
+            VoteError::NotOpen
+            | VoteError::Closed
+            | VoteError::Sequence { .. }
+            | VoteError::NotLatest => StatusCode::CONFLICT,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+    pub fn reason(&self) -> &'static str {
+        match self {
+            VoteError::UnknownPoll => "unknown_poll",
+            VoteError::NotOpen => "not_open",
+            VoteError::Closed => "closed",
+            VoteError::Proof(error) => error.reason(),
+            VoteError::SpecMismatch => "spec_mismatch",
+            VoteError::InvalidBallot(_) => "invalid_ballot",
+            VoteError::Sequence { .. } => "sequence",
+            VoteError::NotLatest => "not_latest",
+        }
+    }
+}
+
+impl fmt::Display for VoteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VoteError::UnknownPoll => write!(f, "Unknown poll"),
+            VoteError::NotOpen => write!(f, "Voting has not opened yet"),
+            VoteError::Closed => write!(f, "Poll is closed"),
+            VoteError::Proof(error) => write!(f, "{}", error),
+            VoteError::SpecMismatch => write!(f, "Proof was made for a different poll spec"),
+            VoteError::InvalidBallot(error) => write!(f, "Invalid ballot: {}", error),
+            VoteError::Sequence { expected } => {
+                write!(f, "Expected ballot sequence {}", expected)
+            }
+            VoteError::NotLatest => write!(f, "Replaced ballot is not the latest of the voter"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum DecryptionError {
+    UnknownPoll,
+    NotEncrypted,
+    // only the final tally is ever decrypted
+    Open,
+    UnknownTrustee,
+    DuplicateTrustee,
+    ShareCount { expected: usize },
+    InvalidShare(ElGamalError),
+    // the stored ballots could not be summed or the sum decrypted
+    Tally(ElGamalError),
+}
+
+impl DecryptionError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            DecryptionError::UnknownPoll => StatusCode::NOT_FOUND,
+            DecryptionError::Open | DecryptionError::DuplicateTrustee => StatusCode::CONFLICT,
+            DecryptionError::Tally(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+    pub fn reason(&self) -> &'static str {
+        match self {
+            DecryptionError::UnknownPoll => "unknown_poll",
+            DecryptionError::NotEncrypted => "not_encrypted",
+            DecryptionError::Open => "open",
+            DecryptionError::UnknownTrustee => "unknown_trustee",
+            DecryptionError::DuplicateTrustee => "duplicate_trustee",
+            DecryptionError::ShareCount { .. } => "share_count",
+            DecryptionError::InvalidShare(_) => "invalid_share",
+            DecryptionError::Tally(_) => "tally",
+        }
+    }
+}
+
+impl fmt::Display for DecryptionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecryptionError::UnknownPoll => write!(f, "Unknown poll"),
+            DecryptionError::NotEncrypted => write!(f, "Poll is not encrypted"),
+            DecryptionError::Open => write!(f, "Poll is still open"),
+            DecryptionError::UnknownTrustee => write!(f, "Unknown trustee"),
+            DecryptionError::DuplicateTrustee => {
+                write!(f, "Trustee already submitted their shares")
+            }
+            DecryptionError::ShareCount { expected } => {
+                write!(f, "Expected {} decryption shares", expected)
+            }
+            DecryptionError::InvalidShare(error) => write!(f, "{}", error),
+            DecryptionError::Tally(error) => write!(f, "Failed to decrypt the tally: {}", error),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RevealError {
+    UnknownPoll,
+    NotCommitReveal,
+    // ballots are only opened between closes_at and reveal_closes_at
+    Phase,
+    UnknownCommitment,
+    AlreadyRevealed,
+    // the ballot and salt don't open the commitment
+    Mismatch,
+    InvalidBallot(BallotError),
+}
+
+impl RevealError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            RevealError::UnknownPoll | RevealError::UnknownCommitment => StatusCode::NOT_FOUND,
+            RevealError::Phase | RevealError::AlreadyRevealed => StatusCode::CONFLICT,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+    pub fn reason(&self) -> &'static str {
+        match self {
+            RevealError::UnknownPoll => "unknown_poll",
+            RevealError::NotCommitReveal => "not_commit_reveal",
+            RevealError::Phase => "phase",
+            RevealError::UnknownCommitment => "unknown_commitment",
+            RevealError::AlreadyRevealed => "already_revealed",
+            RevealError::Mismatch => "mismatch",
+            RevealError::InvalidBallot(_) => "invalid_ballot",
+        }
+    }
+}
+
+impl fmt::Display for RevealError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RevealError::UnknownPoll => write!(f, "Unknown poll"),
+            RevealError::NotCommitReveal => write!(f, "Poll does not commit to ballots"),
+            RevealError::Phase => write!(f, "Poll is not in its reveal phase"),
+            RevealError::UnknownCommitment => write!(f, "No ballot was committed to"),
+            RevealError::AlreadyRevealed => write!(f, "Ballot was already revealed"),
+            RevealError::Mismatch => write!(f, "Ballot and salt don't match the commitment"),
+            RevealError::InvalidBallot(error) => write!(f, "Invalid ballot: {}", error),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum AdminError {
+    UnknownPoll,
+    DuplicatePoll,
+    InvalidDefinition(String),
+    // phases only move forward, commit-reveal polls pass through their reveal phase
+    Transition { from: PollPhase, to: PollPhase },
+    // registrations can only be revoked before voting opens
+    Revocation,
+    UnknownRegistration,
+}
+
+impl AdminError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AdminError::UnknownPoll | AdminError::UnknownRegistration => StatusCode::NOT_FOUND,
+            AdminError::DuplicatePoll | AdminError::Transition { .. } | AdminError::Revocation => {
+                StatusCode::CONFLICT
+            }
+            AdminError::InvalidDefinition(_) => StatusCode::BAD_REQUEST,
+        }
+    }
+    pub fn reason(&self) -> &'static str {
+        match self {
+            AdminError::UnknownPoll => "unknown_poll",
+            AdminError::DuplicatePoll => "duplicate_poll",
+            AdminError::InvalidDefinition(_) => "invalid_definition",
+            AdminError::Transition { .. } => "transition",
+            AdminError::Revocation => "revocation",
+            AdminError::UnknownRegistration => "unknown_registration",
+        }
+    }
+}
+
+impl fmt::Display for AdminError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AdminError::UnknownPoll => write!(f, "Unknown poll"),
+            AdminError::DuplicatePoll => write!(f, "Poll already exists"),
+            AdminError::InvalidDefinition(reason) => write!(f, "Invalid poll: {}", reason),
+            AdminError::Transition { from, to } => {
+                write!(f, "Poll can't move from {:?} to {:?}", from, to)
+            }
+            AdminError::Revocation => {
+                write!(f, "Registrations can only be revoked before voting opens")
+            }
+            AdminError::UnknownRegistration => write!(f, "User is not registered"),
+        }
+    }
+}
+
+impl From<ProofError> for VoteError {
+    fn from(error: ProofError) -> Self {
+        VoteError::Proof(error)
+    }
+}
+
+// the state of a single poll, the config of a configured poll is replaced by the one
+// in the config file on restore, a poll created through the admin api keeps its own
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PollState {
+    #[serde(default)]
+    pub config: PollConfig,
+    #[serde(default)]
+    created: bool,
+    #[serde(default)]
+    phase: PollPhase,
+    // only these users may register if set
+    #[serde(default)]
+    allowlist: Option<BTreeSet<GitHubUser>>,
+    github_users: InMemoryGitHubUserState,
+    tree_state: InMemoryTreeState,
+    votes: InMemoryVoteState,
+    #[serde(default)]
+    decryption: InMemoryDecryptionState,
+}
+impl PollState {
+    pub fn new(config: PollConfig) -> PollState {
+        PollState {
+            created: false,
+            phase: match config.registration_phase {
+                true => PollPhase::Registration,
+                false => PollPhase::Open,
+            },
+            allowlist: None,
+            github_users: InMemoryGitHubUserState {
+                github_users: HashSet::new(),
+                positions: HashMap::new(),
+            },
+            tree_state: InMemoryTreeState {
+                tree_state: default_tree_state(config.tree_depth),
+            },
+            votes: InMemoryVoteState {
+                ballots: Vec::new(),
+                latest: HashMap::new(),
+                committed: HashMap::new(),
+                tickets: HashSet::new(),
+            },
+            decryption: InMemoryDecryptionState::default(),
+            config,
+        }
+    }
+    pub fn eligibility(&self) -> Option<Box<dyn EligibilityPolicy>> {
+        self.config.eligibility.as_ref().map(eligibility::build)
+    }
+    pub fn tree(&self) -> &TreeState {
+        &self.tree_state.tree_state
+    }
+    fn is_sealed(&self) -> bool {
+        self.config.sealed && self.phase != PollPhase::Closed
+    }
+    // the proofs publish encrypted or committed ballots
+    fn hides_ballots(&self) -> bool {
+        self.config.encryption.is_some() || self.config.commit_reveal
+    }
+    fn check_registration(&self, username: &GitHubUser) -> Result<(), RegistrationError> {
+        if !matches!(self.phase, PollPhase::Registration | PollPhase::Open) {
+            return Err(RegistrationError::Closed);
+        }
+        // the username becomes part of the GitHub api url
+        if username.is_empty()
+            || !username
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-')
+        {
+            return Err(RegistrationError::InvalidUsername);
+        }
+        if self.github_users.contains(username) {
+            return Err(RegistrationError::DuplicateUser);
+        };
+        if self
+            .allowlist
+            .as_ref()
+            .is_some_and(|allowlist| !allowlist.contains(&login(username)))
+        {
+            return Err(RegistrationError::Ineligible(
+                "not on the allowlist".to_string(),
+            ));
+        }
+        if self.tree().leafs.len() >= self.config.capacity() {
+            return Err(RegistrationError::TreeFull);
+        }
+        Ok(())
+    }
+    fn check_vote(&self, outputs: &CircuitOutputs) -> Result<(), VoteError> {
+        match self.phase {
+            PollPhase::Open => {}
+            PollPhase::Registration => return Err(VoteError::NotOpen),
+            _ => return Err(VoteError::Closed),
+        }
+        // the first tag of a revoting voter is kept with the used nullifiers
+        check_outputs(
+            outputs,
+            &self.tree().root_history,
+            &self.tree().used_nullifiers,
+        )?;
+        // the circuit binds the tag of the replaced ballot to the sequence before this one,
+        // so a revote can neither skip ahead nor replay an older ballot
+        if self.config.revoting && !outputs.previous.is_empty() {
+            match self.votes.latest_sequence(&outputs.previous) {
+                Some(latest) if latest.checked_add(1) == Some(outputs.sequence) => {}
+                Some(latest) => {
+                    return Err(VoteError::Sequence {
+                        expected: latest + 1,
+                    })
+                }
+                None => return Err(VoteError::NotLatest),
+            }
+        } else if outputs.sequence != 0 {
+            return Err(VoteError::Sequence { expected: 0 });
+        }
+        // the circuit validated the ballot against outputs.spec
+        if outputs.spec != self.config.spec() {
+            return Err(VoteError::SpecMismatch);
+        }
+        match (&outputs.ballot, outputs.spec.encryption_key) {
+            (PublicBallot::Plain(ballot), None) if !outputs.spec.commit_reveal => ballot
+                .validate(&outputs.spec)
+                .and_then(|_| ballot.within_budget(&outputs.spec, outputs.weight))
+                .map_err(VoteError::InvalidBallot),
+            // the circuit encrypted the votes of a valid ballot
+            (PublicBallot::Encrypted(votes), Some(_))
+                if votes.len() == outputs.spec.options.len() =>
+            {
+                Ok(())
+            }
+            // the circuit committed to a valid ballot, it is validated again when revealed
+            (PublicBallot::Committed(_), None) if outputs.spec.commit_reveal => Ok(()),
+            _ => Err(VoteError::SpecMismatch),
+        }
+    }
+    // the closing transition, publishes the tally of a sealed poll
+    fn close(&mut self) {
+        self.phase = PollPhase::Closed;
+        match self.config.encryption {
+            Some(_) => info!(poll = %self.config.id, "poll closed, waiting for the trustees"),
+            None => {
+                let results: PollResults = self.results();
+                info!(poll = %self.config.id, tally = ?results.tally, outcome = ?results.outcome, "poll closed")
+            }
+        }
+    }
+    // commit-reveal polls move to their reveal phase, the others close
+    fn end_voting(&mut self) {
+        if self.config.commit_reveal {
+            self.phase = PollPhase::Reveal;
+            info!(poll = %self.config.id, "voting closed, reveal phase started");
+        } else {
+            self.close();
+        }
+    }
+    fn transition(&mut self, phase: PollPhase) -> Result<(), AdminError> {
+        match (self.phase, phase) {
+            (PollPhase::Registration, PollPhase::Open) => {
+                self.phase = PollPhase::Open;
+                info!(poll = %self.config.id, "voting opened");
+            }
+            (PollPhase::Open, PollPhase::Reveal) if self.config.commit_reveal => self.end_voting(),
+            (PollPhase::Open, PollPhase::Closed) if !self.config.commit_reveal => self.end_voting(),
+            (PollPhase::Reveal, PollPhase::Closed) => self.close(),
+            (from, to) => return Err(AdminError::Transition { from, to }),
+        }
+        Ok(())
+    }
+    // the leaf is replaced by one nobody can open and the tree is rebuilt, the roots of the
+    // leaves inserted after it change, their voters fetch a new snapshot before voting
+    fn revoke(&mut self, username: &GitHubUser) -> Result<(), AdminError> {
+        if self.phase != PollPhase::Registration {
+            return Err(AdminError::Revocation);
+        }
+        let position: usize = self
+            .github_users
+            .remove(username)
+            .ok_or(AdminError::UnknownRegistration)?;
+        let mut leafs: Vec<Identity> = self.tree().leafs.clone();
+        leafs[position] = revoked_leaf();
+        self.tree_state.tree_state = replay(self.config.tree_depth, &leafs);
+        info!(poll = %self.config.id, username = %username, "registration revoked");
+        Ok(())
+    }
+    // stores lowercase logins, like the eligibility policies compare them
+    fn edit_allowlist(&mut self, edit: AllowlistEdit) {
+        if edit.clear {
+            self.allowlist = None;
+        }
+        if let Some(allowlist) = &mut self.allowlist {
+            for user in &edit.remove {
+                allowlist.remove(&login(user));
+            }
+        }
+        if !edit.add.is_empty() {
+            self.allowlist
+                .get_or_insert_with(BTreeSet::new)
+                .extend(edit.add.iter().map(|user| login(user)));
+        }
+        info!(poll = %self.config.id, allowlist = ?self.allowlist, "allowlist changed");
+    }
+    /// the snapshot handed out when leaf `index - 1` was inserted, as of the current tree
+    pub fn snapshot(&self, index: usize) -> Option<VotingTree> {
+        let leafs: &[Identity] = self.tree().leafs.get(..index)?;
+        (index > 0).then(|| replay(self.config.tree_depth, leafs).voting_tree)
+    }
+    pub fn overview(&self) -> PollOverview {
+        let mut registered: Vec<String> = self.github_users.github_users.iter().cloned().collect();
+        registered.sort();
+        PollOverview {
+            info: self.info(),
+            registered,
+            ballots: self.tree().used_nullifiers.len() as u64,
+            allowlist: self
+                .allowlist
+                .as_ref()
+                .map(|allowlist| allowlist.iter().cloned().collect()),
+        }
+    }
+    // opens a committed ballot in place, a revealed ballot is counted like a plain one
+    fn reveal(&mut self, payload: RevealPayload) -> Result<(), RevealError> {
+        if !self.config.commit_reveal {
+            return Err(RevealError::NotCommitReveal);
+        }
+        if self.phase != PollPhase::Reveal {
+            return Err(RevealError::Phase);
+        }
+        let index: usize = self
+            .votes
+            .position(&payload.nullifier)
+            .ok_or(RevealError::UnknownCommitment)?;
+        let WeightedBallot { ballot, weight } = &self.votes.ballots[index];
+        let PublicBallot::Committed(commitment) = ballot else {
+            return Err(RevealError::AlreadyRevealed);
+        };
+        if payload.salt.len() != SALT_LENGTH
+            || payload.ballot.reveal_commitment(&payload.salt) != *commitment
+        {
+            return Err(RevealError::Mismatch);
+        }
+        let spec = self.config.spec();
+        payload
+            .ballot
+            .validate(&spec)
+            .and_then(|_| payload.ballot.within_budget(&spec, *weight))
+            .map_err(RevealError::InvalidBallot)?;
+        self.votes.ballots[index].ballot = PublicBallot::Plain(payload.ballot);
+        info!(poll = %self.config.id, "ballot revealed");
+        Ok(())
+    }
+    fn aggregate(&self) -> Result<Vec<Ciphertext>, DecryptionError> {
+        tally::aggregate(self.config.options.len(), &self.votes.ballots)
+            .map_err(DecryptionError::Tally)
+    }
+    // verifies the shares of a trustee against the aggregate, decrypts the tally at the threshold
+    fn accept_shares(&mut self, submitted: TrusteeShares) -> Result<(), DecryptionError> {
+        let encryption = self
+            .config
+            .encryption
+            .as_ref()
+            .ok_or(DecryptionError::NotEncrypted)?;
+        if self.phase != PollPhase::Closed {
+            return Err(DecryptionError::Open);
+        }
+        let key: Point = encryption
+            .verification_key(submitted.index)
+            .ok_or(DecryptionError::UnknownTrustee)?;
+        if self.decryption.shares.contains_key(&submitted.index) {
+            return Err(DecryptionError::DuplicateTrustee);
+        }
+        let aggregate: Vec<Ciphertext> = self.aggregate()?;
+        if submitted.shares.len() != aggregate.len() {
+            return Err(DecryptionError::ShareCount {
+                expected: aggregate.len(),
+            });
+        }
+        for (ciphertext, share) in aggregate.iter().zip(&submitted.shares) {
+            if share.index != submitted.index {
+                return Err(DecryptionError::UnknownTrustee);
+            }
+            verify_share(&key, ciphertext, share).map_err(DecryptionError::InvalidShare)?;
+        }
+        self.decryption
+            .shares
+            .insert(submitted.index, submitted.shares);
+        info!(poll = %self.config.id, trustee = submitted.index, "decryption shares accepted");
+        if self.decryption.tally.is_some() || self.decryption.shares.len() < encryption.threshold {
+            return Ok(());
+        }
+        let max: u64 = tally::max_votes(&self.config.spec(), &self.votes.ballots);
+        let mut counts: HashMap<String, u64> = HashMap::new();
+        for (i, (option, ciphertext)) in self.config.options.iter().zip(&aggregate).enumerate() {
+            let shares: Vec<DecryptionShare> = self
+                .decryption
+                .shares
+                .values()
+                .map(|shares| shares[i].clone())
+                .collect();
+            let votes: u64 = combine(ciphertext, &shares, encryption.threshold, max)
+                .map_err(DecryptionError::Tally)?;
+            counts.insert(option.clone(), votes);
+        }
+        self.decryption.tally = Some(counts);
+        info!(
+            poll = %self.config.id,
+            tally = ?self.decryption.tally,
+            outcome = ?self.results().outcome,
+            "tally decrypted"
+        );
+        Ok(())
+    }
+    pub fn info(&self) -> PollInfo {
+        PollInfo {
+            id: self.config.id.clone(),
+            title: self.config.title.clone(),
+            phase: self.phase,
+            spec: self.config.spec(),
+        }
+    }
+    /// whether a vote was accepted with this ticket, replaced ballots keep theirs
+    pub fn has_ticket(&self, ticket: &str) -> bool {
+        self.votes.tickets.contains(ticket)
+    }
+
+    pub fn results(&self) -> PollResults {
+        let mut results = PollResults {
+            poll: self.config.id.clone(),
+            phase: self.phase,
+            sealed: self.config.sealed,
+            kind: self.config.ballot,
+            ballots: self.tree().used_nullifiers.len() as u64,
+            tally: None,
+            rounds: None,
+            winner: None,
+            aggregate: None,
+            trustees: None,
+            unrevealed: None,
+            outcome: None,
+        };
+        if self.is_sealed() {
+            return results;
+        }
+        if self.config.commit_reveal {
+            let committed = |b: &&WeightedBallot| matches!(b.ballot, PublicBallot::Committed(_));
+            results.unrevealed = Some(self.votes.ballots.iter().filter(committed).count() as u64);
+        }
+        if self.config.encryption.is_some() {
+            // nothing is known before the poll closes, the tally only once it was decrypted
+            if self.phase == PollPhase::Closed {
+                results.aggregate = self.aggregate().ok();
+                results.trustees = Some(self.decryption.shares.keys().copied().collect());
+                results.tally = self.decryption.tally.clone();
+            }
+        } else {
+            results.tally = Some(tally::count(&self.votes.ballots));
+            if self.config.ballot == BallotKind::Ranked {
+                let runoff = tally::instant_runoff(&self.config.options, &self.votes.ballots);
+                results.rounds = Some(runoff.rounds);
+                results.winner = runoff.winner;
+            }
+        }
+        results.outcome = self.outcome(&results);
+        results
+    }
+    // decided on the final counts of a closed poll, the last runoff round of a ranked poll
+    fn outcome(&self, results: &PollResults) -> Option<Outcome> {
+        if self.phase != PollPhase::Closed {
+            return None;
+        }
+        let tally: &HashMap<String, u64> = results.tally.as_ref()?;
+        let counted: Vec<&WeightedBallot> = self
+            .votes
+            .ballots
+            .iter()
+            .filter(|b| !matches!(b.ballot, PublicBallot::Committed(_)))
+            .collect();
+        let last_round: Option<HashMap<String, u64>> = results
+            .rounds
+            .as_ref()
+            .and_then(|rounds| rounds.last())
+            .map(|round| round.counts.clone().into_iter().collect());
+        let counts: &HashMap<String, u64> = last_round.as_ref().unwrap_or(tally);
+        let total: u64 = match (self.config.ballot, &last_round) {
+            // shares of the votes rather than of the voters
+            (BallotKind::Quadratic, _) | (_, Some(_)) => counts.values().sum(),
+            _ => counted.iter().map(|b| b.weight).sum(),
+        };
+        let count = FinalCount {
+            counts,
+            total,
+            ballots: counted.len() as u64,
+            // revoked leaves stay in the tree
+            registered: self.github_users.github_users.len() as u64,
+        };
+        // the nullifiers are fixed once voting ends and can't be re-drawn by proving again
+        let seed: Vec<u8> = hash(CryptoHasherSha256, &self.tree().used_nullifiers.concat());
+        Some(outcome::evaluate(
+            &self.config.rules,
+            &count,
+            &self.config.options,
+            &seed,
+        ))
+    }
+}
+
+// an acknowledgement that can't be linked to the vote itself, the nullifier is public in the
+// journal, so the ticket must not be derived from it
+pub fn vote_ticket() -> String {
+    to_hex(&rand::random::<[u8; 16]>())
+}
+
+pub struct ServiceState {
+    polls: HashMap<PollId, PollState>,
+    storage: Option<Storage>,
+    events: broadcast::Sender<(PollId, PollEvent)>,
+    // by challenge, not persisted, a restart only costs the pending registrations a new one
+    challenges: HashMap<Vec<u8>, IssuedChallenge>,
+}
+impl ServiceState {
+    // restores persisted polls, the config stays the source of truth for the configured polls,
+    // polls created through the admin api are restored with their persisted config
+    pub fn new(configs: Vec<PollConfig>, storage: Option<Storage>) -> io::Result<ServiceState> {
+        let mut persisted: HashMap<PollId, PollState> = match &storage {
+            Some(storage) => storage.load()?.unwrap_or_default(),
+            None => HashMap::new(),
+        };
+        let mut polls: HashMap<PollId, PollState> = HashMap::new();
+        for config in configs {
+            let poll: PollState = match persisted.remove(&config.id) {
+                Some(mut poll) => {
+                    if poll.tree().voting_tree.depth != config.tree_depth {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!(
+                                "poll {}: persisted tree depth differs from config",
+                                config.id
+                            ),
+                        ));
+                    }
+                    poll.config = config;
+                    poll
+                }
+                None => PollState::new(config),
+            };
+            polls.insert(poll.config.id.clone(), poll);
+        }
+        for (id, poll) in persisted {
+            match poll.created {
+                true => {
+                    polls.insert(id, poll);
+                }
+                false => warn!(poll = %id, "ignoring persisted poll that is not configured"),
+            }
+        }
+        let (events, _) = broadcast::channel(EVENT_BUFFER);
+        Ok(ServiceState {
+            polls,
+            storage,
+            events,
+            challenges: HashMap::new(),
+        })
+    }
+    fn persist(&self) {
+        if let Some(storage) = &self.storage {
+            if let Err(e) = storage.save(&self.polls) {
+                error!(error = %e, "failed to persist state");
+            }
+        }
+    }
+    /// every event of every poll from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<(PollId, PollEvent)> {
+        self.events.subscribe()
+    }
+    // nobody may be subscribed, the event is dropped then
+    fn emit(&self, poll_id: &str, event: PollEvent) {
+        let _ = self.events.send((poll_id.to_string(), event));
+    }
+    fn tree_changed(&self, poll_id: &str) {
+        if let Some(poll) = self.poll(poll_id) {
+            self.emit(
+                poll_id,
+                PollEvent::Tree {
+                    leaves: poll.tree().leafs.len() as u64,
+                    root: poll.tree().root_history.last().map(|root| to_hex(root)),
+                },
+            );
+        }
+    }
+    // the tally of an encrypted poll is published once the trustees decrypted it
+    fn phase_changed(&self, poll_id: &str) {
+        let Some(poll) = self.poll(poll_id) else {
+            return;
+        };
+        self.emit(poll_id, PollEvent::Phase { phase: poll.phase });
+        if poll.phase == PollPhase::Closed && poll.config.encryption.is_none() {
+            self.emit(
+                poll_id,
+                PollEvent::Results {
+                    results: poll.results(),
+                },
+            );
+        }
+    }
+    pub fn poll(&self, poll_id: &str) -> Option<&PollState> {
+        self.polls.get(poll_id)
+    }
+    pub fn polls(&self) -> impl Iterator<Item = &PollState> {
+        self.polls.values()
+    }
+
+    /// the checks that don't need the GitHub lookup, run again by `register`
+    pub fn check_registration(
+        &self,
+        poll_id: &str,
+        username: &GitHubUser,
+    ) -> Result<(), RegistrationError> {
+        self.poll(poll_id)
+            .ok_or(RegistrationError::UnknownPoll)?
+            .check_registration(username)
+    }
+
+    /// the data a registration has to sign, bound to the poll, the login and the identity,
+    /// it can be redeemed once within CHALLENGE_TTL
+    pub fn issue_challenge(
+        &mut self,
+        poll_id: &str,
+        username: &GitHubUser,
+        identity: &Identity,
+    ) -> Result<Vec<u8>, RegistrationError> {
+        self.check_registration(poll_id, username)?;
+        self.challenges
+            .retain(|_, issued| issued.issued_at.elapsed() < CHALLENGE_TTL);
+        if self.challenges.len() >= MAX_CHALLENGES {
+            return Err(RegistrationError::TooManyChallenges);
+        }
+        // the parts are length prefixed so that no two registrations share a preimage
+        let mut payload: Vec<u8> = b"cypher-poll-challenge".to_vec();
+        for part in [poll_id.as_bytes(), login(username).as_bytes(), identity] {
+            payload.extend_from_slice(&(part.len() as u64).to_be_bytes());
+            payload.extend_from_slice(part);
+        }
+        payload.extend_from_slice(&rand::random::<[u8; 32]>());
+        let challenge: Vec<u8> = hash(CryptoHasherSha256, &payload);
+        self.challenges.insert(
+            challenge.clone(),
+            IssuedChallenge {
+                poll_id: poll_id.to_string(),
+                username: login(username),
+                identity: identity.clone(),
+                issued_at: Instant::now(),
+            },
+        );
+        Ok(challenge)
+    }
+
+    /// consumes the challenge of a registration, a replayed registration finds it gone
+    pub fn redeem_challenge(
+        &mut self,
+        poll_id: &str,
+        username: &GitHubUser,
+        identity: &Identity,
+        challenge: &[u8],
+    ) -> Result<(), RegistrationError> {
+        match self.challenges.remove(challenge) {
+            Some(issued)
+                if issued.poll_id == poll_id
+                    && issued.username == login(username)
+                    && issued.identity == *identity
+                    && issued.issued_at.elapsed() < CHALLENGE_TTL =>
+            {
+                Ok(())
+            }
+            _ => Err(RegistrationError::InvalidChallenge),
+        }
+    }
+
+    /// inserts the identity of a verified user and returns the new tree snapshot
+    pub fn register(
+        &mut self,
+        poll_id: &str,
+        username: &GitHubUser,
+        identity: Identity,
+        weight: u64,
+    ) -> Result<VotingTree, RegistrationError> {
+        let poll: &mut PollState = self
+            .polls
+            .get_mut(poll_id)
+            .ok_or(RegistrationError::UnknownPoll)?;
+        poll.check_registration(username)?;
+        let position: usize = poll.tree().leafs.len();
+        poll.github_users.insert(username, position);
+        let leaf: Identity = match poll.config.weights {
+            Some(_) => weighted_leaf(&identity, weight),
+            None => identity,
+        };
+        poll.tree_state.insert_nullifier(leaf);
+        info!(poll = %poll_id, username = %username, "registration accepted");
+        let snapshot: VotingTree = poll.tree().voting_tree.clone();
+        self.persist();
+        self.tree_changed(poll_id);
+        Ok(snapshot)
+    }
+
+    /// the checks of `accept_vote` on unverified outputs, to reject a proof before verification
+    pub fn check_vote(&self, poll_id: &str, outputs: &CircuitOutputs) -> Result<(), VoteError> {
+        self.poll(poll_id)
+            .ok_or(VoteError::UnknownPoll)?
+            .check_vote(outputs)
+    }
+
+    /// redeems the nullifier of verified outputs and counts the vote, returns the vote ticket
+    pub fn accept_vote(
+        &mut self,
+        poll_id: &str,
+        outputs: CircuitOutputs,
+    ) -> Result<String, VoteError> {
+        let poll: &mut PollState = self.polls.get_mut(poll_id).ok_or(VoteError::UnknownPoll)?;
+        poll.check_vote(&outputs)?;
+        let (ballot, weight, nullifier): (PublicBallot, u64, Vec<u8>) =
+            (outputs.ballot, outputs.weight, outputs.nullifier);
+        let ticket: String = vote_ticket();
+        match (poll.is_sealed(), ballot.plain()) {
+            (false, Some(plain)) => {
+                info!(poll = %poll_id, ballot = ?plain.choices(), "vote accepted")
+            }
+            (_, None) => info!(poll = %poll_id, "vote accepted"),
+            // neither the ballot nor the running tally may show up before the poll closes
+            (true, Some(_)) => info!(poll = %poll_id, "sealed vote accepted"),
+        }
+        let shown: Option<Ballot> = match poll.is_sealed() {
+            true => None,
+            false => ballot.plain().cloned(),
+        };
+        let weighted: WeightedBallot = WeightedBallot { ballot, weight };
+        if !poll.config.revoting {
+            poll.tree_state.insert_used_nullifier(nullifier.clone());
+            match poll.config.commit_reveal {
+                true => poll.votes.commit(&nullifier, weighted),
+                false => poll.votes.insert(weighted),
+            }
+        } else if !poll
+            .votes
+            .revote(&nullifier, &outputs.previous, outputs.sequence, weighted)
+        {
+            // the first ballot of the voter
+            poll.tree_state.insert_used_nullifier(nullifier);
+        } else {
+            info!(poll = %poll_id, sequence = outputs.sequence, "earlier ballot replaced");
+        }
+        if !poll.is_sealed() && !poll.hides_ballots() {
+            info!(poll = %poll_id, tally = ?tally::count(&poll.votes.ballots), "current state of the election");
+        }
+        poll.votes.tickets.insert(ticket.clone());
+        let ballots: u64 = poll.tree().used_nullifiers.len() as u64;
+        self.persist();
+        self.emit(
+            poll_id,
+            PollEvent::Vote {
+                ballots,
+                ballot: shown,
+            },
+        );
+        Ok(ticket)
+    }
+
+    /// stores the verified decryption shares of a trustee for a closed encrypted poll
+    pub fn submit_shares(
+        &mut self,
+        poll_id: &str,
+        shares: TrusteeShares,
+    ) -> Result<(), DecryptionError> {
+        let poll: &mut PollState = self
+            .polls
+            .get_mut(poll_id)
+            .ok_or(DecryptionError::UnknownPoll)?;
+        let decrypted: bool = poll.decryption.tally.is_some();
+        poll.accept_shares(shares)?;
+        let results: Option<PollResults> =
+            (!decrypted && poll.decryption.tally.is_some()).then(|| poll.results());
+        self.persist();
+        if let Some(results) = results {
+            self.emit(poll_id, PollEvent::Results { results });
+        }
+        Ok(())
+    }
+
+    /// opens the committed ballot published under a nullifier (or revoting tag)
+    pub fn reveal(&mut self, poll_id: &str, payload: RevealPayload) -> Result<(), RevealError> {
+        self.polls
+            .get_mut(poll_id)
+            .ok_or(RevealError::UnknownPoll)?
+            .reveal(payload)?;
+        self.persist();
+        Ok(())
+    }
+
+    /// closes the open polls whose closes_at has passed,
+    /// commit-reveal polls pass through their reveal phase first
+    pub fn close_expired(&mut self, now: u64) {
+        let mut changed: Vec<PollId> = Vec::new();
+        let expired = |t: Option<u64>| t.is_some_and(|t| t <= now);
+        for poll in self.polls.values_mut() {
+            match poll.phase {
+                PollPhase::Open if expired(poll.config.closes_at) => poll.end_voting(),
+                PollPhase::Reveal if expired(poll.config.reveal_closes_at) => poll.close(),
+                _ => continue,
+            }
+            changed.push(poll.config.id.clone());
+        }
+        if !changed.is_empty() {
+            self.persist();
+        }
+        for poll_id in changed {
+            self.phase_changed(&poll_id);
+        }
+    }
+
+    /// adds a poll defined through the admin api, it is persisted together with its config
+    pub fn create_poll(&mut self, config: PollConfig) -> Result<PollInfo, AdminError> {
+        config
+            .validate()
+            .map_err(|e| AdminError::InvalidDefinition(e.to_string()))?;
+        // the table would be read from the filesystem of the service
+        if config
+            .weights
+            .as_ref()
+            .is_some_and(|weights| weights.table.is_some())
+        {
+            return Err(AdminError::InvalidDefinition(
+                "weights.table is not supported, list the weights under weights.users".to_string(),
+            ));
+        }
+        if self.polls.contains_key(&config.id) {
+            return Err(AdminError::DuplicatePoll);
+        }
+        let mut poll: PollState = PollState::new(config);
+        poll.created = true;
+        let info: PollInfo = poll.info();
+        info!(poll = %info.id, phase = ?info.phase, "poll created");
+        self.polls.insert(info.id.clone(), poll);
+        self.persist();
+        Ok(info)
+    }
+
+    pub fn transition(&mut self, poll_id: &str, phase: PollPhase) -> Result<(), AdminError> {
+        self.polls
+            .get_mut(poll_id)
+            .ok_or(AdminError::UnknownPoll)?
+            .transition(phase)?;
+        self.persist();
+        self.phase_changed(poll_id);
+        Ok(())
+    }
+
+    pub fn revoke(&mut self, poll_id: &str, username: &GitHubUser) -> Result<(), AdminError> {
+        self.polls
+            .get_mut(poll_id)
+            .ok_or(AdminError::UnknownPoll)?
+            .revoke(username)?;
+        self.persist();
+        self.tree_changed(poll_id);
+        Ok(())
+    }
+
+    pub fn edit_allowlist(&mut self, poll_id: &str, edit: AllowlistEdit) -> Result<(), AdminError> {
+        self.polls
+            .get_mut(poll_id)
+            .ok_or(AdminError::UnknownPoll)?
+            .edit_allowlist(edit);
+        self.persist();
+        Ok(())
+    }
+}
+
+// a leaf no identity hashes to
+fn revoked_leaf() -> Identity {
+    hash(CryptoHasherSha256, b"cypher-poll-revoked-leaf")
+}
+
+// the tree state after inserting the leafs in order
+fn replay(depth: usize, leafs: &[Identity]) -> TreeState {
+    let mut tree_state: TreeState = default_tree_state(depth);
+    for leaf in leafs {
+        tree_state.voting_tree.add_leaf(leaf.clone());
+        tree_state.root_history.push(
+            tree_state
+                .voting_tree
+                .root
+                .clone()
+                .expect("Tree has no root"),
+        );
+        tree_state.leafs.push(leaf.clone());
+    }
+    tree_state
+}
+
+pub fn default_tree_state(depth: usize) -> TreeState {
+    let mut voting_tree: VotingTree = VotingTree {
+        zero_node: hash(CryptoHasherSha256, &vec![0; 32]),
+        zero_levels: Vec::new(),
+        // size must equal tree depth
+        filled: vec![vec![]; depth],
+        root: None,
+        index: 0,
+        // the maximum amount of identities this tree can store
+        // is 2^depth (depth:5 => max_identity_count:32)
+        depth,
+    };
+    voting_tree.calculate_zero_levels();
+
+    TreeState {
+        root_history: Vec::new(),
+        used_nullifiers: Vec::new(),
+        voting_tree,
+        leafs: Vec::new(),
+    }
+}
+
+// a state without storage that holds the poll "test"
+#[cfg(test)]
+pub(crate) fn test_poll(config: PollConfig) -> ServiceState {
+    ServiceState::new(
+        vec![PollConfig {
+            id: "test".to_string(),
+            ..config
+        }],
+        None,
+    )
+    .expect("Invalid test poll")
+}
+
+// the outputs of a first ballot against the latest root of the poll
+#[cfg(test)]
+fn outputs(poll: &PollState, nullifier: u8, ballot: PublicBallot) -> CircuitOutputs {
+    CircuitOutputs {
+        nullifier: vec![nullifier; 32],
+        previous: Vec::new(),
+        root_history: poll
+            .tree()
+            .root_history
+            .last()
+            .cloned()
+            .into_iter()
+            .collect(),
+        ballot,
+        spec: poll.config.spec(),
+        weight: 1,
+        sequence: 0,
+    }
+}
+
+#[cfg(test)]
+fn plurality(choice: &str) -> PublicBallot {
+    PublicBallot::Plain(Ballot::Plurality {
+        choice: choice.to_string(),
+    })
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_concurrent_duplicates() {
+    use std::sync::Arc;
+    use tokio::sync::Mutex;
+    let state = Arc::new(Mutex::new(test_poll(PollConfig::default())));
+    // the same GitHub user registers from many connections at once
+    let registrations: Vec<_> = (0..16u8)
+        .map(|i| {
+            let state = Arc::clone(&state);
+            tokio::spawn(async move {
+                state
+                    .lock()
+                    .await
+                    .register("test", &"octocat".to_string(), vec![i; 32], 1)
+            })
+        })
+        .collect();
+    let mut registered: usize = 0;
+    for registration in registrations {
+        if registration.await.unwrap().is_ok() {
+            registered += 1;
+        }
+    }
+    assert_eq!(registered, 1);
+    // logins are case insensitive on GitHub
+    let mut service = state.lock().await;
+    assert!(matches!(
+        service.register("test", &"OctoCat".to_string(), vec![16; 32], 1),
+        Err(RegistrationError::DuplicateUser)
+    ));
+    drop(service);
+    let outputs: CircuitOutputs = outputs(
+        state.lock().await.poll("test").unwrap(),
+        7,
+        plurality("Overlord"),
+    );
+    // the same nullifier is redeemed from many connections at once
+    let votes: Vec<_> = (0..16)
+        .map(|_| {
+            let state = Arc::clone(&state);
+            let outputs = outputs.clone();
+            tokio::spawn(async move {
+                state.lock().await.check_vote("test", &outputs)?;
+                // verification happens here, outside the lock
+                tokio::task::yield_now().await;
+                state.lock().await.accept_vote("test", outputs)
+            })
+        })
+        .collect();
+    let mut accepted: usize = 0;
+    for vote in votes {
+        match vote.await.unwrap() {
+            Ok(_) => accepted += 1,
+            Err(error) => assert_eq!(error.reason(), "duplicate_nullifier"),
+        }
+    }
+    assert_eq!(accepted, 1);
+    let results: PollResults = state.lock().await.poll("test").unwrap().results();
+    assert_eq!(results.ballots, 1);
+    assert_eq!(results.tally.unwrap()["Overlord"], 1);
+}
+
+#[test]
+fn test_registration_challenge() {
+    let mut state = test_poll(PollConfig::default());
+    let user = |name: &str| name.to_string();
+    let challenge: Vec<u8> = state
+        .issue_challenge("test", &user("Octocat"), &vec![1; 32])
+        .unwrap();
+    // bound to the identity it was issued for, and gone after a failed attempt
+    for identity in [vec![2; 32], vec![1; 32]] {
+        let error = state
+            .redeem_challenge("test", &user("octocat"), &identity, &challenge)
+            .unwrap_err();
+        assert_eq!(error.reason(), "invalid_challenge");
+    }
+    let challenge: Vec<u8> = state
+        .issue_challenge("test", &user("Octocat"), &vec![1; 32])
+        .unwrap();
+    assert!(state
+        .redeem_challenge("test", &user("octocat"), &vec![1; 32], &challenge)
+        .is_ok());
+    // a replayed registration finds the challenge redeemed
+    let error = state
+        .redeem_challenge("test", &user("octocat"), &vec![1; 32], &challenge)
+        .unwrap_err();
+    assert_eq!(error.reason(), "invalid_challenge");
+}
+
+#[test]
+fn test_revoting() {
+    use crypto::identity::revote_tag;
+    let mut state = test_poll(PollConfig {
+        options: vec!["Overlord".to_string(), "TimeLord".to_string()],
+        revoting: true,
+        ..Default::default()
+    });
+    state
+        .register("test", &"octocat".to_string(), vec![1; 32], 1)
+        .unwrap();
+    let first: CircuitOutputs = outputs(state.poll("test").unwrap(), 7, plurality("Overlord"));
+    // the tags the circuit derives from the nullifier and the sequence
+    let tag = |sequence: u64| revote_tag(&vec![7; 32], sequence);
+    let outputs = |choice: &str, sequence: u64, previous: Vec<u8>| CircuitOutputs {
+        nullifier: tag(sequence),
+        previous,
+        ballot: plurality(choice),
+        sequence,
+        ..first.clone()
+    };
+    let ticket: String = state
+        .accept_vote("test", outputs("Overlord", 0, Vec::new()))
+        .unwrap();
+    // a replayed first ballot, a skipped sequence and a sequence that does not follow the
+    // replaced ballot are rejected
+    for (outputs, reason) in [
+        (outputs("TimeLord", 0, Vec::new()), "duplicate_nullifier"),
+        (outputs("TimeLord", 2, tag(1)), "not_latest"),
+        (outputs("TimeLord", u64::MAX, tag(0)), "sequence"),
+    ] {
+        let error = state.accept_vote("test", outputs).unwrap_err();
+        assert_eq!(error.reason(), reason);
+    }
+    let replaced: String = state
+        .accept_vote("test", outputs("TimeLord", 1, tag(0)))
+        .unwrap();
+    // the replaced ballot can't be replaced a second time
+    let error = state
+        .accept_vote("test", outputs("Overlord", 1, tag(0)))
+        .unwrap_err();
+    assert_eq!(error.reason(), "not_latest");
+    // tickets are not derived from the tag, both stay valid
+    assert_ne!(ticket, replaced);
+    assert!(state.poll("test").unwrap().has_ticket(&ticket));
+    assert!(state.poll("test").unwrap().has_ticket(&replaced));
+    let results: PollResults = state.poll("test").unwrap().results();
+    assert_eq!(results.ballots, 1);
+    let tally = results.tally.unwrap();
+    assert_eq!(tally.get("Overlord"), None);
+    assert_eq!(tally["TimeLord"], 1);
+}
+
+#[test]
+fn test_encrypted_tally() {
+    use crate::config::EncryptionConfig;
+    use crypto::elgamal::{decryption_share, encrypt, public_key, random_scalar, split, KeyShare};
+    let random = |seed: u8| random_scalar(&[seed; 64]);
+    let hex = |secret: &[u8; 32]| to_hex(&public_key(secret).unwrap());
+    let secret: [u8; 32] = random(1);
+    // 2 of 3 trustees
+    let keys: Vec<KeyShare> = split(&secret, &[random(2)], 3).unwrap();
+    let mut state = test_poll(PollConfig {
+        options: vec!["a".to_string(), "b".to_string()],
+        ballot: BallotKind::Approval,
+        closes_at: Some(100),
+        encryption: Some(EncryptionConfig {
+            public_key: hex(&secret),
+            threshold: 2,
+            verification_keys: keys.iter().map(|key| hex(&key.secret)).collect(),
+        }),
+        ..Default::default()
+    });
+    let encryption_key: [u8; 32] = state
+        .poll("test")
+        .unwrap()
+        .config
+        .spec()
+        .encryption_key
+        .unwrap();
+    for i in 0..2u8 {
+        state
+            .register("test", &format!("voter{}", i), vec![i; 32], 1)
+            .unwrap();
+    }
+    // approves a and b, then b only
+    for (i, votes) in [[1u64, 1], [0, 1]].iter().enumerate() {
+        let encrypted: Vec<Ciphertext> = votes
+            .iter()
+            .enumerate()
+            .map(|(j, votes)| {
+                let randomness: [u8; 32] = random(10 + (2 * i + j) as u8);
+                encrypt(&encryption_key, *votes, &randomness).unwrap()
+            })
+            .collect();
+        let outputs = outputs(
+            state.poll("test").unwrap(),
+            i as u8,
+            PublicBallot::Encrypted(encrypted),
+        );
+        state.accept_vote("test", outputs).unwrap();
+    }
+    let trustee = |key: &KeyShare, state: &ServiceState| TrusteeShares {
+        index: key.index,
+        shares: state
+            .poll("test")
+            .unwrap()
+            .results()
+            .aggregate
+            .unwrap()
+            .iter()
+            .map(|ciphertext| decryption_share(key, ciphertext, &random(30)).unwrap())
+            .collect(),
+    };
+    // nothing is published or decrypted before the poll closes
+    assert!(state.poll("test").unwrap().results().aggregate.is_none());
+    let early = TrusteeShares {
+        index: 1,
+        shares: Vec::new(),
+    };
+    assert_eq!(
+        state.submit_shares("test", early).unwrap_err().reason(),
+        "open"
+    );
+    state.close_expired(100);
+    let shares: TrusteeShares = trustee(&keys[0], &state);
+    state.submit_shares("test", shares.clone()).unwrap();
+    assert_eq!(
+        state.submit_shares("test", shares).unwrap_err().reason(),
+        "duplicate_trustee"
+    );
+    // the shares of trustee 2 don't verify against the key of trustee 3
+    let mut forged: TrusteeShares = trustee(&keys[1], &state);
+    forged.index = 3;
+    forged.shares.iter_mut().for_each(|share| share.index = 3);
+    assert_eq!(
+        state.submit_shares("test", forged).unwrap_err().reason(),
+        "invalid_share"
+    );
+    assert!(state.poll("test").unwrap().results().tally.is_none());
+    state
+        .submit_shares("test", trustee(&keys[2], &state))
+        .unwrap();
+    let results: PollResults = state.poll("test").unwrap().results();
+    assert_eq!(results.trustees, Some(vec![1, 3]));
+    let tally = results.tally.unwrap();
+    assert_eq!((tally["a"], tally["b"]), (1, 2));
+}
+
+#[test]
+fn test_commit_reveal() {
+    let mut state = test_poll(PollConfig {
+        options: vec!["Overlord".to_string(), "TimeLord".to_string()],
+        closes_at: Some(100),
+        commit_reveal: true,
+        reveal_closes_at: Some(200),
+        ..Default::default()
+    });
+    let ballot = |choice: &str| Ballot::Plurality {
+        choice: choice.to_string(),
+    };
+    for i in 0..2u8 {
+        state
+            .register("test", &format!("voter{}", i), vec![i; 32], 1)
+            .unwrap();
+    }
+    for (i, choice) in ["Overlord", "TimeLord"].iter().enumerate() {
+        let outputs = outputs(
+            state.poll("test").unwrap(),
+            i as u8,
+            PublicBallot::Committed(ballot(choice).reveal_commitment(&[i as u8; 32])),
+        );
+        state.accept_vote("test", outputs).unwrap();
+    }
+    let reveal = |i: u8, choice: &str, salt: u8| RevealPayload {
+        nullifier: vec![i; 32],
+        ballot: ballot(choice),
+        salt: vec![salt; 32],
+    };
+    let reason = |result: Result<(), RevealError>| result.unwrap_err().reason();
+    assert_eq!(
+        reason(state.reveal("test", reveal(0, "Overlord", 0))),
+        "phase"
+    );
+    state.close_expired(100);
+    // the vote can't be changed by opening the commitment differently
+    assert_eq!(
+        reason(state.reveal("test", reveal(0, "TimeLord", 0))),
+        "mismatch"
+    );
+    assert_eq!(
+        reason(state.reveal("test", reveal(0, "Overlord", 1))),
+        "mismatch"
+    );
+    state.reveal("test", reveal(0, "Overlord", 0)).unwrap();
+    assert_eq!(
+        reason(state.reveal("test", reveal(0, "Overlord", 0))),
+        "already_revealed"
+    );
+    assert_eq!(
+        reason(state.reveal("test", reveal(7, "Overlord", 0))),
+        "unknown_commitment"
+    );
+    // the second voter never reveals
+    state.close_expired(200);
+    assert_eq!(
+        reason(state.reveal("test", reveal(1, "TimeLord", 1))),
+        "phase"
+    );
+    let results: PollResults = state.poll("test").unwrap().results();
+    assert_eq!(results.phase, PollPhase::Closed);
+    assert_eq!((results.ballots, results.unrevealed), (2, Some(1)));
+    let tally = results.tally.unwrap();
+    assert_eq!(
+        (tally.get("Overlord"), tally.get("TimeLord")),
+        (Some(&1), None)
+    );
+    // the unrevealed ballot counts towards neither the turnout nor the share
+    let outcome: Outcome = results.outcome.unwrap();
+    assert_eq!((outcome.turnout, outcome.counted), (0.5, 1));
+    assert_eq!(
+        outcome.decision,
+        client::types::Decision::Winner {
+            option: "Overlord".to_string(),
+            share: 1.0,
+            tie_break: false
+        }
+    );
+}
+
+#[test]
+fn test_admin_lifecycle() {
+    let mut state = ServiceState::new(Vec::new(), None).unwrap();
+    let config: PollConfig = toml::from_str(
+        r#"
+        id = "charter"
+        options = ["yes", "no"]
+        registration_phase = true
+        "#,
+    )
+    .unwrap();
+    assert_eq!(
+        state.create_poll(config.clone()).unwrap().phase,
+        PollPhase::Registration
+    );
+    assert_eq!(
+        state.create_poll(config).unwrap_err().reason(),
+        "duplicate_poll"
+    );
+    let user = |name: &str| name.to_string();
+    state
+        .edit_allowlist(
+            "charter",
+            AllowlistEdit {
+                add: vec![user("Alice"), user("bob"), user("carol")],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    let error = state
+        .register("charter", &user("mallory"), vec![9; 32], 1)
+        .unwrap_err();
+    assert_eq!(error.reason(), "ineligible");
+    let snapshots: Vec<VotingTree> = ["alice", "bob", "carol"]
+        .iter()
+        .enumerate()
+        .map(|(i, name)| {
+            state
+                .register("charter", &user(name), vec![i as u8; 32], 1)
+                .unwrap()
+        })
+        .collect();
+    // bob is revoked, alice keeps her snapshot, carol needs a new one
+    state.revoke("charter", &user("Bob")).unwrap();
+    let poll: &PollState = state.poll("charter").unwrap();
+    assert_eq!(
+        poll.overview().registered,
+        vec![user("alice"), user("carol")]
+    );
+    assert_eq!(poll.snapshot(1).as_ref(), Some(&snapshots[0]));
+    let refreshed: VotingTree = poll.snapshot(3).unwrap();
+    assert_ne!(refreshed.root, snapshots[2].root);
+    assert!(!poll
+        .tree()
+        .root_history
+        .contains(&snapshots[1].root.clone().unwrap()));
+    assert!(poll.tree().root_history.contains(&refreshed.root.unwrap()));
+    // phases only move forward, revocations end once voting opens
+    assert_eq!(
+        state
+            .transition("charter", PollPhase::Reveal)
+            .unwrap_err()
+            .reason(),
+        "transition"
+    );
+    state.transition("charter", PollPhase::Open).unwrap();
+    assert_eq!(
+        state
+            .revoke("charter", &user("alice"))
+            .unwrap_err()
+            .reason(),
+        "revocation"
+    );
+    state.transition("charter", PollPhase::Closed).unwrap();
+    let results: PollResults = state.poll("charter").unwrap().results();
+    assert_eq!(results.phase, PollPhase::Closed);
+    assert_eq!(
+        results.outcome.unwrap().decision,
+        client::types::Decision::NoQuorum
+    );
+}
+
+#[test]
+fn test_poll_events() {
+    let mut state = test_poll(PollConfig {
+        options: vec!["alice".to_string(), "bob".to_string()],
+        sealed: true,
+        closes_at: Some(100),
+        ..Default::default()
+    });
+    let mut events = state.subscribe();
+    state
+        .register("test", &"octocat".to_string(), vec![1; 32], 1)
+        .unwrap();
+    let root: Vec<u8> = state.poll("test").unwrap().tree().root_history[0].clone();
+    assert!(matches!(
+        events.try_recv().unwrap().1,
+        PollEvent::Tree { leaves: 1, root: Some(hex) } if hex == to_hex(&root)
+    ));
+    let outputs = outputs(state.poll("test").unwrap(), 7, plurality("alice"));
+    state.accept_vote("test", outputs).unwrap();
+    // a sealed poll does not show the ballot
+    assert!(matches!(
+        events.try_recv().unwrap().1,
+        PollEvent::Vote {
+            ballots: 1,
+            ballot: None
+        }
+    ));
+    state.close_expired(100);
+    assert!(matches!(
+        events.try_recv().unwrap().1,
+        PollEvent::Phase {
+            phase: PollPhase::Closed
+        }
+    ));
+    let PollEvent::Results { results } = events.try_recv().unwrap().1 else {
+        panic!("Expected the results");
+    };
+    assert_eq!(results.tally.unwrap()["alice"], 1);
+    assert!(events.try_recv().is_err());
+}
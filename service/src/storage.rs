@@ -0,0 +1,41 @@
+// persists the state of all polls to a single json file in the storage directory,
+// written to a temporary file first so that a crash never leaves a truncated state behind
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+const STATE_FILE: &str = "state.json";
+
+#[derive(Clone)]
+pub struct Storage {
+    path: PathBuf,
+}
+
+impl Storage {
+    pub fn open(directory: &Path) -> io::Result<Storage> {
+        fs::create_dir_all(directory)?;
+        Ok(Storage {
+            path: directory.join(STATE_FILE),
+        })
+    }
+
+    pub fn load<T: DeserializeOwned>(&self) -> io::Result<Option<T>> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+        let raw: Vec<u8> = fs::read(&self.path)?;
+        serde_json::from_slice(&raw)
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn save<T: Serialize>(&self, state: &T) -> io::Result<()> {
+        let serialized: Vec<u8> =
+            serde_json::to_vec(state).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let tmp: PathBuf = self.path.with_extension("json.tmp");
+        fs::write(&tmp, serialized)?;
+        fs::rename(tmp, &self.path)
+    }
+}
@@ -0,0 +1,200 @@
+// tally engines
+// results are always computed from the stored ballots, never from a running count
+use client::types::TallyRound;
+use crypto::elgamal::{Ciphertext, ElGamalError};
+use risc0_types::{Ballot, BallotKind, PollSpec, PublicBallot};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+// a ballot counts as often as the weight of its voter
+#[derive(Clone, Serialize, Deserialize)]
+pub struct WeightedBallot {
+    pub ballot: PublicBallot,
+    pub weight: u64,
+}
+
+/// votes per option, a ranked ballot counts for its first preference,
+/// approval and multi-select ballots count once for each selected option,
+/// quadratic ballots count their allocated votes, encrypted ballots are skipped
+pub fn count(ballots: &[WeightedBallot]) -> HashMap<String, u64> {
+    let mut counts: HashMap<String, u64> = HashMap::new();
+    for WeightedBallot { ballot, weight } in ballots {
+        let Some(ballot) = ballot.plain() else {
+            continue;
+        };
+        let counted: Vec<(&String, u64)> = match ballot {
+            Ballot::Ranked { ranking } => ranking.iter().take(1).map(|c| (c, *weight)).collect(),
+            // the weight already scaled the credit budget
+            Ballot::Quadratic { allocations } => {
+                allocations.iter().map(|a| (&a.option, a.votes)).collect()
+            }
+            // validation rejects repeated options
+            _ => ballot.choices().into_iter().map(|c| (c, *weight)).collect(),
+        };
+        for (choice, votes) in counted {
+            *counts.entry(choice.to_string()).or_default() += votes;
+        }
+    }
+    counts
+}
+
+pub struct Runoff {
+    pub rounds: Vec<TallyRound>,
+    // None if every remaining option is tied or all ballots are exhausted
+    pub winner: Option<String>,
+}
+
+/// instant runoff: each ballot counts for its highest ranked option that is still in the race,
+/// an option with a majority of the continuing ballots wins, otherwise all options tied for
+/// the fewest votes are eliminated at once
+pub fn instant_runoff(options: &[String], ballots: &[WeightedBallot]) -> Runoff {
+    let mut active: HashSet<&String> = options.iter().collect();
+    let mut rounds: Vec<TallyRound> = Vec::new();
+    while !active.is_empty() {
+        let mut counts: BTreeMap<String, u64> = active
+            .iter()
+            .map(|option| (option.to_string(), 0))
+            .collect();
+        let mut exhausted: u64 = 0;
+        let mut continuing: u64 = 0;
+        for WeightedBallot { ballot, weight } in ballots {
+            // an unrevealed ballot is neither continuing nor exhausted
+            let Some(ballot) = ballot.plain() else {
+                continue;
+            };
+            match ballot.choices().into_iter().find(|c| active.contains(c)) {
+                Some(choice) => {
+                    *counts.get_mut(choice.as_str()).unwrap() += weight;
+                    continuing += weight;
+                }
+                None => exhausted += weight,
+            }
+        }
+        let leader: Option<String> = counts
+            .iter()
+            .find(|(_, count)| **count * 2 > continuing)
+            .map(|(option, _)| option.clone());
+        let fewest: u64 = *counts.values().min().unwrap();
+        let eliminated: Vec<String> = match leader {
+            Some(_) => Vec::new(),
+            None => counts
+                .iter()
+                .filter(|(_, count)| **count == fewest)
+                .map(|(option, _)| option.clone())
+                .collect(),
+        };
+        let tied: bool = eliminated.len() == active.len();
+        active.retain(|option| !eliminated.contains(*option));
+        rounds.push(TallyRound {
+            counts,
+            exhausted,
+            eliminated: if tied { Vec::new() } else { eliminated },
+        });
+        if leader.is_some() || tied || continuing == 0 {
+            return Runoff {
+                rounds,
+                winner: leader,
+            };
+        }
+    }
+    Runoff {
+        rounds,
+        winner: None,
+    }
+}
+
+/// the encrypted votes per option of all encrypted ballots
+pub fn aggregate(
+    options: usize,
+    ballots: &[WeightedBallot],
+) -> Result<Vec<Ciphertext>, ElGamalError> {
+    let mut sums: Vec<Ciphertext> = vec![Ciphertext::zero(); options];
+    for WeightedBallot { ballot, .. } in ballots {
+        if let PublicBallot::Encrypted(votes) = ballot {
+            for (sum, votes) in sums.iter_mut().zip(votes) {
+                *sum = sum.add(votes)?;
+            }
+        }
+    }
+    Ok(sums)
+}
+
+/// the most votes a single option can have, bounds the search for a decrypted count
+pub fn max_votes(spec: &PollSpec, ballots: &[WeightedBallot]) -> u64 {
+    ballots
+        .iter()
+        .fold(0u64, |max, WeightedBallot { weight, .. }| {
+            let votes: u64 = match spec.kind {
+                // n votes cost n^2 credits
+                BallotKind::Quadratic => spec.credits.unwrap_or(0).saturating_mul(*weight),
+                _ => *weight,
+            };
+            max.saturating_add(votes)
+        })
+}
+
+#[test]
+fn test_instant_runoff() {
+    let options: Vec<String> = ["a", "b", "c", "d"].iter().map(|o| o.to_string()).collect();
+    let weighted = |weight: u64, ranking: &[&str]| WeightedBallot {
+        ballot: PublicBallot::Plain(Ballot::Ranked {
+            ranking: ranking.iter().map(|o| o.to_string()).collect(),
+        }),
+        weight,
+    };
+    let ranked = |ranking: &[&str]| weighted(1, ranking);
+    let mut ballots: Vec<WeightedBallot> = Vec::new();
+    ballots.extend((0..4).map(|_| ranked(&["a", "d"])));
+    ballots.extend((0..3).map(|_| ranked(&["b", "c"])));
+    ballots.extend((0..2).map(|_| ranked(&["c", "b"])));
+    ballots.push(ranked(&["d"]));
+    assert_eq!(count(&ballots)["a"], 4);
+    let runoff = instant_runoff(&options, &ballots);
+    // d is eliminated first and its ballot is exhausted,
+    // c goes next and transfers to b, which then has 5 of 9 continuing ballots
+    assert_eq!(runoff.rounds.len(), 3);
+    assert_eq!(runoff.rounds[0].eliminated, vec!["d".to_string()]);
+    assert_eq!(runoff.rounds[1].counts["a"], 4);
+    assert_eq!(runoff.rounds[1].exhausted, 1);
+    assert_eq!(runoff.rounds[1].eliminated, vec!["c".to_string()]);
+    assert_eq!(runoff.rounds[2].counts["b"], 5);
+    assert_eq!(runoff.winner, Some("b".to_string()));
+    // a perfect tie has no winner
+    let tie = instant_runoff(&options[..2], &[ranked(&["a"]), ranked(&["b"])]);
+    assert_eq!(tie.winner, None);
+    assert_eq!(tie.rounds.len(), 1);
+    // a single heavy voter outweighs two light ones
+    let heavy = instant_runoff(
+        &options[..2],
+        &[weighted(3, &["a"]), ranked(&["b"]), ranked(&["b"])],
+    );
+    assert_eq!(heavy.winner, Some("a".to_string()));
+    // every approved option counts once
+    let approved = count(&[
+        WeightedBallot {
+            ballot: PublicBallot::Plain(Ballot::Approval {
+                approved: vec!["a".to_string(), "b".to_string()],
+            }),
+            weight: 1,
+        },
+        WeightedBallot {
+            ballot: PublicBallot::Plain(Ballot::Approval {
+                approved: vec!["b".to_string()],
+            }),
+            weight: 2,
+        },
+    ]);
+    assert_eq!((approved["a"], approved["b"]), (1, 3));
+    // quadratic ballots count votes, not credits
+    let allocated = count(&[WeightedBallot {
+        ballot: PublicBallot::Plain(
+            Ballot::from_choices(
+                BallotKind::Quadratic,
+                vec!["a=3".to_string(), "b=1".to_string()],
+            )
+            .unwrap(),
+        ),
+        weight: 2,
+    }]);
+    assert_eq!((allocated["a"], allocated["b"]), (3, 1));
+}
@@ -0,0 +1,238 @@
+// outbound webhooks
+// phase transitions and published results of a poll are posted to its webhooks as signed json,
+// failed deliveries are retried with exponential backoff, every delivery ends up in a log that
+// the admin api serves
+use crate::{
+    config::{WebhookConfig, WebhookEvent},
+    state::{PollId, ServiceState},
+};
+use client::types::{PollEvent, WebhookPayload, DELIVERY_HEADER, SIGNATURE_HEADER};
+use crypto::{hmac_sha256, to_hex};
+use reqwest::{header::CONTENT_TYPE, Client};
+use serde::Serialize;
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::sync::{broadcast::error::RecvError, Mutex as StateMutex};
+use tracing::{info, warn};
+
+// the oldest deliveries are dropped from the log first
+const MAX_LOGGED_DELIVERIES: usize = 1000;
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Delivery {
+    pub id: String,
+    pub poll: PollId,
+    pub url: String,
+    pub event: String,
+    pub timestamp: u64,
+    pub attempts: u32,
+    pub delivered: bool,
+    // of the last attempt
+    pub status: Option<u16>,
+    pub error: Option<String>,
+}
+
+pub struct WebhookDispatcher {
+    client: Client,
+    // before the second attempt, doubled before every further one
+    backoff: Duration,
+    log: Mutex<VecDeque<Delivery>>,
+}
+
+impl WebhookDispatcher {
+    pub fn new(backoff: Duration) -> WebhookDispatcher {
+        WebhookDispatcher {
+            client: Client::builder()
+                .timeout(DELIVERY_TIMEOUT)
+                .build()
+                .expect("Failed to build webhook client"),
+            backoff,
+            log: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// delivers the phase and results events of every poll to its webhooks
+    pub async fn run(self: Arc<Self>, state: Arc<StateMutex<ServiceState>>) {
+        let mut events = state.lock().await.subscribe();
+        loop {
+            let (poll_id, event): (PollId, PollEvent) = match events.recv().await {
+                Ok(event) => event,
+                Err(RecvError::Lagged(missed)) => {
+                    warn!(missed, "webhook dispatcher missed events");
+                    continue;
+                }
+                Err(RecvError::Closed) => return,
+            };
+            let kind: WebhookEvent = match event {
+                PollEvent::Phase { .. } => WebhookEvent::Phase,
+                PollEvent::Results { .. } => WebhookEvent::Results,
+                _ => continue,
+            };
+            let targets: Vec<WebhookConfig> = match state.lock().await.poll(&poll_id) {
+                Some(poll) => poll.config.webhooks.clone(),
+                None => continue,
+            };
+            let payload = WebhookPayload {
+                id: to_hex(&rand::random::<[u8; 16]>()),
+                poll: poll_id,
+                timestamp: crate::unix_now(),
+                event,
+            };
+            for target in targets
+                .into_iter()
+                .filter(|target| target.events.contains(&kind))
+            {
+                let dispatcher = Arc::clone(&self);
+                let payload = payload.clone();
+                tokio::spawn(async move { dispatcher.deliver(&target, &payload).await });
+            }
+        }
+    }
+
+    /// posts the payload until the target answers with a success or the attempts run out
+    pub async fn deliver(&self, target: &WebhookConfig, payload: &WebhookPayload) -> Delivery {
+        let body: Vec<u8> = serde_json::to_vec(payload).expect("Failed to serialize payload");
+        let mut delivery = Delivery {
+            id: payload.id.clone(),
+            poll: payload.poll.clone(),
+            url: target.url.clone(),
+            event: payload.event.name().to_string(),
+            timestamp: payload.timestamp,
+            attempts: 0,
+            delivered: false,
+            status: None,
+            error: None,
+        };
+        match target.secret() {
+            Ok(secret) => {
+                let signature: String =
+                    format!("sha256={}", to_hex(&hmac_sha256(secret.as_bytes(), &body)));
+                while !delivery.delivered && delivery.attempts < target.max_attempts {
+                    if delivery.attempts > 0 {
+                        tokio::time::sleep(self.backoff * 2u32.pow(delivery.attempts - 1)).await;
+                    }
+                    delivery.attempts += 1;
+                    let response = self
+                        .client
+                        .post(&target.url)
+                        .header(CONTENT_TYPE, "application/json")
+                        .header(SIGNATURE_HEADER, &signature)
+                        .header(DELIVERY_HEADER, &payload.id)
+                        .body(body.clone())
+                        .send()
+                        .await;
+                    match response {
+                        Ok(response) => {
+                            delivery.status = Some(response.status().as_u16());
+                            delivery.delivered = response.status().is_success();
+                            delivery.error = (!delivery.delivered)
+                                .then(|| format!("Response Status {}", response.status()));
+                        }
+                        Err(e) => {
+                            delivery.status = None;
+                            delivery.error = Some(e.to_string());
+                        }
+                    }
+                }
+            }
+            Err(e) => delivery.error = Some(e),
+        }
+        match delivery.delivered {
+            true => {
+                info!(poll = %delivery.poll, url = %delivery.url, event = %delivery.event, attempts = delivery.attempts, "webhook delivered")
+            }
+            false => {
+                warn!(poll = %delivery.poll, url = %delivery.url, event = %delivery.event, attempts = delivery.attempts, error = ?delivery.error, "webhook delivery failed")
+            }
+        }
+        let mut log = self.log.lock().unwrap();
+        if log.len() >= MAX_LOGGED_DELIVERIES {
+            log.pop_front();
+        }
+        log.push_back(delivery.clone());
+        delivery
+    }
+
+    /// the logged deliveries of a poll, oldest first
+    pub fn deliveries(&self, poll_id: &str) -> Vec<Delivery> {
+        self.log
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|delivery| delivery.poll == poll_id)
+            .cloned()
+            .collect()
+    }
+}
+
+#[tokio::test]
+async fn test_webhook_delivery() {
+    use axum::{body::Bytes, http::HeaderMap, http::StatusCode, routing::post, Router};
+    use client::types::PollPhase;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    std::env::set_var("CYPHER_POLL_TEST_WEBHOOK_SECRET", "secret");
+    // a local receiver that fails the first attempt
+    let attempts: Arc<AtomicU32> = Arc::new(AtomicU32::new(0));
+    let received: Arc<Mutex<Vec<(String, Bytes)>>> = Arc::new(Mutex::new(Vec::new()));
+    let receiver = {
+        let (attempts, received) = (Arc::clone(&attempts), Arc::clone(&received));
+        Router::new().route(
+            "/hook",
+            post(move |headers: HeaderMap, body: Bytes| async move {
+                if attempts.fetch_add(1, Ordering::SeqCst) == 0 {
+                    return StatusCode::INTERNAL_SERVER_ERROR;
+                }
+                let signature: String = headers[SIGNATURE_HEADER].to_str().unwrap().to_string();
+                received.lock().unwrap().push((signature, body));
+                StatusCode::OK
+            }),
+        )
+    };
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, receiver).await.unwrap() });
+    let dispatcher = WebhookDispatcher::new(Duration::from_millis(10));
+    let target = WebhookConfig {
+        url: format!("http://{}/hook", address),
+        secret_env: "CYPHER_POLL_TEST_WEBHOOK_SECRET".to_string(),
+        events: vec![WebhookEvent::Phase],
+        max_attempts: 3,
+    };
+    let payload = WebhookPayload {
+        id: "delivery".to_string(),
+        poll: "board".to_string(),
+        timestamp: 1,
+        event: PollEvent::Phase {
+            phase: PollPhase::Closed,
+        },
+    };
+    let delivery: Delivery = dispatcher.deliver(&target, &payload).await;
+    assert!(delivery.delivered);
+    assert_eq!(delivery.attempts, 2);
+    let (signature, body) = received.lock().unwrap().pop().unwrap();
+    assert_eq!(
+        signature,
+        format!("sha256={}", to_hex(&hmac_sha256(b"secret", &body)))
+    );
+    let decoded: WebhookPayload = serde_json::from_slice(&body).unwrap();
+    assert!(matches!(
+        decoded.event,
+        PollEvent::Phase {
+            phase: PollPhase::Closed
+        }
+    ));
+    assert_eq!(dispatcher.deliveries("board").len(), 1);
+    // an unreachable target is given up after max_attempts
+    let unreachable = WebhookConfig {
+        url: "http://127.0.0.1:9/hook".to_string(),
+        ..target
+    };
+    let delivery: Delivery = dispatcher.deliver(&unreachable, &payload).await;
+    assert!(!delivery.delivered);
+    assert_eq!(delivery.attempts, 3);
+    assert_eq!(dispatcher.deliveries("board").len(), 2);
+}
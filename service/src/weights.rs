@@ -0,0 +1,30 @@
+// voter weights
+// assigned once at registration and bound into the leaf, the guest outputs the weight with the
+// ballot, so weighted polls are encrypted and only the tally of all weights is opened
+use crate::{config::WeightsConfig, eligibility, gauth::GitHubApi, state::RegistrationError};
+
+pub async fn assign(
+    weights: &WeightsConfig,
+    github: &dyn GitHubApi,
+    username: &str,
+) -> Result<u64, RegistrationError> {
+    // GitHub logins are case insensitive
+    if let Some((_, weight)) = weights
+        .users
+        .iter()
+        .find(|(user, _)| user.eq_ignore_ascii_case(username))
+    {
+        return Ok(*weight);
+    }
+    for rule in &weights.rules {
+        match eligibility::build(&rule.policy)
+            .check(github, username)
+            .await
+        {
+            Ok(()) => return Ok(rule.weight),
+            Err(RegistrationError::Ineligible(_)) => continue,
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(weights.default)
+}
@@ -0,0 +1,134 @@
+// key ceremony and tally decryption for encrypted polls
+// keygen splits a fresh poll key into one share per trustee and forgets it, the service only
+// learns the public key and the verification keys, decrypt turns the published aggregate of a
+// closed poll into decryption shares with proofs and posts them to the service
+use clap::{Parser, Subcommand};
+use client::types::{PollResults, TrusteeShares};
+use crypto::{
+    elgamal::{decryption_share, public_key, random_scalar, split, Ciphertext, KeyShare},
+    to_hex,
+};
+use rand::{rngs::OsRng, RngCore};
+use reqwest::blocking::Client;
+use std::{fs, path::PathBuf};
+
+#[derive(Parser)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// generate a poll key and split it among the trustees
+    Keygen {
+        /// trustees needed to decrypt the tally
+        #[arg(long)]
+        threshold: u32,
+        #[arg(long)]
+        trustees: u32,
+        /// directory for the key shares and the encryption config of the poll
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// contribute the decryption shares of a trustee to the tally of a closed poll
+    Decrypt {
+        /// key share file written by keygen
+        #[arg(long)]
+        share: PathBuf,
+        #[arg(long, default_value = "http://127.0.0.1:8080")]
+        server: String,
+        #[arg(long)]
+        poll: String,
+    },
+}
+
+fn random() -> [u8; 32] {
+    let mut random: [u8; 64] = [0; 64];
+    OsRng.fill_bytes(&mut random);
+    random_scalar(&random)
+}
+
+fn keygen(threshold: u32, trustees: u32, out: PathBuf) {
+    if threshold == 0 || threshold > trustees {
+        panic!("Threshold must be between 1 and the number of trustees");
+    }
+    // the poll key only exists in this function
+    let secret: [u8; 32] = random();
+    let coefficients: Vec<[u8; 32]> = (1..threshold).map(|_| random()).collect();
+    let shares: Vec<KeyShare> =
+        split(&secret, &coefficients, trustees).expect("Failed to split key");
+    fs::create_dir_all(&out).expect("Failed to create output directory");
+    let mut verification_keys: Vec<String> = Vec::new();
+    for share in &shares {
+        let path: PathBuf = out.join(format!("trustee-{}.json", share.index));
+        fs::write(&path, serde_json::to_vec(share).unwrap()).expect("Failed to write key share");
+        verification_keys.push(to_hex(&public_key(&share.secret).unwrap()));
+        println!("key share {}: {}", share.index, path.display());
+    }
+    let config: String = format!(
+        "[polls.encryption]\npublic_key = \"{}\"\nthreshold = {}\nverification_keys = [\n{}]\n",
+        to_hex(&public_key(&secret).unwrap()),
+        threshold,
+        verification_keys
+            .iter()
+            .map(|key| format!("    \"{}\",\n", key))
+            .collect::<String>()
+    );
+    let path: PathBuf = out.join("encryption.toml");
+    fs::write(&path, &config).expect("Failed to write encryption config");
+    println!("poll config: {}\n\n{}", path.display(), config);
+}
+
+fn decrypt(share: PathBuf, server: String, poll: String) {
+    let key: KeyShare = serde_json::from_slice(&fs::read(share).expect("Failed to read key share"))
+        .expect("Failed to decode key share");
+    let client = Client::new();
+    let results: PollResults = client
+        .get(format!("{}/polls/{}/results", server, poll))
+        .send()
+        .and_then(|response| response.error_for_status())
+        .and_then(|response| response.json())
+        .expect("Failed to fetch results");
+    let aggregate: Vec<Ciphertext> = results
+        .aggregate
+        .expect("No aggregate published, the poll is not encrypted or still open");
+    let shares = TrusteeShares {
+        index: key.index,
+        shares: aggregate
+            .iter()
+            .map(|ciphertext| {
+                decryption_share(&key, ciphertext, &random()).expect("Invalid aggregate")
+            })
+            .collect(),
+    };
+    let response = client
+        .post(format!("{}/polls/{}/shares", server, poll))
+        .json(&shares)
+        .send()
+        .expect("Failed to submit decryption shares");
+    let status = response.status();
+    match status.is_success() {
+        true => println!("Decryption shares of trustee {} accepted", key.index),
+        false => println!(
+            "Error: Response Status {}: {}",
+            status,
+            response.text().unwrap_or_default()
+        ),
+    }
+}
+
+fn main() {
+    match Cli::parse().command {
+        Command::Keygen {
+            threshold,
+            trustees,
+            out,
+        } => keygen(threshold, trustees, out),
+        Command::Decrypt {
+            share,
+            server,
+            poll,
+        } => decrypt(share, server, poll),
+    }
+}
@@ -0,0 +1,127 @@
+// dev-mode backend: runs the circuit logic natively and ships the outputs as the proof
+// there is NO soundness here, anyone can forge a dev proof.
+// only meant for tests and local demos, the service refuses dev proofs unless started in insecure mode
+use crate::proof::{ProofBackend, ProofEnvelope, ProofError, VoteProver, VoteVerifier};
+use crate::prover::logic::prover_logic;
+use risc0_types::{CircuitInputs, CircuitOutputs};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+pub struct DevProver;
+impl VoteProver for DevProver {
+    fn backend(&self) -> ProofBackend {
+        ProofBackend::Dev
+    }
+    fn prove(&self, mut inputs: CircuitInputs) -> Result<ProofEnvelope, ProofError> {
+        // the guest panics on invalid inputs, mirror that as a proving error
+        let outputs: CircuitOutputs = catch_unwind(AssertUnwindSafe(|| prover_logic(&mut inputs)))
+            .map_err(|_| ProofError::Proving("Circuit logic rejected the inputs".to_string()))?;
+        let proof: Vec<u8> =
+            serde_json::to_vec(&outputs).map_err(|e| ProofError::Malformed(e.to_string()))?;
+        Ok(ProofEnvelope::new(self.backend(), proof))
+    }
+}
+
+pub struct DevVerifier;
+impl VoteVerifier for DevVerifier {
+    fn supports(&self, backend: ProofBackend) -> bool {
+        backend == ProofBackend::Dev
+    }
+    fn verify(&self, envelope: &ProofEnvelope) -> Result<CircuitOutputs, ProofError> {
+        // there is nothing to verify beyond the encoding
+        self.inspect(envelope)
+    }
+    fn inspect(&self, envelope: &ProofEnvelope) -> Result<CircuitOutputs, ProofError> {
+        envelope.check_version()?;
+        if !self.supports(envelope.backend) {
+            return Err(ProofError::UnsupportedBackend(envelope.backend));
+        }
+        serde_json::from_slice(&envelope.proof).map_err(|e| ProofError::Malformed(e.to_string()))
+    }
+}
+
+#[test]
+fn test_dev_roundtrip() {
+    use crate::proof::check_outputs;
+    use crate::storage::TreeState;
+    use crypto::{gpg::GpgSigner, identity::UniqueIdentity, to_hex};
+    use risc0_types::{Ballot, PollSpec, PublicBallot};
+    use std::fs;
+    let public_key_string: String = fs::read_to_string(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/../resources/test/key.asc"
+    ))
+    .expect("Failed to read public key");
+    let mut signer = GpgSigner {
+        secret_key_asc_path: None,
+        public_key_asc_string: Some(public_key_string.clone()),
+        signed_secret_key: None,
+        signed_public_key: None,
+    };
+    signer.init_verifier();
+    let mut identity = UniqueIdentity {
+        nullifier: None,
+        identity: None,
+    };
+    identity.generate_nullifier("Hello".to_string());
+    let ballot = |choice: &str| Ballot::Plurality {
+        choice: choice.to_string(),
+    };
+    let spec = PollSpec {
+        options: vec!["Overlord".to_string(), "TimeLord".to_string()],
+        ..Default::default()
+    };
+    identity.compute_public_identity(
+        signer.signed_public_key.unwrap(),
+        ballot("Overlord").commitment(),
+    );
+    let mut tree_state = TreeState::new(Vec::new(), Vec::new(), Vec::new());
+    tree_state.insert_nullifier(identity.identity.unwrap());
+
+    let proof = DevProver
+        .prove(CircuitInputs {
+            root_history: tree_state.root_history.clone(),
+            snapshot: tree_state.voting_tree.clone(),
+            nullifier: identity.nullifier.clone().unwrap(),
+            ballot: ballot("Overlord"),
+            spec: spec.clone(),
+            weight: 1,
+            sequence: 0,
+            randomness: Vec::new(),
+            salt: Vec::new(),
+            public_key_string: public_key_string.clone(),
+        })
+        .expect("Failed to generate dev proof");
+    // the proof bytes travel as a hex string
+    let encoded: serde_json::Value = serde_json::to_value(&proof).unwrap();
+    assert_eq!(
+        encoded["proof"],
+        serde_json::Value::String(to_hex(&proof.proof))
+    );
+    let proof: ProofEnvelope = serde_json::from_value(encoded).unwrap();
+    let outputs = DevVerifier
+        .verify(&proof)
+        .expect("Failed to verify dev proof");
+    assert_eq!(outputs.ballot, PublicBallot::Plain(ballot("Overlord")));
+    check_outputs(
+        &outputs,
+        &tree_state.root_history,
+        &tree_state.used_nullifiers,
+    )
+    .expect("Rejected vote");
+
+    // a different vote does not match the registered leaf
+    assert!(DevProver
+        .prove(CircuitInputs {
+            root_history: tree_state.root_history.clone(),
+            snapshot: tree_state.voting_tree.clone(),
+            nullifier: identity.nullifier.unwrap(),
+            ballot: ballot("TimeLord"),
+            spec,
+            weight: 1,
+            sequence: 0,
+            randomness: Vec::new(),
+            salt: Vec::new(),
+            public_key_string,
+        })
+        .is_err());
+}
@@ -1,2 +1,4 @@
+pub mod dev;
+pub mod proof;
 pub mod prover;
 pub mod storage;
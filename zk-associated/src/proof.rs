@@ -0,0 +1,173 @@
+// prover-agnostic proof handling
+// every proving backend wraps its native proof in a ProofEnvelope,
+// the service only ever deals with envelopes and the decoded CircuitOutputs
+use crate::storage::TreeRoot;
+use crypto::{from_hex, identity::Nullifier, to_hex};
+use risc0_types::{CircuitInputs, CircuitOutputs};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+
+/// bumped whenever the envelope layout or the journal encoding changes
+pub const PROOF_ENVELOPE_VERSION: u16 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProofBackend {
+    Risc0Stark,
+    Risc0Groth16,
+    // insecure, see dev.rs
+    Dev,
+}
+
+impl fmt::Display for ProofBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProofBackend::Risc0Stark => write!(f, "risc0-stark"),
+            ProofBackend::Risc0Groth16 => write!(f, "risc0-groth16"),
+            ProofBackend::Dev => write!(f, "dev"),
+        }
+    }
+}
+
+/// The wire format of a vote, `proof` is the serialized backend specific proof
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProofEnvelope {
+    pub version: u16,
+    pub backend: ProofBackend,
+    // hex encoded, a json array of numbers is several times the size of the receipt
+    #[serde(serialize_with = "serialize_hex", deserialize_with = "deserialize_hex")]
+    pub proof: Vec<u8>,
+}
+
+fn serialize_hex<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&to_hex(bytes))
+}
+
+fn deserialize_hex<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    let hex: String = String::deserialize(deserializer)?;
+    from_hex(&hex).ok_or_else(|| de::Error::custom("proof is not hex encoded"))
+}
+
+impl ProofEnvelope {
+    pub fn new(backend: ProofBackend, proof: Vec<u8>) -> ProofEnvelope {
+        ProofEnvelope {
+            version: PROOF_ENVELOPE_VERSION,
+            backend,
+            proof,
+        }
+    }
+    pub fn check_version(&self) -> Result<(), ProofError> {
+        if self.version != PROOF_ENVELOPE_VERSION {
+            return Err(ProofError::UnsupportedVersion(self.version));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum ProofError {
+    UnsupportedVersion(u16),
+    UnsupportedBackend(ProofBackend),
+    Malformed(String),
+    Invalid(String),
+    Proving(String),
+    DuplicateNullifier,
+    UnknownRoot,
+}
+
+impl fmt::Display for ProofError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProofError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported proof envelope version {}", version)
+            }
+            ProofError::UnsupportedBackend(backend) => {
+                write!(f, "Unsupported proof backend {}", backend)
+            }
+            ProofError::Malformed(reason) => write!(f, "Malformed proof: {}", reason),
+            ProofError::Invalid(reason) => write!(f, "Invalid proof: {}", reason),
+            ProofError::Proving(reason) => write!(f, "Failed to generate proof: {}", reason),
+            ProofError::DuplicateNullifier => write!(f, "Duplicate nullifier"),
+            ProofError::UnknownRoot => write!(f, "Root is not contained in Root History"),
+        }
+    }
+}
+
+impl std::error::Error for ProofError {}
+
+impl ProofError {
+    /// a stable, low cardinality name for metrics and logs
+    pub fn reason(&self) -> &'static str {
+        match self {
+            ProofError::UnsupportedVersion(_) => "unsupported_version",
+            ProofError::UnsupportedBackend(_) => "unsupported_backend",
+            ProofError::Malformed(_) => "malformed",
+            ProofError::Invalid(_) => "invalid",
+            ProofError::Proving(_) => "proving",
+            ProofError::DuplicateNullifier => "duplicate_nullifier",
+            ProofError::UnknownRoot => "unknown_root",
+        }
+    }
+}
+
+pub trait VoteProver: Send + Sync {
+    fn backend(&self) -> ProofBackend;
+    fn prove(&self, inputs: CircuitInputs) -> Result<ProofEnvelope, ProofError>;
+}
+
+pub trait VoteVerifier: Send + Sync {
+    fn supports(&self, backend: ProofBackend) -> bool;
+    /// verifies the envelope and returns the public outputs of the circuit
+    fn verify(&self, envelope: &ProofEnvelope) -> Result<CircuitOutputs, ProofError>;
+    /// decodes the public outputs WITHOUT verifying the proof
+    fn inspect(&self, envelope: &ProofEnvelope) -> Result<CircuitOutputs, ProofError>;
+}
+
+/// Dispatches envelopes to the first verifier that supports their backend
+#[derive(Default)]
+pub struct VerifierSet {
+    verifiers: Vec<Box<dyn VoteVerifier>>,
+}
+
+impl VerifierSet {
+    pub fn with(mut self, verifier: impl VoteVerifier + 'static) -> VerifierSet {
+        self.verifiers.push(Box::new(verifier));
+        self
+    }
+    fn verifier(&self, envelope: &ProofEnvelope) -> Result<&dyn VoteVerifier, ProofError> {
+        self.verifiers
+            .iter()
+            .find(|v| v.supports(envelope.backend))
+            .map(|v| v.as_ref())
+            .ok_or(ProofError::UnsupportedBackend(envelope.backend))
+    }
+}
+
+impl VoteVerifier for VerifierSet {
+    fn supports(&self, backend: ProofBackend) -> bool {
+        self.verifiers.iter().any(|v| v.supports(backend))
+    }
+    fn verify(&self, envelope: &ProofEnvelope) -> Result<CircuitOutputs, ProofError> {
+        self.verifier(envelope)?.verify(envelope)
+    }
+    fn inspect(&self, envelope: &ProofEnvelope) -> Result<CircuitOutputs, ProofError> {
+        self.verifier(envelope)?.inspect(envelope)
+    }
+}
+
+/// check the public outputs of a verified proof against the tree state
+pub fn check_outputs(
+    outputs: &CircuitOutputs,
+    root_history: &[TreeRoot],
+    used_nullifiers: &[Nullifier],
+) -> Result<(), ProofError> {
+    if used_nullifiers.contains(&outputs.nullifier) {
+        return Err(ProofError::DuplicateNullifier);
+    }
+    for root in &outputs.root_history {
+        if !root_history.contains(root) {
+            return Err(ProofError::UnknownRoot);
+        }
+    }
+    Ok(())
+}
@@ -1,15 +1,21 @@
 // compute a zero knowledge proof
-// that Sha256(nullifier, public_key) is a leaf
+// that Sha256(nullifier, public_key, ballot), extended by the weight in a weighted poll, is a leaf
 // that the merkle proof of that leaf is valid for one of the roots in a given list
+// that the ballot is valid for the poll spec and within the credit budget of the leaf
+// in a revoting poll the leaf does not commit to the ballot and a tag replaces the nullifier,
+// the tag of the previous ballot proves that the sequence grows by one
+// in an encrypted poll only the encrypted votes per option leave the circuit
+// in a commit-reveal poll only a salted commitment to the ballot leaves the circuit
 
-use risc0_types::{CircuitInputs, CircuitOutputs};
+use risc0_types::{CircuitInputs, CircuitOutputs, PublicBallot, SALT_LENGTH};
 // private inputs: tree snapshot, public key
 // public inputs/outputs: list of roots
 // public outputs: nullifier
 use super::merkle::compute_root;
 use crate::storage::TreeRoot;
+use crypto::elgamal::{encrypt, Ciphertext};
 use crypto::gpg::GpgSigner;
-use crypto::identity::{Identity, UniqueIdentity};
+use crypto::identity::{revote_tag, weighted_leaf, Identity, UniqueIdentity};
 pub fn prover_logic(inputs: &mut CircuitInputs) -> CircuitOutputs {
     let mut gpg_signer: GpgSigner = GpgSigner {
         secret_key_asc_path: None,
@@ -22,18 +28,72 @@ pub fn prover_logic(inputs: &mut CircuitInputs) -> CircuitOutputs {
         nullifier: Some(inputs.nullifier.clone()),
         identity: None,
     };
-    uid.compute_public_identity(gpg_signer.signed_public_key.unwrap(), inputs.vote.clone());
+    if let Err(e) = inputs
+        .ballot
+        .validate(&inputs.spec)
+        .and_then(|_| inputs.ballot.within_budget(&inputs.spec, inputs.weight))
+    {
+        panic!("Invalid ballot: {}", e)
+    }
+    uid.compute_public_identity(
+        gpg_signer.signed_public_key.unwrap(),
+        inputs.spec.leaf_commitment(Some(&inputs.ballot)).unwrap(),
+    );
     let identity: Identity = uid.identity.unwrap();
-    let new_root: TreeRoot = compute_root(&mut inputs.snapshot, identity);
+    let leaf: Identity = if inputs.spec.weighted {
+        weighted_leaf(&identity, inputs.weight)
+    } else {
+        assert_eq!(inputs.weight, 1, "Unweighted polls count every vote once");
+        identity
+    };
+    let new_root: TreeRoot = compute_root(&mut inputs.snapshot, leaf);
 
     if !inputs.root_history.contains(&new_root) {
         println!("Root: {:?}", &new_root);
         println!("Root history: {:?}", &inputs.root_history);
         panic!("Root is not contained in Root History")
     };
+    // the nullifier itself stays private if it is redeemed more than once
+    let (nullifier, previous): (Vec<u8>, Vec<u8>) = if inputs.spec.revoting {
+        let previous: Vec<u8> = match inputs.sequence {
+            0 => Vec::new(),
+            sequence => revote_tag(&inputs.nullifier, sequence - 1),
+        };
+        (revote_tag(&inputs.nullifier, inputs.sequence), previous)
+    } else {
+        assert_eq!(inputs.sequence, 0, "Only revoting polls accept a sequence");
+        (inputs.nullifier.clone(), Vec::new())
+    };
+    let ballot: PublicBallot = match &inputs.spec.encryption_key {
+        Some(key) => {
+            let votes: Vec<u64> = inputs.ballot.option_votes(&inputs.spec, inputs.weight);
+            assert_eq!(
+                inputs.randomness.len(),
+                votes.len(),
+                "Every option needs its own randomness"
+            );
+            let encrypted: Vec<Ciphertext> = votes
+                .iter()
+                .zip(&inputs.randomness)
+                .map(|(votes, randomness)| {
+                    encrypt(key, *votes, randomness).expect("Invalid encryption key or randomness")
+                })
+                .collect();
+            PublicBallot::Encrypted(encrypted)
+        }
+        None if inputs.spec.commit_reveal => {
+            assert_eq!(inputs.salt.len(), SALT_LENGTH, "Invalid salt length");
+            PublicBallot::Committed(inputs.ballot.reveal_commitment(&inputs.salt))
+        }
+        None => PublicBallot::Plain(inputs.ballot.clone()),
+    };
     CircuitOutputs {
-        nullifier: inputs.nullifier.clone(),
+        nullifier,
+        previous,
         root_history: inputs.root_history.clone(),
-        vote: inputs.vote.clone(),
+        ballot,
+        spec: inputs.spec.clone(),
+        weight: inputs.weight,
+        sequence: inputs.sequence,
     }
 }
@@ -1,4 +1,5 @@
 use crypto::{hash, CryptoHasherSha256};
+use serde::{Deserialize, Serialize};
 use voting_tree::VotingTree;
 
 /// In production, this would live on a Blockchain
@@ -8,7 +9,7 @@ use crypto::identity::{Identity, Nullifier};
 pub type TreeRoot = Vec<u8>;
 pub type Snapshot = TreeState;
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct TreeState {
     pub root_history: Vec<TreeRoot>,
     pub used_nullifiers: Vec<Nullifier>,